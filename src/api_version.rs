@@ -0,0 +1,44 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    Error, HttpResponse,
+};
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-api-version");
+pub const CURRENT: &str = "1";
+pub const PREFIX: &str = "/v1";
+
+/// Rejects requests asking for an unsupported `X-API-Version`, stamps the served version on every
+/// response, and marks unversioned (legacy) routes as deprecated in favour of `/v1`.
+pub async fn negotiate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    if let Some(requested) = req.headers().get(&HEADER) {
+        if requested.as_bytes() != CURRENT.as_bytes() {
+            let resp = HttpResponse::BadRequest().body(format!(
+                "Unsupported API version {:?}; supported: {}",
+                String::from_utf8_lossy(requested.as_bytes()),
+                CURRENT
+            ));
+            return Ok(req.into_response(resp).map_into_right_body());
+        }
+    }
+    let legacy = !is_versioned(req.path());
+    let mut res = next.call(req).await?;
+    let headers = res.headers_mut();
+    headers.insert(HEADER, HeaderValue::from_static(CURRENT));
+    if legacy {
+        headers.insert(
+            HeaderName::from_static("deprecation"),
+            HeaderValue::from_static("true"),
+        );
+    }
+    Ok(res.map_into_left_body())
+}
+
+fn is_versioned(path: &str) -> bool {
+    path == PREFIX || path.starts_with(&format!("{}/", PREFIX))
+}
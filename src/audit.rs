@@ -0,0 +1,80 @@
+use actix_web::HttpRequest;
+use serde::Serialize;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// One line of the audit log: who did what, and when.
+#[derive(Serialize)]
+pub struct AuditEntry<'r> {
+    pub ts_ms: u128,
+    pub request_id: String,
+    pub who: String,
+    pub method: &'r str,
+    pub endpoint: String,
+    pub collection: Option<&'r str>,
+    pub ids: Option<usize>,
+}
+
+/// Append-only NDJSON audit log, enabled by setting `AUDIT_LOG` to a file path.
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    file: Mutex<Option<File>>,
+}
+
+impl AuditLog {
+    pub fn from_env() -> io::Result<Self> {
+        match std::env::var("AUDIT_LOG") {
+            Ok(path) if !path.is_empty() => {
+                let path = PathBuf::from(path);
+                let file = OpenOptions::new().create(true).append(true).open(&path)?;
+                Ok(Self {
+                    path: Some(path),
+                    file: Mutex::new(Some(file)),
+                })
+            }
+            _ => Ok(Self {
+                path: None,
+                file: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    /// Records a mutating request. Failures are reported but never fail the request itself.
+    pub fn record(&self, req: &HttpRequest, collection: Option<&str>, ids: Option<usize>) {
+        let mut file = self.file.lock().unwrap();
+        let Some(file) = file.as_mut() else {
+            return;
+        };
+        let entry = AuditEntry {
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+            request_id: crate::request_id::get(req),
+            who: req
+                .connection_info()
+                .realip_remote_addr()
+                .unwrap_or("unknown")
+                .to_string(),
+            method: req.method().as_str(),
+            endpoint: req
+                .match_pattern()
+                .unwrap_or_else(|| req.path().to_string()),
+            collection,
+            ids,
+        };
+        let line = serde_json::to_string(&entry).expect("audit entry serializes");
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            log::error!("[{}] failed to write audit log: {}", entry.request_id, e);
+        }
+    }
+}
@@ -0,0 +1,569 @@
+use crate::{
+    startup,
+    token::{Claims, Tokens},
+};
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::{from_fn, Next},
+    web, Error, HttpMessage, HttpRequest, HttpResponse, Route,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+/// What a key may do; each role includes the ones before it.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Role {
+    /// Searches and other reads.
+    Read,
+    /// Also writes to points and collections.
+    Write,
+    /// Also the admin routes.
+    Admin,
+}
+
+impl Role {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(Role::Read),
+            "write" => Some(Role::Write),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// What a route does, for tokens allowed only some of it.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Searches and recommendations.
+    Search,
+    /// Other reads, such as points by id and scrolling.
+    Read,
+    /// Writes to points and the collection.
+    Write,
+}
+
+/// Limits a token's requests to the routes of one collection and the actions it was minted for.
+#[derive(Clone, Debug)]
+pub struct Scope {
+    collection: String,
+    actions: Vec<Action>,
+}
+
+impl Scope {
+    /// Why a route doing `action` on `collection` is out of scope, if it is.
+    fn refuses(&self, action: Option<Action>, collection: Option<&str>) -> Option<String> {
+        if let Some(message) = self.refuses_collection(collection.unwrap_or_default()) {
+            return Some(message);
+        }
+        match action {
+            Some(action) if self.actions.contains(&action) => None,
+            _ => Some("Token does not allow this operation".to_string()),
+        }
+    }
+
+    /// Why reading `collection`, such as a join target, is out of scope, if it is.
+    pub fn refuses_collection(&self, collection: &str) -> Option<String> {
+        (collection != self.collection)
+            .then(|| format!("Token is scoped to collection {}", self.collection))
+    }
+}
+
+/// Names a key in usage reports without revealing it: the first 16 hex digits of its SHA-256.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct KeyId(pub String);
+
+impl KeyId {
+    pub fn of(key: &str) -> Self {
+        let digest = Sha256::digest(key.as_bytes());
+        Self(digest[..8].iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+/// The point tags a key may see. Keys without grants see every point.
+#[derive(Clone, Debug)]
+pub struct Grants(Arc<HashSet<String>>);
+
+impl Grants {
+    pub fn new(tags: impl IntoIterator<Item = String>) -> Self {
+        Self(Arc::new(tags.into_iter().collect()))
+    }
+
+    /// Whether a point with `tags` is visible: it must carry at least one granted tag.
+    pub fn allows(&self, tags: &[String]) -> bool {
+        tags.iter().any(|tag| self.0.contains(tag))
+    }
+
+    /// Whether a point with `tags` may be written: it must carry granted tags only, and at least
+    /// one, so the key can read it back.
+    pub fn covers(&self, tags: &[String]) -> bool {
+        !tags.is_empty() && tags.iter().all(|tag| self.0.contains(tag))
+    }
+}
+
+/// Marks requests whose key may read encrypted payload fields in the clear.
+#[derive(Clone, Copy)]
+pub struct Decrypt;
+
+/// The tag grants of the request's key, if it has any.
+pub fn grants(req: &HttpRequest) -> Option<Grants> {
+    req.extensions().get::<Grants>().cloned()
+}
+
+/// Whether the caller may read encrypted payload fields: keys granted `API_KEY_DECRYPT`, and every
+/// caller while authentication is off.
+pub fn decrypts(req: &HttpRequest) -> bool {
+    req.extensions().contains::<Decrypt>()
+        || req
+            .app_data::<web::Data<ApiKeys>>()
+            .is_none_or(|keys| !keys.enabled())
+}
+
+/// The scope of the request's token, if it came with one.
+pub fn scope(req: &HttpRequest) -> Option<Scope> {
+    req.extensions().get::<Scope>().cloned()
+}
+
+struct Key {
+    secret: String,
+    role: Role,
+    grants: Option<Grants>,
+    decrypt: bool,
+}
+
+/// Accepted API keys. Empty means authentication is off.
+#[derive(Default)]
+pub struct ApiKeys {
+    /// Replaced when the configuration is reloaded.
+    keys: RwLock<Vec<Key>>,
+}
+
+impl ApiKeys {
+    /// Reads `API_KEY`, a key with the admin role, and `API_KEYS`, a comma-separated list of
+    /// `key:role` pairs with roles `read`, `write` or `admin`. `API_KEY_TAGS` grants keys, by
+    /// [`KeyId`], the point tags they may see: `id:tag|tag,id:tag`; `API_KEY_DECRYPT` lists the ids
+    /// of the keys that may read encrypted payload fields.
+    pub fn from_env() -> Result<Self, String> {
+        let mut keys = Vec::new();
+        if let Ok(key) = std::env::var("API_KEY") {
+            if !key.is_empty() {
+                keys.push(Key {
+                    secret: key,
+                    role: Role::Admin,
+                    grants: None,
+                    decrypt: false,
+                });
+            }
+        }
+        if let Ok(list) = std::env::var("API_KEYS") {
+            for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let (key, role) = entry
+                    .rsplit_once(':')
+                    .and_then(|(key, role)| Some((key, Role::parse(role)?)))
+                    .filter(|(key, _)| !key.is_empty())
+                    .ok_or_else(|| {
+                        "API_KEYS entries must be key:role with role read, write or admin"
+                            .to_string()
+                    })?;
+                keys.push(Key {
+                    secret: key.to_string(),
+                    role,
+                    grants: None,
+                    decrypt: false,
+                });
+            }
+        }
+        if let Ok(list) = std::env::var("API_KEY_TAGS") {
+            for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let (id, tags) = entry
+                    .split_once(':')
+                    .ok_or_else(|| "API_KEY_TAGS entries must be id:tag|tag".to_string())?;
+                let tags: HashSet<String> = tags
+                    .split('|')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect();
+                let key = keys
+                    .iter_mut()
+                    .find(|k| KeyId::of(&k.secret).0 == id)
+                    .ok_or_else(|| format!("API_KEY_TAGS names no API key with id {}", id))?;
+                if key.role == Role::Admin {
+                    return Err(format!(
+                        "API_KEY_TAGS: key {} is an admin key, which sees every point",
+                        id
+                    ));
+                }
+                key.grants = Some(Grants::new(tags));
+            }
+        }
+        if let Ok(list) = std::env::var("API_KEY_DECRYPT") {
+            for id in list.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+                let key = keys
+                    .iter_mut()
+                    .find(|k| KeyId::of(&k.secret).0 == id)
+                    .ok_or_else(|| format!("API_KEY_DECRYPT names no API key with id {}", id))?;
+                key.decrypt = true;
+            }
+        }
+        Ok(Self {
+            keys: RwLock::new(keys),
+        })
+    }
+
+    /// Accepts the keys of `other` instead, from the next request on.
+    pub fn replace(&self, other: ApiKeys) {
+        *self.keys.write().unwrap() = other.keys.into_inner().unwrap();
+    }
+
+    /// How many keys are accepted.
+    pub fn count(&self) -> usize {
+        self.keys.read().unwrap().len()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.count() > 0
+    }
+
+    fn lookup(&self, key: &str) -> Option<(Role, Option<Grants>, bool)> {
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .find(|k| constant_time_eq(k.secret.as_bytes(), key.as_bytes()))
+            .map(|k| (k.role, k.grants.clone(), k.decrypt))
+    }
+}
+
+/// Compares without returning early, so response times do not reveal how much of a key matched.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The key sent as `Authorization: Bearer <key>` or `X-API-Key: <key>`.
+fn presented_key(req: &ServiceRequest) -> Option<&str> {
+    let headers = req.headers();
+    if let Some(bearer) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return Some(bearer.trim());
+    }
+    headers.get("x-api-key").and_then(|v| v.to_str().ok())
+}
+
+/// Rejects requests without a valid key or unexpired token with 401. The key's role is stored on
+/// the request for the per-route checks, its [`KeyId`] for usage accounting, its [`Grants`] for
+/// the reads and [`Decrypt`] when it has the grant; a token's [`Scope`] is stored too. Tokens never
+/// decrypt.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let Some(keys) = req.app_data::<web::Data<ApiKeys>>().filter(|k| k.enabled()) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    // Probes from the orchestrator carry no key; readiness reveals nothing else.
+    if req.path() == startup::READY_PATH {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let tokens = req.app_data::<web::Data<Tokens>>();
+    let presented = presented_key(&req);
+    let id = KeyId::of(presented.unwrap_or_default());
+    let mut decrypt = false;
+    let (role, grants, scope) = match presented.and_then(|key| keys.lookup(key)) {
+        Some((role, grants, grant)) => {
+            decrypt = grant;
+            (role, grants, None)
+        }
+        None => match presented.zip(tokens).and_then(|(key, t)| t.verify(key)) {
+            Some(claims) if claims.expired() => return Ok(unauthorized(req, "Token expired")),
+            Some(claims) => token_caller(claims),
+            None => return Ok(unauthorized(req, "Missing or invalid API key")),
+        },
+    };
+    if let Some(scope) = scope {
+        req.extensions_mut().insert(scope);
+    }
+    req.extensions_mut().insert(role);
+    req.extensions_mut().insert(id);
+    if let Some(grants) = grants {
+        req.extensions_mut().insert(grants);
+    }
+    if decrypt {
+        req.extensions_mut().insert(Decrypt);
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+fn unauthorized<B>(req: ServiceRequest, message: &'static str) -> ServiceResponse<EitherBody<B>> {
+    let resp = HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+        .body(message);
+    req.into_response(resp).map_into_right_body()
+}
+
+/// What a token's holder may do: write only if it allows writes, within its scope.
+fn token_caller(claims: Claims) -> (Role, Option<Grants>, Option<Scope>) {
+    let role = match claims.actions.contains(&Action::Write) {
+        true => Role::Write,
+        false => Role::Read,
+    };
+    let grants = claims.tags.map(Grants::new);
+    let scope = Scope {
+        collection: claims.collection,
+        actions: claims.actions,
+    };
+    (role, grants, Some(scope))
+}
+
+// Every route declares the role it needs where it is registered, so one added later cannot be
+// reachable with a weaker key than it needs.
+
+/// `route` open to every key.
+pub fn read(route: Route) -> Route {
+    require(Role::Read, Some(Action::Read), route)
+}
+
+/// A search `route`, open to every key.
+pub fn search(route: Route) -> Route {
+    require(Role::Read, Some(Action::Search), route)
+}
+
+/// `route` open to write and admin keys.
+pub fn write(route: Route) -> Route {
+    require(Role::Write, Some(Action::Write), route)
+}
+
+/// `route` changing a collection itself, such as creating, deleting, cloning or configuring it:
+/// open to write and admin keys, but not to tokens, which only write points.
+pub fn manage(route: Route) -> Route {
+    require(Role::Write, None, route)
+}
+
+/// `route` open to admin keys only.
+pub fn admin(route: Route) -> Route {
+    require(Role::Admin, None, route)
+}
+
+fn require(role: Role, action: Option<Action>, route: Route) -> Route {
+    route.wrap(from_fn(move |req, next| check(role, action, req, next)))
+}
+
+/// Wraps the admin routes: with authentication on, only admin keys reach them.
+pub async fn admin_only(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    check(Role::Admin, None, req, next).await
+}
+
+/// With authentication on, answers 403 unless the request's key has at least `role` and, for a
+/// token, the route is on its collection and does one of its actions.
+async fn check(
+    role: Role,
+    action: Option<Action>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let enabled = req
+        .app_data::<web::Data<ApiKeys>>()
+        .is_some_and(|k| k.enabled());
+    let granted = req.extensions().get::<Role>().is_some_and(|r| *r >= role);
+    if enabled && !granted {
+        let message = match role {
+            Role::Admin => "API key does not allow admin routes",
+            _ => "API key does not allow writes",
+        };
+        let resp = HttpResponse::Forbidden().body(message);
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+    let refusal = req
+        .extensions()
+        .get::<Scope>()
+        .and_then(|scope| scope.refuses(action, req.match_info().get("name")));
+    if let Some(message) = refusal {
+        let resp = HttpResponse::Forbidden().body(message);
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::DEFAULT_TTL;
+    use actix_web::{http::StatusCode, test, App};
+    use std::time::Duration;
+
+    const ADMIN: &str = "admin-key";
+    /// A read key with the decrypt grant.
+    const DECRYPTER: &str = "decrypter-key";
+    const SECRET: &[u8] = b"secret";
+
+    /// A token for collection `c` allowing `actions`.
+    fn token(actions: Vec<Action>) -> String {
+        let claims = Claims::new("c".to_string(), actions, None, DEFAULT_TTL);
+        Tokens::new(SECRET, DEFAULT_TTL).mint(&claims)
+    }
+
+    /// Sends `req` with `credential` through authentication to routes needing each role, answering
+    /// with the status and body.
+    async fn call(credential: &str, req: test::TestRequest) -> (StatusCode, String) {
+        let keys = ApiKeys {
+            keys: RwLock::new(vec![
+                Key {
+                    secret: ADMIN.to_string(),
+                    role: Role::Admin,
+                    grants: None,
+                    decrypt: false,
+                },
+                Key {
+                    secret: DECRYPTER.to_string(),
+                    role: Role::Read,
+                    grants: None,
+                    decrypt: true,
+                },
+            ]),
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(keys))
+                .app_data(web::Data::new(Tokens::new(SECRET, DEFAULT_TTL)))
+                .wrap(from_fn(enforce))
+                .route("/c/{name}/points", read(web::get().to(HttpResponse::Ok)))
+                .route("/c/{name}/search", search(web::post().to(HttpResponse::Ok)))
+                .route("/c/{name}/upsert", write(web::post().to(HttpResponse::Ok)))
+                .route("/c/{name}", manage(web::delete().to(HttpResponse::Ok)))
+                .route("/c/{name}/join/{target}", search(web::post().to(join)))
+                .route("/c/{name}/decrypts", read(web::get().to(decrypt)))
+                .route("/usage", admin(web::get().to(HttpResponse::Ok))),
+        )
+        .await;
+        let req = req.insert_header(("x-api-key", credential)).to_request();
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status();
+        let body = test::read_body(resp).await;
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Stands in for a search joining `target`, checked as the search handler does.
+    async fn join(req: HttpRequest) -> HttpResponse {
+        let target = req.match_info().get("target").unwrap_or_default();
+        match scope(&req).and_then(|scope| scope.refuses_collection(target)) {
+            Some(message) => HttpResponse::Forbidden().body(message),
+            None => HttpResponse::Ok().finish(),
+        }
+    }
+
+    async fn decrypt(req: HttpRequest) -> HttpResponse {
+        HttpResponse::Ok().body(decrypts(&req).to_string())
+    }
+
+    #[actix_web::test]
+    async fn only_keys_granted_decrypt_read_encrypted_fields() {
+        let decrypts = |credential: String| async move {
+            let req = test::TestRequest::get().uri("/c/c/decrypts");
+            call(&credential, req).await.1
+        };
+        assert_eq!(decrypts(DECRYPTER.to_string()).await, "true");
+        assert_eq!(decrypts(ADMIN.to_string()).await, "false");
+        assert_eq!(decrypts(token(vec![Action::Read])).await, "false");
+    }
+
+    #[actix_web::test]
+    async fn scoped_tokens_cannot_join_other_collections() {
+        let search = token(vec![Action::Search]);
+        let req = test::TestRequest::post().uri("/c/c/join/d");
+        let (status, body) = call(&search, req).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, "Token is scoped to collection c");
+        let req = test::TestRequest::post().uri("/c/c/join/c");
+        assert_eq!(call(&search, req).await.0, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn tokens_cannot_change_collections() {
+        let write = token(vec![Action::Search, Action::Read, Action::Write]);
+        let req = test::TestRequest::post().uri("/c/c/upsert");
+        assert_eq!(call(&write, req).await.0, StatusCode::OK);
+        let req = test::TestRequest::delete().uri("/c/c");
+        assert_eq!(call(&write, req).await.0, StatusCode::FORBIDDEN);
+        let req = test::TestRequest::delete().uri("/c/c");
+        assert_eq!(call(ADMIN, req).await.0, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn tokens_reach_only_their_collection() {
+        let search = token(vec![Action::Search]);
+        let req = test::TestRequest::post().uri("/c/c/search");
+        assert_eq!(call(&search, req).await.0, StatusCode::OK);
+        let req = test::TestRequest::post().uri("/c/d/search");
+        let (status, body) = call(&search, req).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, "Token is scoped to collection c");
+    }
+
+    #[actix_web::test]
+    async fn tokens_do_only_what_they_were_minted_for() {
+        let search = token(vec![Action::Search]);
+        let req = test::TestRequest::get().uri("/c/c/points");
+        let (status, body) = call(&search, req).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, "Token does not allow this operation");
+        let read = token(vec![Action::Read]);
+        let req = test::TestRequest::get().uri("/c/c/points");
+        assert_eq!(call(&read, req).await.0, StatusCode::OK);
+        let req = test::TestRequest::post().uri("/c/c/search");
+        assert_eq!(call(&read, req).await.0, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn search_tokens_cannot_write() {
+        let search = token(vec![Action::Search, Action::Read]);
+        let req = test::TestRequest::post().uri("/c/c/upsert");
+        let (status, body) = call(&search, req).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, "API key does not allow writes");
+    }
+
+    #[actix_web::test]
+    async fn tokens_cannot_reach_admin_routes() {
+        let all = token(vec![Action::Search, Action::Read, Action::Write]);
+        let req = test::TestRequest::get().uri("/usage");
+        let (status, body) = call(&all, req).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, "API key does not allow admin routes");
+        let req = test::TestRequest::get().uri("/usage");
+        assert_eq!(call(ADMIN, req).await.0, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn expired_and_forged_tokens_are_unauthorized() {
+        let claims = Claims::new("c".to_string(), vec![Action::Search], None, Duration::ZERO);
+        let expired = Tokens::new(SECRET, DEFAULT_TTL).mint(&claims);
+        let req = test::TestRequest::post().uri("/c/c/search");
+        let (status, body) = call(&expired, req).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, "Token expired");
+        let forged = Tokens::new(b"other", DEFAULT_TTL).mint(&claims);
+        let req = test::TestRequest::post().uri("/c/c/search");
+        assert_eq!(call(&forged, req).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn tokens_get_the_role_of_their_actions() {
+        let caller =
+            |actions| token_caller(Claims::new("c".to_string(), actions, None, DEFAULT_TTL)).0;
+        assert_eq!(caller(vec![Action::Search, Action::Read]), Role::Read);
+        assert_eq!(caller(vec![Action::Write]), Role::Write);
+    }
+}
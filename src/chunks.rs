@@ -0,0 +1,198 @@
+use crate::{filter, payload};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+
+/// Most neighbouring chunks a search may ask for on each side of a hit.
+pub const MAX_CONTEXT: usize = 32;
+
+/// Declares a collection's points to be chunks of parent documents, e.g.
+/// `{"parent_field": "doc_id", "position_field": "chunk"}`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkConfig {
+    /// Dotted payload key holding the parent's id, as a number or a numeric string.
+    pub parent_field: String,
+    /// Dotted payload key holding the chunk's integer position in its parent. Chunks without one
+    /// are ordered by id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position_field: Option<String>,
+}
+
+impl ChunkConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let fields = std::iter::once(&self.parent_field).chain(&self.position_field);
+        for field in fields {
+            if field.split('.').any(str::is_empty) {
+                return Err(format!("invalid chunk field {:?}", field));
+            }
+        }
+        Ok(())
+    }
+
+    fn parent(&self, payload: &Value) -> Option<u64> {
+        filter::lookup(payload, &self.parent_field).and_then(payload::as_id)
+    }
+
+    /// Sort key of chunk `id` among its siblings.
+    fn position(&self, id: u64, payload: &Value) -> (i64, u64) {
+        let position = self
+            .position_field
+            .as_ref()
+            .and_then(|field| filter::lookup(payload, field))
+            .and_then(Value::as_i64)
+            .unwrap_or(id as i64);
+        (position, id)
+    }
+}
+
+/// Parent id -> its chunks in position order, kept in step with a collection's records.
+#[derive(Default)]
+pub struct ChunkIndex {
+    config: Option<ChunkConfig>,
+    parents: HashMap<u64, BTreeSet<(i64, u64)>>,
+}
+
+impl ChunkIndex {
+    pub fn new(config: Option<&ChunkConfig>) -> Self {
+        Self {
+            config: config.cloned(),
+            parents: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, id: u64, payload: &Value) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        if let Some(parent) = config.parent(payload) {
+            let position = config.position(id, payload);
+            self.parents.entry(parent).or_default().insert(position);
+        }
+    }
+
+    pub fn remove(&mut self, id: u64, payload: &Value) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let Some(parent) = config.parent(payload) else {
+            return;
+        };
+        if let Some(chunks) = self.parents.get_mut(&parent) {
+            chunks.remove(&config.position(id, payload));
+            if chunks.is_empty() {
+                self.parents.remove(&parent);
+            }
+        }
+    }
+
+    /// The parent of the chunk with `payload`, when the collection has chunks.
+    pub fn parent(&self, payload: &Value) -> Option<u64> {
+        self.config.as_ref()?.parent(payload)
+    }
+
+    /// Ids of up to `n` siblings before and after chunk `id`, nearest last and first.
+    pub fn siblings(&self, id: u64, payload: &Value, n: usize) -> (Vec<u64>, Vec<u64>) {
+        let Some(config) = &self.config else {
+            return (Vec::new(), Vec::new());
+        };
+        let Some(chunks) = config.parent(payload).and_then(|p| self.parents.get(&p)) else {
+            return (Vec::new(), Vec::new());
+        };
+        let position = config.position(id, payload);
+        let mut before: Vec<u64> = chunks
+            .range(..position)
+            .rev()
+            .take(n)
+            .map(|(_, id)| *id)
+            .collect();
+        before.reverse();
+        let after = chunks
+            .range(position..)
+            .skip(1)
+            .take(n)
+            .map(|(_, id)| *id)
+            .collect();
+        (before, after)
+    }
+}
+
+/// How a parent's score is computed from its matching chunks.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    /// Distance of the nearest chunk.
+    #[default]
+    Best,
+    /// Mean distance of the chunks found.
+    Mean,
+}
+
+/// Search option returning parents instead of chunks: `top_k` then counts parents.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupByParent {
+    #[serde(default)]
+    pub aggregate: Aggregate,
+    /// Best chunks returned with each parent.
+    #[serde(default = "default_hits_per_parent")]
+    pub hits_per_parent: usize,
+}
+
+fn default_hits_per_parent() -> usize {
+    1
+}
+
+/// A parent and the chunks of it a search found, best first.
+pub struct Group {
+    pub parent_id: u64,
+    pub distance: f32,
+    /// Matching chunks found, including those beyond `hits_per_parent`.
+    pub chunks: usize,
+    pub hits: Vec<(u64, f32)>,
+}
+
+/// Groups `results`, best first, by parent and returns the `top_k` best parents. Chunks without
+/// a parent are left out.
+pub fn group(
+    results: &[(u64, f32)],
+    parent_of: impl Fn(u64) -> Option<u64>,
+    options: &GroupByParent,
+    top_k: usize,
+) -> Vec<Group> {
+    let mut order = Vec::new();
+    let mut by_parent: HashMap<u64, Vec<(u64, f32)>> = HashMap::new();
+    for &(id, distance) in results {
+        let Some(parent) = parent_of(id) else {
+            continue;
+        };
+        by_parent
+            .entry(parent)
+            .or_insert_with(|| {
+                order.push(parent);
+                Vec::new()
+            })
+            .push((id, distance));
+    }
+    let mut groups: Vec<Group> = order
+        .into_iter()
+        .map(|parent_id| {
+            let mut hits = by_parent.remove(&parent_id).unwrap_or_default();
+            let distance = match options.aggregate {
+                Aggregate::Best => hits[0].1,
+                Aggregate::Mean => hits.iter().map(|(_, d)| d).sum::<f32>() / hits.len() as f32,
+            };
+            let chunks = hits.len();
+            hits.truncate(options.hits_per_parent);
+            Group {
+                parent_id,
+                distance,
+                chunks,
+                hits,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    groups.truncate(top_k);
+    groups
+}
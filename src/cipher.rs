@@ -0,0 +1,137 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{borrow::Cow, sync::OnceLock};
+
+/// Starts every sealed value. Plain JSON never does, so files written before a key was set are
+/// still told apart and read.
+const SEALED: &[u8] = b"enc1:";
+
+/// The key encrypted payload fields are sealed with, set at startup from `ENCRYPTION_KEY`.
+static FIELD_KEY: OnceLock<Cipher> = OnceLock::new();
+
+/// Encrypts files at rest, and payload fields, with AES-256-GCM. Each value is sealed on its own
+/// under a random nonce, so a WAL can be sealed line by line as it is appended, and is stored as
+/// `enc1:<base64>` of the nonce, ciphertext and tag.
+#[derive(Clone)]
+pub struct Cipher {
+    key: LessSafeKey,
+}
+
+impl Cipher {
+    /// Reads `ENCRYPTION_KEY`, 32 bytes in hex; files are written in the clear when it is unset.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let hex = match std::env::var("ENCRYPTION_KEY") {
+            Ok(hex) if !hex.is_empty() => hex,
+            _ => return Ok(None),
+        };
+        let key: Option<Vec<u8>> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect();
+        match key.as_deref().map(<[u8; 32]>::try_from) {
+            Some(Ok(key)) => Ok(Some(Self::new(&key))),
+            _ => Err("ENCRYPTION_KEY must be 64 hex digits (32 bytes)".to_string()),
+        }
+    }
+
+    pub fn new(key: &[u8; 32]) -> Self {
+        let key = UnboundKey::new(&AES_256_GCM, key).expect("AES-256 takes a 32-byte key");
+        Self {
+            key: LessSafeKey::new(key),
+        }
+    }
+
+    pub fn seal(&self, plain: &[u8]) -> Vec<u8> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("the system random source is readable");
+        let mut sealed = plain.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .expect("values fit AES-GCM's length limit");
+        let encoded = STANDARD.encode([&nonce[..], &sealed].concat());
+        [SEALED, encoded.as_bytes()].concat()
+    }
+
+    /// The plaintext of a value [`seal`](Self::seal) returned.
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        let undecryptable = || "cannot be decrypted: wrong ENCRYPTION_KEY, or damaged".to_string();
+        let encoded = sealed.strip_prefix(SEALED).ok_or_else(undecryptable)?;
+        let mut bytes = STANDARD
+            .decode(encoded.trim_ascii_end())
+            .map_err(|_| undecryptable())?;
+        if bytes.len() < NONCE_LEN {
+            return Err(undecryptable());
+        }
+        let mut plain = bytes.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&bytes).map_err(|_| undecryptable())?;
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut plain)
+            .map_err(|_| undecryptable())?
+            .len();
+        plain.truncate(len);
+        Ok(plain)
+    }
+}
+
+/// Seals encrypted payload fields with `cipher` from now on; the first key set stays.
+pub fn set_field_key(cipher: Cipher) {
+    let _ = FIELD_KEY.set(cipher);
+}
+
+pub fn field_key() -> Option<&'static Cipher> {
+    FIELD_KEY.get()
+}
+
+pub fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.starts_with(SEALED)
+}
+
+/// `bytes` as plaintext: opened with `cipher` when sealed, as they are otherwise.
+pub fn unseal<'a>(cipher: Option<&Cipher>, bytes: &'a [u8]) -> Result<Cow<'a, [u8]>, String> {
+    match cipher {
+        _ if !is_sealed(bytes) => Ok(Cow::Borrowed(bytes)),
+        Some(cipher) => cipher.open(bytes).map(Cow::Owned),
+        None => Err("is encrypted, but ENCRYPTION_KEY is not set".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_values_open_with_their_key_only() {
+        let cipher = Cipher::new(&[7; 32]);
+        let sealed = cipher.seal(b"{\"id\":1}");
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.windows(4).any(|w| w == b"\"id\""));
+        assert_eq!(cipher.open(&sealed).unwrap(), b"{\"id\":1}");
+        assert_ne!(cipher.seal(b"{\"id\":1}"), sealed);
+        assert!(Cipher::new(&[8; 32]).open(&sealed).is_err());
+        let mut damaged = sealed.clone();
+        let at = SEALED.len() + 20;
+        damaged[at] = if damaged[at] == b'A' { b'B' } else { b'A' };
+        assert!(cipher.open(&damaged).is_err());
+        assert!(cipher.open(&sealed[..sealed.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn plaintext_reads_as_it_is() {
+        let cipher = Cipher::new(&[7; 32]);
+        assert_eq!(&*unseal(Some(&cipher), b"{}").unwrap(), b"{}");
+        assert_eq!(&*unseal(None, b"{}").unwrap(), b"{}");
+        let sealed = cipher.seal(b"{}");
+        assert_eq!(&*unseal(Some(&cipher), &sealed).unwrap(), b"{}");
+        assert!(unseal(None, &sealed).is_err());
+    }
+}
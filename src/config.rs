@@ -0,0 +1,225 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use toml::{Table, Value};
+
+/// Read when `CONFIG_FILE` is unset and the file exists.
+const DEFAULT_PATH: &str = "config.toml";
+/// Prefix of the environment variables named after config keys: `VDB__STORAGE__DIR` sets
+/// `storage.dir`.
+const ENV_PREFIX: &str = "VDB__";
+
+/// Config file keys and the environment variables they stand for.
+const KEYS: &[(&str, &str)] = &[
+    ("host", "HOST"),
+    ("port", "PORT"),
+    ("log_level", "RUST_LOG"),
+    ("node_id", "NODE_ID"),
+    ("admin.host", "ADMIN_HOST"),
+    ("admin.port", "ADMIN_PORT"),
+    ("unix_socket.path", "UNIX_SOCKET"),
+    ("unix_socket.mode", "UNIX_SOCKET_MODE"),
+    ("tls.cert", "TLS_CERT"),
+    ("tls.key", "TLS_KEY"),
+    ("tls.http_redirect_port", "HTTP_REDIRECT_PORT"),
+    ("auth.api_key", "API_KEY"),
+    ("auth.api_keys", "API_KEYS"),
+    ("auth.api_key_tags", "API_KEY_TAGS"),
+    ("auth.api_key_decrypt", "API_KEY_DECRYPT"),
+    ("tokens.secret", "TOKEN_SECRET"),
+    ("tokens.max_ttl_secs", "TOKEN_MAX_TTL_SECS"),
+    ("usage.max_requests", "USAGE_MAX_REQUESTS"),
+    ("usage.max_write_bytes", "USAGE_MAX_WRITE_BYTES"),
+    ("network.allowed_cidrs", "ALLOWED_CIDRS"),
+    ("storage.dir", "STORAGE_DIR"),
+    ("storage.trash_retention_secs", "TRASH_RETENTION_SECS"),
+    ("storage.ignore_lock", "STORAGE_IGNORE_LOCK"),
+    ("storage.wal_checkpoint_secs", "WAL_CHECKPOINT_SECS"),
+    ("storage.encryption_key", "ENCRYPTION_KEY"),
+    ("snapshots.dir", "SNAPSHOT_DIR"),
+    ("audit_log.path", "AUDIT_LOG"),
+    ("query_log.path", "QUERY_LOG"),
+    ("query_log.max_bytes", "QUERY_LOG_MAX_BYTES"),
+    ("query_log.files", "QUERY_LOG_FILES"),
+    ("query_log.vectors", "QUERY_LOG_VECTORS"),
+    ("search.verbosity", "SEARCH_VERBOSITY"),
+    ("search.envelope", "RESPONSE_ENVELOPE"),
+    ("limits.max_top_k", "MAX_TOP_K"),
+    ("limits.max_batch_size", "MAX_BATCH_SIZE"),
+    ("limits.max_body_bytes", "MAX_BODY_BYTES"),
+    ("limits.max_filter_clauses", "MAX_FILTER_CLAUSES"),
+    ("limits.max_filter_depth", "MAX_FILTER_DEPTH"),
+    ("memory.soft_limit_bytes", "MEMORY_SOFT_LIMIT_BYTES"),
+    ("memory.hard_limit_bytes", "MEMORY_HARD_LIMIT_BYTES"),
+    ("memory.degraded_ef_search", "MEMORY_DEGRADED_EF_SEARCH"),
+    ("hnsw.max_nb_connection", "HNSW_MAX_NB_CONNECTION"),
+    ("hnsw.ef_search", "HNSW_EF_SEARCH"),
+    ("hnsw.max_elements", "HNSW_MAX_ELEMENTS"),
+    ("hnsw.ef_construction", "HNSW_EF_CONSTRUCTION"),
+    ("hnsw.max_layer", "HNSW_MAX_LAYER"),
+    ("hnsw.extend_candidates", "HNSW_EXTEND_CANDIDATES"),
+    ("hnsw.keep_pruned", "HNSW_KEEP_PRUNED"),
+];
+
+/// Variables and the values to set them to.
+type Settings = Vec<(&'static str, String)>;
+
+/// Variables set from the config file, which a reload may change or unset again.
+static FROM_FILE: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// Loads the TOML file named by `CONFIG_FILE`, or `config.toml` when present, into the
+/// environment variables its keys stand for. Variables already set win, so the environment
+/// overrides the file; `VDB__SECTION__KEY` variables override both. Returns the path read, if
+/// any.
+pub fn load() -> Result<Option<PathBuf>, String> {
+    let mut prefixed = Vec::new();
+    for (name, value) in std::env::vars_os() {
+        let Some(name) = name.to_str().filter(|n| n.starts_with(ENV_PREFIX)) else {
+            continue;
+        };
+        let var = prefixed_var(name).ok_or_else(|| format!("unknown variable {}", name))?;
+        let value = value
+            .into_string()
+            .map_err(|_| format!("{} is not valid UTF-8", name))?;
+        prefixed.push((var, value));
+    }
+    for (var, value) in prefixed {
+        std::env::set_var(var, value);
+    }
+    reload()
+}
+
+/// Reads the config file again, replacing the variables it set before. Variables from the
+/// environment still win. A file that cannot be read leaves the variables as they were.
+pub fn reload() -> Result<Option<PathBuf>, String> {
+    let read = read_file()?;
+    let mut from_file = FROM_FILE.lock().unwrap();
+    for var in from_file.drain(..) {
+        std::env::remove_var(var);
+    }
+    let Some((path, settings)) = read else {
+        return Ok(None);
+    };
+    for (var, value) in settings {
+        if std::env::var_os(var).is_none() {
+            std::env::set_var(var, value);
+            from_file.push(var);
+        }
+    }
+    Ok(Some(path))
+}
+
+/// The config file's path and the variable and value of each of its keys, if there is a file.
+fn read_file() -> Result<Option<(PathBuf, Settings)>, String> {
+    let path = match std::env::var("CONFIG_FILE") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) if Path::new(DEFAULT_PATH).exists() => PathBuf::from(DEFAULT_PATH),
+        Err(_) => return Ok(None),
+    };
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| format!("could not read config file {}: {}", path.display(), e))?;
+    let table: Table = text
+        .parse()
+        .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+    let mut settings = Vec::new();
+    flatten("", &table, &mut settings)
+        .map_err(|e| format!("config file {}: {}", path.display(), e))?;
+    Ok(Some((path, settings)))
+}
+
+/// Collects the variable and value of every key in `table`, whose keys start with `prefix`.
+fn flatten(prefix: &str, table: &Table, settings: &mut Settings) -> Result<(), String> {
+    for (key, value) in table {
+        let path = match prefix {
+            "" => key.clone(),
+            prefix => format!("{}.{}", prefix, key),
+        };
+        if let Value::Table(inner) = value {
+            flatten(&path, inner, settings)?;
+            continue;
+        }
+        let Some((_, var)) = KEYS.iter().find(|(k, _)| *k == path) else {
+            return Err(format!("unknown key {}", path));
+        };
+        let value = match value {
+            Value::Array(items) => items
+                .iter()
+                .map(scalar)
+                .collect::<Option<Vec<_>>>()
+                .map(|items| items.join(",")),
+            value => scalar(value),
+        };
+        let value = value.ok_or_else(|| format!("{} must be a string, number or boolean", path))?;
+        settings.push((var, value));
+    }
+    Ok(())
+}
+
+/// The variable a `VDB__SECTION__KEY` variable stands for, matching the key case-insensitively.
+fn prefixed_var(name: &str) -> Option<&'static str> {
+    let key = name
+        .strip_prefix(ENV_PREFIX)?
+        .split("__")
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join(".");
+    KEYS.iter().find(|(k, _)| *k == key).map(|(_, var)| *var)
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixed_variables_name_keys() {
+        assert_eq!(prefixed_var("VDB__PORT"), Some("PORT"));
+        assert_eq!(prefixed_var("VDB__STORAGE__DIR"), Some("STORAGE_DIR"));
+        assert_eq!(prefixed_var("VDB__AUTH__API_KEYS"), Some("API_KEYS"));
+        assert_eq!(prefixed_var("VDB__hnsw__ef_search"), Some("HNSW_EF_SEARCH"));
+        assert_eq!(prefixed_var("VDB__LOG_LEVEL"), Some("RUST_LOG"));
+        assert_eq!(prefixed_var("VDB__STORAGE_DIR"), None);
+        assert_eq!(prefixed_var("VDB__STORAGE__DIR__X"), None);
+        assert_eq!(prefixed_var("STORAGE_DIR"), None);
+    }
+
+    #[test]
+    fn every_key_has_a_prefixed_variable() {
+        for (key, var) in KEYS {
+            let name = format!("{}{}", ENV_PREFIX, key.replace('.', "__").to_uppercase());
+            assert_eq!(prefixed_var(&name), Some(*var), "{}", name);
+        }
+    }
+
+    #[test]
+    fn reloads_replace_what_the_file_set() {
+        let path = std::env::temp_dir().join(format!("vector_db-{}.toml", uuid::Uuid::new_v4()));
+        std::env::set_var("CONFIG_FILE", &path);
+        std::env::set_var("QUERY_LOG_MAX_BYTES", "99");
+        let write = |text: &str| std::fs::write(&path, text).unwrap();
+        write("[query_log]\nfiles = 3\nmax_bytes = 10\n");
+        reload().unwrap();
+        assert_eq!(std::env::var("QUERY_LOG_FILES").as_deref(), Ok("3"));
+        assert_eq!(std::env::var("QUERY_LOG_MAX_BYTES").as_deref(), Ok("99"));
+        write("[query_log]\nmax_bytes = 10\n");
+        reload().unwrap();
+        assert!(std::env::var("QUERY_LOG_FILES").is_err());
+        write("[query_log]\nfiles = 4\nnope = 1\n");
+        assert!(reload().is_err());
+        assert!(std::env::var("QUERY_LOG_FILES").is_err());
+        assert_eq!(std::env::var("QUERY_LOG_MAX_BYTES").as_deref(), Ok("99"));
+        std::env::remove_var("CONFIG_FILE");
+        std::env::remove_var("QUERY_LOG_MAX_BYTES");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+/// Content-hash deduplication settings for a collection.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DedupConfig {
+    /// Payload fields that take part in the hash, next to the vector itself.
+    #[serde(default)]
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub mode: DedupMode,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMode {
+    /// Drop the incoming duplicate and keep the stored point untouched.
+    #[default]
+    Skip,
+    /// Merge the incoming payload's top-level fields into the stored point.
+    Merge,
+}
+
+/// Hash of a vector and the selected payload fields. serde_json objects are key-sorted, so the
+/// serialized form of a field value is canonical.
+pub fn content_hash(vector: &[f32], payload: &serde_json::Value, fields: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for x in vector {
+        x.to_bits().hash(&mut hasher);
+    }
+    for field in fields {
+        field.hash(&mut hasher);
+        payload.get(field).map(|v| v.to_string()).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+pub fn merge_payload(target: &mut serde_json::Value, incoming: &serde_json::Value) {
+    match (target, incoming) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(incoming)) => {
+            for (k, v) in incoming {
+                target.insert(k.clone(), v.clone());
+            }
+        }
+        (target, incoming) if !incoming.is_null() => *target = incoming.clone(),
+        _ => {}
+    }
+}
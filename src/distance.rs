@@ -0,0 +1,65 @@
+use hnsw_rs::prelude::Distance;
+
+/// Largest vector norm accepted by `"dot"` collections; a little over 1 for rounding in
+/// normalization.
+pub const MAX_DOT_NORM: f32 = 1.001;
+
+/// Inner-product distance `1 - a·b`, as `DistDot` computes it, for vectors of norm at most one.
+/// `DistDot` asserts the result is non-negative, which rounding breaks even for unit vectors;
+/// this clamps it at zero instead, as the graph requires.
+#[derive(Default, Clone, Copy)]
+pub struct DotProduct;
+
+impl Distance<f32> for DotProduct {
+    fn eval(&self, va: &[f32], vb: &[f32]) -> f32 {
+        let dot: f32 = va.iter().zip(vb).map(|(a, b)| a * b).sum();
+        (1.0 - dot).max(0.0)
+    }
+}
+
+pub fn norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+/// Turns a distance under the named metric into a score where higher is better: the cosine
+/// similarity for `"cosine"`, the inner product for `"dot"` and `1 / (1 + distance)`, in (0, 1],
+/// for `"l2"`.
+pub fn score(metric: &str, distance: f32) -> f32 {
+    match metric {
+        "cosine" | "dot" => 1.0 - distance,
+        _ => 1.0 / (1.0 + distance),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_distance_is_clamped_at_zero() {
+        let v = [0.6, 0.8];
+        assert_eq!(DotProduct.eval(&v, &v), 0.0);
+        // Norms just over 1 from rounding must not go negative either.
+        let w = [0.6006, 0.8008];
+        assert_eq!(DotProduct.eval(&w, &w), 0.0);
+        assert!((DotProduct.eval(&[1.0, 0.0], &[0.0, 1.0]) - 1.0).abs() < 1e-6);
+        assert!((DotProduct.eval(&[1.0, 0.0], &[-1.0, 0.0]) - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn scores_rank_closer_points_higher() {
+        assert_eq!(score("dot", 0.25), 0.75);
+        assert_eq!(score("cosine", 0.0), 1.0);
+        assert_eq!(score("l2", 0.0), 1.0);
+        assert_eq!(score("l2", 1.0), 0.5);
+        for metric in ["cosine", "dot", "l2"] {
+            assert!(score(metric, 0.1) > score(metric, 0.2), "{}", metric);
+        }
+    }
+
+    #[test]
+    fn norm_of_vectors() {
+        assert_eq!(norm(&[3.0, 4.0]), 5.0);
+        assert_eq!(norm(&[]), 0.0);
+    }
+}
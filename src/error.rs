@@ -0,0 +1,65 @@
+use crate::request_id::RequestId;
+use actix_web::{
+    body::{to_bytes, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderValue},
+    middleware::Next,
+    Error, HttpMessage,
+};
+use serde::Serialize;
+
+/// Body of every 4xx and 5xx response.
+#[derive(Serialize)]
+pub struct ApiError {
+    pub status: u16,
+    /// The status reason in snake case, e.g. `not_found` or `unprocessable_entity`.
+    pub code: String,
+    pub message: String,
+    /// Same as the `X-Request-Id` response header.
+    pub request_id: String,
+}
+
+/// Turns error responses into JSON `ApiError`s, whether they come from a handler, an extractor
+/// (malformed JSON, bad path parameters), a middleware or the router. Handlers answer with a
+/// plain-text message, which becomes `message`. Responses that already are JSON, such as
+/// validation results, are left alone.
+pub async fn to_json(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let res = next.call(req).await?.map_into_boxed_body();
+    let status = res.status();
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return Ok(res);
+    }
+    let request_id = res
+        .request()
+        .extensions()
+        .get::<RequestId>()
+        .map(|r| r.0.clone())
+        .unwrap_or_default();
+    let (req, res) = res.into_parts();
+    let (mut head, body) = res.into_parts();
+    let message = match to_bytes(body).await {
+        Ok(bytes) if !bytes.is_empty() => String::from_utf8_lossy(&bytes).into_owned(),
+        _ => status.canonical_reason().unwrap_or_default().to_string(),
+    };
+    let reason = status.canonical_reason().unwrap_or("error");
+    let error = ApiError {
+        status: status.as_u16(),
+        code: reason.to_ascii_lowercase().replace([' ', '-'], "_"),
+        message,
+        request_id,
+    };
+    let json = serde_json::to_string(&error).expect("error body serializes");
+    head.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Ok(ServiceResponse::new(req, head.set_body(BoxBody::new(json))))
+}
@@ -0,0 +1,51 @@
+use actix_web::{
+    http::header::{CacheControl, CacheDirective, ETag, EntityTag, Header, IfNoneMatch},
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use serde::Serialize;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::OnceLock,
+};
+
+/// Version string for an in-memory counter. Counters restart at zero, so the tag includes a
+/// per-process id to keep tags from a previous run from matching.
+pub fn versioned(counter: u64) -> String {
+    static INSTANCE: OnceLock<String> = OnceLock::new();
+    let instance = INSTANCE.get_or_init(|| uuid::Uuid::new_v4().simple().to_string());
+    format!("{}-{}", &instance[..8], counter)
+}
+
+/// Version string for a resource without a change counter, derived from its serialized form.
+pub fn of_content(value: &impl Serialize) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_vec(value)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// 304 response for a request whose `If-None-Match` already has `version`.
+pub fn not_modified(req: &HttpRequest, version: &str) -> Option<HttpResponse> {
+    let tag = EntityTag::new_strong(version.to_string());
+    let matches = match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(items)) => items.iter().any(|t| t.weak_eq(&tag)),
+        Err(_) => false,
+    };
+    matches.then(|| with_tag(HttpResponse::NotModified(), version).finish())
+}
+
+/// Starts a 200 carrying the ETag for `version`. Clients and proxies may cache the body but must
+/// revalidate (`no-cache`).
+pub fn ok(version: &str) -> HttpResponseBuilder {
+    with_tag(HttpResponse::Ok(), version)
+}
+
+fn with_tag(mut builder: HttpResponseBuilder, version: &str) -> HttpResponseBuilder {
+    builder
+        .insert_header(ETag(EntityTag::new_strong(version.to_string())))
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]));
+    builder
+}
@@ -0,0 +1,77 @@
+use actix_web::http::header::HeaderName;
+use serde::{Deserialize, Serialize};
+
+/// Response header naming the bucket an experiment search ran in.
+pub const HEADER: HeaderName = HeaderName::from_static("x-experiment-bucket");
+
+/// Splits searches between templates by a client-provided user key, so each user consistently
+/// lands in the same bucket.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bucket {
+    pub name: String,
+    /// Share of users, in percent; the buckets' weights add up to 100.
+    pub weight: u32,
+    /// Template the bucket's searches run.
+    pub template: String,
+}
+
+impl Experiment {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.buckets.is_empty() {
+            return Err("an experiment needs at least one bucket".to_string());
+        }
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            let valid_chars = bucket
+                .name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+            if bucket.name.is_empty() || bucket.name.len() > 64 || !valid_chars {
+                return Err(format!("invalid bucket name {:?}", bucket.name));
+            }
+            if self.buckets[..i].iter().any(|b| b.name == bucket.name) {
+                return Err(format!("duplicate bucket name {:?}", bucket.name));
+            }
+        }
+        let total: u32 = self.buckets.iter().map(|b| b.weight).sum();
+        if total != 100 {
+            return Err(format!("bucket weights add up to {}, expected 100", total));
+        }
+        Ok(())
+    }
+
+    /// The bucket for `user_key` in the experiment called `name`. Assignment depends only on
+    /// the two, so it survives restarts and differs between experiments.
+    pub fn assign(&self, name: &str, user_key: &str) -> &Bucket {
+        let point = (stable_hash(&[name.as_bytes(), b"\0", user_key.as_bytes()]) % 100) as u32;
+        let mut upto = 0;
+        for bucket in &self.buckets {
+            upto += bucket.weight;
+            if point < upto {
+                return bucket;
+            }
+        }
+        self.buckets.last().expect("validated to be non-empty")
+    }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is specified and so stable across builds, followed by
+/// the murmur3 finalizer so keys differing only in their last byte spread across buckets.
+fn stable_hash(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in parts.iter().flat_map(|p| p.iter()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
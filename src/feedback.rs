@@ -0,0 +1,88 @@
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+/// Events kept per collection when there is no storage directory; older ones are dropped.
+const KEEP_IN_MEMORY: usize = 10_000;
+
+/// Relevance feedback on one search, identified by the `X-Request-Id` it was answered with.
+#[derive(Serialize)]
+pub struct FeedbackEvent {
+    pub ts_ms: u64,
+    pub query_id: String,
+    pub clicked: Vec<u64>,
+    pub accepted: Vec<u64>,
+}
+
+/// A collection's feedback events, appended to `feedback.jsonl` in its storage directory or,
+/// without one, kept in memory.
+pub struct FeedbackLog {
+    file: Option<(PathBuf, File)>,
+    memory: VecDeque<String>,
+    /// Events recorded by this process; versions exports.
+    pub recorded: u64,
+}
+
+impl FeedbackLog {
+    pub fn open(path: Option<PathBuf>) -> Result<Self, String> {
+        let file = match path {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                Some((path, file))
+            }
+            None => None,
+        };
+        Ok(Self {
+            file,
+            memory: VecDeque::new(),
+            recorded: 0,
+        })
+    }
+
+    pub fn record(&mut self, event: &FeedbackEvent) -> Result<(), String> {
+        let line = serde_json::to_string(event).map_err(|e| e.to_string())?;
+        match &mut self.file {
+            Some((path, file)) => {
+                writeln!(file, "{}", line).map_err(|e| format!("{}: {}", path.display(), e))?
+            }
+            None => {
+                if self.memory.len() == KEEP_IN_MEMORY {
+                    self.memory.pop_front();
+                }
+                self.memory.push_back(line);
+            }
+        }
+        self.recorded += 1;
+        Ok(())
+    }
+
+    /// Forces recorded events to disk, when they are stored there.
+    pub fn sync(&self) -> Result<(), String> {
+        match &self.file {
+            Some((path, file)) => file
+                .sync_all()
+                .map_err(|e| format!("{}: {}", path.display(), e)),
+            None => Ok(()),
+        }
+    }
+
+    /// All stored events as NDJSON, oldest first.
+    pub fn export(&self) -> Result<Vec<u8>, String> {
+        match &self.file {
+            Some((path, _)) => fs::read(path).map_err(|e| format!("{}: {}", path.display(), e)),
+            None => Ok(self
+                .memory
+                .iter()
+                .flat_map(|line| line.bytes().chain(std::iter::once(b'\n')))
+                .collect()),
+        }
+    }
+}
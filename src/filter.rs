@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Payload conditions a search hit must satisfy: all of `must`, at least one of `should` (when
+/// given) and none of `must_not`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    #[serde(default)]
+    pub must: Vec<Condition>,
+    #[serde(default)]
+    pub should: Vec<Condition>,
+    #[serde(default)]
+    pub must_not: Vec<Condition>,
+}
+
+/// A test on the payload field at `key`; dots address nested objects (`meta.lang`).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Condition {
+    pub key: String,
+    #[serde(flatten)]
+    pub test: Test,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Test {
+    /// Equal to the value, or containing it when the field is an array.
+    Match(Value),
+    /// Equal to, or when the field is an array containing, any of the values.
+    In(Vec<Value>),
+    Range(Range),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Range {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gt: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gte: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lt: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lte: Option<f64>,
+}
+
+impl Filter {
+    pub fn matches(&self, payload: &Value) -> bool {
+        self.must.iter().all(|c| c.matches(payload))
+            && (self.should.is_empty() || self.should.iter().any(|c| c.matches(payload)))
+            && !self.must_not.iter().any(|c| c.matches(payload))
+    }
+}
+
+impl Filter {
+    /// Conditions in all lists, counting every value of an `in` condition as one.
+    pub fn clauses(&self) -> usize {
+        let conditions = self.must.iter().chain(&self.should).chain(&self.must_not);
+        conditions
+            .map(|c| match &c.test {
+                Test::In(values) => values.len().max(1),
+                _ => 1,
+            })
+            .sum()
+    }
+
+    /// Segments in the most deeply nested key, e.g. 2 for `meta.lang`.
+    pub fn depth(&self) -> usize {
+        let conditions = self.must.iter().chain(&self.should).chain(&self.must_not);
+        conditions
+            .map(|c| c.key.split('.').count())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl Condition {
+    fn matches(&self, payload: &Value) -> bool {
+        let Some(field) = lookup(payload, &self.key) else {
+            return false;
+        };
+        let values = match field {
+            Value::Array(items) => items.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        match &self.test {
+            Test::Match(wanted) => values.iter().any(|v| json_eq(v, wanted)),
+            Test::In(wanted) => values
+                .iter()
+                .any(|v| wanted.iter().any(|w| json_eq(v, w))),
+            Test::Range(range) => values
+                .iter()
+                .any(|v| v.as_f64().is_some_and(|x| range.contains(x))),
+        }
+    }
+}
+
+impl Range {
+    fn contains(&self, x: f64) -> bool {
+        self.gt.is_none_or(|b| x > b)
+            && self.gte.is_none_or(|b| x >= b)
+            && self.lt.is_none_or(|b| x < b)
+            && self.lte.is_none_or(|b| x <= b)
+    }
+}
+
+/// The value at a dotted `key`, e.g. `meta.lang`.
+pub fn lookup<'v>(payload: &'v Value, key: &str) -> Option<&'v Value> {
+    key.split('.').try_fold(payload, |v, part| v.get(part))
+}
+
+/// JSON equality that treats `1` and `1.0` as the same number.
+fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn filter(filter: Value) -> Filter {
+        serde_json::from_value(filter).unwrap()
+    }
+
+    #[test]
+    fn match_on_scalars_and_arrays() {
+        let f = filter(json!({"must": [{"key": "lang", "match": "en"}]}));
+        assert!(f.matches(&json!({"lang": "en"})));
+        assert!(f.matches(&json!({"lang": ["de", "en"]})));
+        assert!(!f.matches(&json!({"lang": "de"})));
+        assert!(!f.matches(&json!({"other": "en"})));
+        assert!(!f.matches(&json!(null)));
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        let f = filter(json!({"must": [{"key": "n", "match": 1}]}));
+        assert!(f.matches(&json!({"n": 1.0})));
+        assert!(!f.matches(&json!({"n": "1"})));
+        let f = filter(json!({"must": [{"key": "n", "in": [2.0, 3]}]}));
+        assert!(f.matches(&json!({"n": 2})));
+        assert!(f.matches(&json!({"n": [5, 3]})));
+        assert!(!f.matches(&json!({"n": 4})));
+    }
+
+    #[test]
+    fn range_bounds() {
+        let f = filter(json!({"must": [{"key": "n", "range": {"gt": 1, "lte": 3}}]}));
+        assert!(!f.matches(&json!({"n": 1})));
+        assert!(f.matches(&json!({"n": 1.5})));
+        assert!(f.matches(&json!({"n": 3})));
+        assert!(!f.matches(&json!({"n": 3.5})));
+        assert!(f.matches(&json!({"n": [0, 2]})));
+        assert!(!f.matches(&json!({"n": "2"})));
+    }
+
+    #[test]
+    fn nested_keys() {
+        let f = filter(json!({"must": [{"key": "meta.lang", "match": "en"}]}));
+        assert!(f.matches(&json!({"meta": {"lang": "en"}})));
+        assert!(!f.matches(&json!({"meta.lang": "en"})));
+        assert!(!f.matches(&json!({"meta": "en"})));
+    }
+
+    #[test]
+    fn must_should_and_must_not_combine() {
+        let f = filter(json!({
+            "must": [{"key": "a", "match": 1}],
+            "should": [{"key": "b", "match": 1}, {"key": "c", "match": 1}],
+            "must_not": [{"key": "d", "match": 1}],
+        }));
+        assert!(f.matches(&json!({"a": 1, "b": 1})));
+        assert!(f.matches(&json!({"a": 1, "c": 1})));
+        assert!(!f.matches(&json!({"a": 1})));
+        assert!(!f.matches(&json!({"b": 1})));
+        assert!(!f.matches(&json!({"a": 1, "b": 1, "d": 1})));
+        assert!(Filter::default().matches(&json!({})));
+    }
+
+    #[test]
+    fn clauses_and_depth() {
+        let f = filter(json!({
+            "must": [{"key": "a", "in": [1, 2, 3]}, {"key": "x.y.z", "match": 1}],
+            "must_not": [{"key": "b", "in": []}],
+        }));
+        assert_eq!(f.clauses(), 5);
+        assert_eq!(f.depth(), 3);
+        assert_eq!(Filter::default().clauses(), 0);
+        assert_eq!(Filter::default().depth(), 0);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(serde_json::from_value::<Filter>(json!({"mustnt": []})).is_err());
+        let range = json!({"must": [{"key": "n", "range": {"gt": 1, "above": 2}}]});
+        assert!(serde_json::from_value::<Filter>(range).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Optional GraphQL interface (`--features graphql`), served at `/graphql`.
+
+use crate::{
+    auth::{self, Decrypt, Grants},
+    payload::PayloadSelector,
+    AppState, Collection, GrowthEvent, SearchParams, VectorRecord, Visible,
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use async_graphql::{
+    http::GraphiQLSource, Context, EmptyMutation, EmptySubscription, Json, Object, Result, Schema,
+    SimpleObject,
+};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+
+pub type VectorSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn schema(state: web::Data<AppState<'static>>) -> VectorSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(state)
+        .finish()
+}
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/graphql", auth::read(web::post().to(graphql)))
+        .route("/graphql", auth::read(web::get().to(graphiql)));
+}
+
+/// Runs a query with the caller's tag grants, if any, and [`Decrypt`] if it may read encrypted
+/// fields, in its context.
+async fn graphql(
+    schema: web::Data<VectorSchema>,
+    http: HttpRequest,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    let mut req = req.into_inner();
+    if let Some(grants) = auth::grants(&http) {
+        req = req.data(grants);
+    }
+    if auth::decrypts(&http) {
+        req = req.data(Decrypt);
+    }
+    schema.execute(req).await.into()
+}
+
+async fn graphiql() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+fn state<'c>(ctx: &Context<'c>) -> &'c AppState<'static> {
+    ctx.data_unchecked::<web::Data<AppState<'static>>>()
+}
+
+fn visible<'c>(ctx: &Context<'c>) -> Visible<'c> {
+    Visible::new(None, ctx.data_opt::<Grants>())
+}
+
+pub struct QueryRoot;
+
+#[derive(SimpleObject)]
+struct CollectionInfo {
+    name: String,
+    dim: usize,
+    distance: String,
+    points: usize,
+    capacity: usize,
+    growth_events: Json<Vec<GrowthEvent>>,
+}
+
+struct Point(VectorRecord);
+
+impl Point {
+    /// Record `r` of `coll`, with its encrypted fields opened if the caller may read them.
+    fn new(ctx: &Context<'_>, coll: &Collection, r: &VectorRecord) -> Self {
+        let decrypt = ctx.data_opt::<Decrypt>().is_some();
+        Self(VectorRecord {
+            payload: coll.shown(&r.payload, None, decrypt),
+            ..r.clone()
+        })
+    }
+}
+
+#[Object]
+impl Point {
+    async fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    async fn vector(&self) -> &Vec<f32> {
+        &self.0.vector
+    }
+
+    /// The stored payload, optionally narrowed to the given top-level fields.
+    async fn payload(&self, fields: Option<Vec<String>>) -> Json<serde_json::Value> {
+        match fields {
+            Some(fields) => Json(PayloadSelector::Include(fields).apply(&self.0.payload)),
+            None => Json(self.0.payload.clone()),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct Hit {
+    id: u64,
+    distance: f32,
+    /// `distance` as a score where higher is better, e.g. the cosine similarity.
+    score: f32,
+    point: Option<Point>,
+}
+
+#[Object]
+impl QueryRoot {
+    async fn collections(&self, ctx: &Context<'_>) -> Vec<CollectionInfo> {
+        let collections = state(ctx).collections.read().unwrap();
+        collections
+            .iter()
+            .map(|(name, coll)| (name, coll.read().unwrap()))
+            .map(|(name, coll)| CollectionInfo {
+                name: name.clone(),
+                dim: coll.dim,
+                distance: coll.config.distance.clone(),
+                points: coll.len(),
+                capacity: coll.config.hnsw.max_elements,
+                growth_events: Json(coll.growth_events.clone()),
+            })
+            .collect()
+    }
+
+    async fn point(&self, ctx: &Context<'_>, collection: String, id: u64) -> Result<Option<Point>> {
+        let coll = state(ctx)
+            .collection(&collection)
+            .ok_or("Collection not found")?;
+        let coll = coll.read().unwrap();
+        let visible = visible(ctx);
+        Ok(coll
+            .get(id)
+            .filter(|r| visible.may_read(r))
+            .map(|r| Point::new(ctx, &coll, r)))
+    }
+
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        collection: String,
+        query: Vec<f32>,
+        top_k: usize,
+    ) -> Result<Vec<Hit>> {
+        let with_point = ctx.look_ahead().field("point").exists();
+        state(ctx).limits.check_top_k(top_k)?;
+        let coll = state(ctx)
+            .collection(&collection)
+            .ok_or("Collection not found")?;
+        let coll = coll.read().unwrap();
+        coll.check_vector(&query)?;
+        Ok(coll
+            .search_with(&query, top_k, visible(ctx), &SearchParams::default(), None)
+            .into_iter()
+            .map(|(id, distance)| Hit {
+                id,
+                distance,
+                score: coll.score(distance),
+                point: with_point
+                    .then(|| coll.get(id))
+                    .flatten()
+                    .map(|r| Point::new(ctx, &coll, r)),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// 2024-01-01T00:00:00Z, so the 41-bit timestamp lasts until ~2093.
+const EPOCH_MS: u64 = 1_704_067_200_000;
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+pub const MAX_NODE_ID: u64 = (1 << NODE_BITS) - 1;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+
+/// Snowflake-style id generator: 41 bits of milliseconds, 10 bits of node id, 12 bits of sequence.
+/// Ids are unique across nodes as long as every node is started with a distinct `NODE_ID`.
+pub struct IdGenerator {
+    node_id: u64,
+    state: Mutex<(u64, u64)>, // (last timestamp, sequence)
+}
+
+impl IdGenerator {
+    pub fn new(node_id: u64) -> Self {
+        assert!(node_id <= MAX_NODE_ID, "node id must be <= {}", MAX_NODE_ID);
+        Self {
+            node_id,
+            state: Mutex::new((0, 0)),
+        }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let node_id = match std::env::var("NODE_ID") {
+            Ok(v) => v
+                .parse::<u64>()
+                .ok()
+                .filter(|id| *id <= MAX_NODE_ID)
+                .ok_or_else(|| format!("NODE_ID must be an integer in 0..={}", MAX_NODE_ID))?,
+            Err(_) => 0,
+        };
+        Ok(Self::new(node_id))
+    }
+
+    pub fn next_id(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let (last, seq) = *state;
+        // Never go backwards, even if the wall clock does.
+        let mut now = now_ms().max(last);
+        let seq = if now == last {
+            if seq == MAX_SEQUENCE {
+                while now <= last {
+                    std::hint::spin_loop();
+                    now = now_ms();
+                }
+                0
+            } else {
+                seq + 1
+            }
+        } else {
+            0
+        };
+        *state = (now, seq);
+        ((now - EPOCH_MS) << (NODE_BITS + SEQUENCE_BITS)) | (self.node_id << SEQUENCE_BITS) | seq
+    }
+}
+
+fn now_ms() -> u64 {
+    let ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    ms.max(EPOCH_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u64) -> u64 {
+        (id >> SEQUENCE_BITS) & MAX_NODE_ID
+    }
+
+    #[test]
+    fn ids_increase_past_the_sequence_limit() {
+        let generator = IdGenerator::new(3);
+        let ids: Vec<u64> = (0..3 * (MAX_SEQUENCE + 1))
+            .map(|_| generator.next_id())
+            .collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(ids.iter().all(|&id| node(id) == 3));
+    }
+
+    #[test]
+    fn nodes_never_collide() {
+        let a = IdGenerator::new(0);
+        let b = IdGenerator::new(MAX_NODE_ID);
+        let ids: Vec<(u64, u64)> = (0..1000).map(|_| (a.next_id(), b.next_id())).collect();
+        assert!(ids.iter().all(|(a, b)| a != b));
+        assert!(ids
+            .iter()
+            .all(|(a, b)| node(*a) == 0 && node(*b) == MAX_NODE_ID));
+    }
+
+    #[test]
+    fn ids_do_not_go_back_with_the_clock() {
+        let generator = IdGenerator::new(1);
+        let ahead = now_ms() + 60_000;
+        *generator.state.lock().unwrap() = (ahead, 5);
+        let id = generator.next_id();
+        assert_eq!(id >> (NODE_BITS + SEQUENCE_BITS), ahead - EPOCH_MS);
+        assert_eq!(id & MAX_SEQUENCE, 6);
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_id_is_bounded() {
+        IdGenerator::new(MAX_NODE_ID + 1);
+    }
+}
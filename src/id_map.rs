@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+/// Maps external u64 point ids to the dense `usize` slots used as HNSW data ids.
+///
+/// Every insert gets a fresh slot because hnsw_rs cannot replace or remove a point. A slot is live
+/// only while its external id still maps to it, so slots left behind by re-inserts and deletes are
+/// skipped at search time.
+#[derive(Default)]
+pub struct IdMapper {
+    internal: HashMap<u64, usize>,
+    external: Vec<u64>,
+}
+
+impl IdMapper {
+    /// Allocates a new slot for `external`, superseding any slot it had before.
+    pub fn assign(&mut self, external: u64) -> usize {
+        let slot = self.external.len();
+        self.external.push(external);
+        self.internal.insert(external, slot);
+        slot
+    }
+
+    /// Unmaps `external`, returning the slot it occupied.
+    pub fn remove(&mut self, external: u64) -> Option<usize> {
+        self.internal.remove(&external)
+    }
+
+    /// External id of a live slot.
+    pub fn external(&self, slot: usize) -> Option<u64> {
+        let external = *self.external.get(slot)?;
+        (self.internal.get(&external) == Some(&slot)).then_some(external)
+    }
+
+    /// Number of live ids.
+    pub fn len(&self) -> usize {
+        self.internal.len()
+    }
+
+    /// Number of slots handed out, live or not.
+    pub fn slots(&self) -> usize {
+        self.external.len()
+    }
+}
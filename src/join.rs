@@ -0,0 +1,45 @@
+use crate::{
+    auth::Grants,
+    filter,
+    payload::{self, PayloadSelector},
+    Collection, VectorRecord,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Enriches search hits with the payload of a point in another collection, e.g. a chunk's parent
+/// document: `{"collection": "docs", "field": "doc_id"}`.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Join {
+    pub collection: String,
+    /// Dotted payload key of the hit holding the other point's id, as a number or a numeric
+    /// string.
+    pub field: String,
+    /// Trims the joined payload.
+    #[serde(default)]
+    pub payload_selector: Option<PayloadSelector>,
+}
+
+impl Join {
+    /// The id `payload` refers to, if it has one.
+    pub fn key(&self, payload: &Value) -> Option<u64> {
+        filter::lookup(payload, &self.field).and_then(payload::as_id)
+    }
+
+    /// The payload of point `id` in `target`, or `null` when there is no such point or `grants`
+    /// do not cover its tags. Encrypted fields are opened when the caller may `decrypt` them.
+    pub fn payload(
+        &self,
+        target: &Collection,
+        id: Option<u64>,
+        grants: Option<&Grants>,
+        decrypt: bool,
+    ) -> Value {
+        let visible = |r: &&VectorRecord| grants.is_none_or(|g| g.allows(&r.tags));
+        match id.and_then(|id| target.get(id)).filter(visible) {
+            Some(record) => target.shown(&record.payload, self.payload_selector.as_ref(), decrypt),
+            None => Value::Null,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::filter::Filter;
+
+/// Per-request resource limits. Requests beyond them are rejected with 422 rather than attempted.
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub max_top_k: usize,
+    /// Maximum number of points in one upsert or ids in one delete.
+    pub max_batch_size: usize,
+    /// Largest JSON request body accepted; bigger ones get 413.
+    pub max_body_bytes: usize,
+    /// Most conditions in one filter, counting each value of an `in` condition.
+    pub max_filter_clauses: usize,
+    /// Most segments in a filter key such as `meta.lang`.
+    pub max_filter_depth: usize,
+}
+
+impl Limits {
+    /// Reads `MAX_TOP_K`, `MAX_BATCH_SIZE`, `MAX_BODY_BYTES`, `MAX_FILTER_CLAUSES` and
+    /// `MAX_FILTER_DEPTH`.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            max_top_k: env_usize("MAX_TOP_K", 10_000)?,
+            max_batch_size: env_usize("MAX_BATCH_SIZE", 100_000)?,
+            max_body_bytes: env_usize("MAX_BODY_BYTES", 2 * 1024 * 1024)?,
+            max_filter_clauses: env_usize("MAX_FILTER_CLAUSES", 256)?,
+            max_filter_depth: env_usize("MAX_FILTER_DEPTH", 8)?,
+        })
+    }
+
+    /// Every point of a filtered search or scroll may be checked against the whole filter, so
+    /// its cost grows with the number of conditions.
+    pub fn check_filter(&self, filter: Option<&Filter>) -> Result<(), String> {
+        let Some(filter) = filter else {
+            return Ok(());
+        };
+        if filter.clauses() > self.max_filter_clauses {
+            return Err(format!(
+                "filter has {} clauses, exceeding the maximum of {}",
+                filter.clauses(),
+                self.max_filter_clauses
+            ));
+        }
+        if filter.depth() > self.max_filter_depth {
+            return Err(format!(
+                "filter key nests {} levels deep, exceeding the maximum of {}",
+                filter.depth(),
+                self.max_filter_depth
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_top_k(&self, top_k: usize) -> Result<(), String> {
+        if top_k == 0 {
+            return Err("top_k must be greater than 0".to_string());
+        }
+        if top_k > self.max_top_k {
+            return Err(format!(
+                "top_k {} exceeds the maximum of {}",
+                top_k, self.max_top_k
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_batch(&self, what: &str, len: usize) -> Result<(), String> {
+        if len > self.max_batch_size {
+            return Err(format!(
+                "{} count {} exceeds the maximum batch size of {}",
+                what, len, self.max_batch_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn env_usize(key: &str, default: usize) -> Result<usize, String> {
+    match std::env::var(key) {
+        Ok(v) => v
+            .parse()
+            .map_err(|_| format!("{} must be a non-negative integer, got {:?}", key, v)),
+        Err(_) => Ok(default),
+    }
+}
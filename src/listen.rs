@@ -0,0 +1,106 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+
+/// Addresses the server listens on. Admin routes are served only on `admin` when it is set, and
+/// on the public listener otherwise.
+#[derive(Clone)]
+pub struct Listeners {
+    pub public: SocketAddr,
+    pub admin: Option<SocketAddr>,
+    /// Unix domain socket serving the public API alongside `public`.
+    pub unix: Option<UnixSocket>,
+}
+
+#[derive(Clone)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+    /// Permission bits applied after binding, e.g. `0o660`.
+    pub mode: Option<u32>,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    /// Removes a socket file left behind by a previous run, which would make the bind fail.
+    pub fn remove_stale(&self) -> std::io::Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+
+        match std::fs::symlink_metadata(&self.path) {
+            Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(&self.path),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply_mode(&self) -> std::io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        match self.mode {
+            Some(mode) => {
+                std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(mode))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Listeners {
+    /// Reads `HOST` and `PORT`, `ADMIN_HOST` and `ADMIN_PORT` for the admin listener, and
+    /// `UNIX_SOCKET` and `UNIX_SOCKET_MODE` (octal) for the socket.
+    pub fn from_env() -> Result<Self, String> {
+        let host = env_parse("HOST", IpAddr::from([127, 0, 0, 1]))?;
+        let port = env_parse("PORT", 5202)?;
+        let admin = match std::env::var("ADMIN_PORT") {
+            Ok(_) => Some(SocketAddr::new(
+                env_parse("ADMIN_HOST", IpAddr::from([127, 0, 0, 1]))?,
+                env_parse("ADMIN_PORT", 0)?,
+            )),
+            Err(_) => None,
+        };
+        let unix = match std::env::var("UNIX_SOCKET") {
+            Ok(path) => Some(UnixSocket {
+                path: PathBuf::from(path),
+                mode: match std::env::var("UNIX_SOCKET_MODE") {
+                    Ok(v) => Some(
+                        u32::from_str_radix(&v, 8)
+                            .map_err(|_| format!("invalid UNIX_SOCKET_MODE {:?}", v))?,
+                    ),
+                    Err(_) => None,
+                },
+            }),
+            Err(_) => None,
+        };
+        Ok(Self {
+            public: SocketAddr::new(host, port),
+            admin,
+            unix,
+        })
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> Result<T, String> {
+    match std::env::var(key) {
+        Ok(v) => v.parse().map_err(|_| format!("invalid {} {:?}", key, v)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Answers 404 for admin routes reached through the public listener, so they are not even
+/// discoverable there.
+pub async fn admin_only(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let admin = req.app_data::<web::Data<Listeners>>().and_then(|l| l.admin);
+    if admin.is_some_and(|admin| req.app_config().local_addr() != admin) {
+        let resp = HttpResponse::NotFound().finish();
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
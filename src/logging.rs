@@ -0,0 +1,62 @@
+use crate::request_id;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{io::Write, sync::RwLock};
+
+/// The installed logger, replaced when the configuration is reloaded.
+static LOGGER: Reloadable = Reloadable(RwLock::new(None));
+
+struct Reloadable(RwLock<Option<env_logger::Logger>>);
+
+impl Log for Reloadable {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let logger = self.0.read().unwrap();
+        logger.as_ref().is_some_and(|l| l.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = self.0.read().unwrap().as_ref() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = self.0.read().unwrap().as_ref() {
+            logger.flush();
+        }
+    }
+}
+
+/// Installs the logger, filtering by `RUST_LOG` (default `info`).
+pub fn init() {
+    reload();
+    log::set_logger(&LOGGER).expect("logger installed twice");
+}
+
+/// Reads `RUST_LOG` again and filters by it from now on.
+pub fn reload() -> LevelFilter {
+    let logger = build();
+    let level = logger.filter();
+    *LOGGER.0.write().unwrap() = Some(logger);
+    log::set_max_level(level);
+    level
+}
+
+fn build() -> env_logger::Logger {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .format(|buf, record| {
+            let level = buf.default_level_style(record.level());
+            write!(
+                buf,
+                "[{} {level}{:<5}{level:#} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                record.args()
+            )?;
+            if let Some(id) = request_id::current() {
+                write!(buf, " request_id={}", id)?;
+            }
+            writeln!(buf)
+        })
+        .build()
+}
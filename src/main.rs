@@ -1,106 +1,1311 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+mod api_version;
+mod audit;
+mod auth;
+mod chunks;
+mod cipher;
+mod config;
+mod dedup;
+mod distance;
+mod error;
+mod etag;
+mod experiment;
+mod feedback;
+mod filter;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod id_gen;
+mod id_map;
+mod join;
+mod limits;
+mod listen;
+mod logging;
+mod memory;
+mod network;
+mod ops;
+mod payload;
+mod payload_index;
+mod query_log;
+mod recommend;
+mod request_id;
+mod response;
+mod shadow;
+mod snapshot;
+mod startup;
+mod storage;
+mod template;
+mod throttle;
+mod tls;
+mod token;
+mod usage;
+mod vector_index;
+mod what_if;
+
+use actix_web::{
+    dev::Service,
+    http::header::{self, HeaderValue},
+    middleware::{from_fn, Logger},
+    web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    env,
-    sync::{Arc, Mutex},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use hnsw_rs::prelude::*;
 use dotenvy::dotenv;
+use futures_util::StreamExt;
+use audit::AuditLog;
+use auth::{ApiKeys, Grants};
+use chunks::{ChunkConfig, ChunkIndex, GroupByParent};
+use dedup::{DedupConfig, DedupMode};
+use experiment::Experiment;
+use feedback::{FeedbackEvent, FeedbackLog};
+use filter::Filter;
+use id_gen::IdGenerator;
+use id_map::IdMapper;
+use join::Join;
+use limits::Limits;
+use listen::Listeners;
+use memory::{Degraded, MemoryGuard};
+use network::Allowlist;
+use ops::{OpState, Operation, Operations};
+use payload::PayloadSelector;
+use payload_index::{IndexKind, PayloadIndex};
+use query_log::{QueryEntry, QueryLog};
+use recommend::{Example, Examples, RecommendBody, Strategy};
+use response::{ResponseOptions, Verbosity};
+use shadow::{Shadow, ShadowTarget};
+use snapshot::{Snapshot, Snapshots};
+use storage::{FsyncPolicy, Manifest, Recovery, Storage, Wal, WalEntry};
+use template::Template;
+use throttle::{Rejection, Throttle, ThrottleConfig, ThrottleStatus};
+use tls::{HttpsPolicy, Tls};
+use token::{Claims, Tokens};
+use usage::Usage;
+use vector_index::{IndexType, VectorIndex};
+use what_if::{Outcome, ParameterSet, Samples};
 
 #[derive(Clone, Serialize, Deserialize)]
 struct CollectionConfig {
-    distance: String, // "l2" or "cosine"
+    distance: String, // "l2", "cosine" or "dot"
+    #[serde(default)]
+    index_type: IndexType,
+    /// Unused by `flat` collections.
+    #[serde(default)]
     hnsw: HnswParams,
+    #[serde(default)]
+    dedup: Option<DedupConfig>,
+    /// Payload fields indexed for filtered search, see `PUT /collections/{name}/index`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    indexes: BTreeMap<String, IndexKind>,
+    /// Named searches, see `PUT /collections/{name}/templates/{template}`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    templates: BTreeMap<String, Template>,
+    /// Template A/B tests, see `PUT /collections/{name}/experiments/{experiment}`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    experiments: BTreeMap<String, Experiment>,
+    /// Set by `POST /collections/{name}/freeze`; writes to the points are rejected.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    frozen: bool,
+    /// Makes the points chunks of parent documents, for grouped and in-context search.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chunks: Option<ChunkConfig>,
+    /// Paces writes to the points, see `PUT /collections/{name}/throttle`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    write_throttle: Option<ThrottleConfig>,
+    /// When WAL writes are forced to disk, see `PUT /collections/{name}/fsync`.
+    #[serde(default)]
+    fsync: FsyncPolicy,
+    /// Top-level payload fields stored encrypted, and shown in the clear only to keys granted
+    /// `API_KEY_DECRYPT`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    encrypted_fields: Vec<String>,
+    /// Top-level payload values with JSON over this many bytes are kept in blob files instead of
+    /// memory, and read back only when a payload is returned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blob_threshold_bytes: Option<usize>,
+}
+
+impl CollectionConfig {
+    fn validate(&self, dim: usize) -> Result<(), String> {
+        if !["l2", "cosine", "dot"].contains(&self.distance.as_str()) {
+            return Err(format!(
+                "unknown distance {:?}; expected \"l2\", \"cosine\" or \"dot\"",
+                self.distance
+            ));
+        }
+        if dim == 0 {
+            return Err("dim must be greater than 0".to_string());
+        }
+        self.hnsw.validate()?;
+        for field in self.indexes.keys() {
+            payload_index::validate_field(field)?;
+        }
+        if let Some(chunks) = &self.chunks {
+            chunks.validate()?;
+        }
+        if let Some(throttle) = &self.write_throttle {
+            throttle.validate()?;
+        }
+        self.fsync.validate()?;
+        self.check_encrypted_fields()?;
+        if self.blob_threshold_bytes == Some(0) {
+            return Err("blob_threshold_bytes must be greater than 0".to_string());
+        }
+        for (name, t) in &self.templates {
+            template::validate_name(name)
+                .and_then(|_| template::check(t))
+                .map_err(|e| format!("template {}: {}", name, e))?;
+        }
+        for (name, experiment) in &self.experiments {
+            self.check_experiment(name, experiment)
+                .map_err(|e| format!("experiment {}: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl CollectionConfig {
+    /// Encrypted values are sealed under a random nonce, so no field lookup can see into them.
+    fn check_encrypted_fields(&self) -> Result<(), String> {
+        if self.encrypted_fields.is_empty() {
+            return Ok(());
+        }
+        if cipher::field_key().is_none() {
+            return Err("encrypted_fields need ENCRYPTION_KEY to be set".to_string());
+        }
+        let nested = |f: &String| f.is_empty() || f.contains('.');
+        if let Some(field) = self.encrypted_fields.iter().find(|f| nested(f)) {
+            return Err(format!("encrypted field {:?} is not top-level", field));
+        }
+        let encrypted = |f: &String| payload::within(f, &self.encrypted_fields);
+        match self.looked_up().into_iter().find(|f| encrypted(f)) {
+            Some(field) => Err(format!(
+                "{} is encrypted, so it cannot be indexed, deduplicated on or used for chunks",
+                field
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The payload fields indexes, dedup and chunks read.
+    fn looked_up(&self) -> Vec<&String> {
+        let mut looked_up: Vec<&String> = self.indexes.keys().collect();
+        looked_up.extend(self.dedup.iter().flat_map(|d| &d.fields));
+        if let Some(chunks) = &self.chunks {
+            looked_up.push(&chunks.parent_field);
+            looked_up.extend(&chunks.position_field);
+        }
+        looked_up
+    }
+
+    fn check_experiment(&self, name: &str, experiment: &Experiment) -> Result<(), String> {
+        template::validate_name(name)?;
+        experiment.validate()?;
+        match experiment
+            .buckets
+            .iter()
+            .find(|b| !self.templates.contains_key(&b.template))
+        {
+            Some(b) => Err(format!(
+                "bucket {} uses unknown template {:?}",
+                b.name, b.template
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+fn validate_collection_name(name: &str) -> Result<(), String> {
+    let valid_chars = name
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    if name.is_empty() || name.len() > 64 || !valid_chars {
+        return Err(format!(
+            "invalid collection name {:?}; use 1-64 ASCII letters, digits, '_' or '-'",
+            name
+        ));
+    }
+    Ok(())
 }
 
+/// Parameters a collection leaves out are taken from the server-wide defaults.
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 struct HnswParams {
     max_nb_connection: usize,
     ef_search: usize,
     max_elements: usize,
+    /// Candidates considered for each point's links while building the graph.
+    ef_construction: usize,
+    /// Layers the graph may grow, at most 16.
+    max_layer: usize,
+    /// Also consider the candidates' neighbours when linking a point, for better graphs on
+    /// clustered data at the cost of slower inserts.
+    extend_candidates: bool,
+    /// Fill up a point's links with pruned candidates when too few survive pruning.
+    keep_pruned: bool,
+}
+
+/// Defaults when the environment sets none.
+const BUILTIN_HNSW: HnswParams = HnswParams {
+    max_nb_connection: 16,
+    ef_search: 64,
+    max_elements: 10_000,
+    ef_construction: 200,
+    max_layer: 16,
+    extend_candidates: false,
+    keep_pruned: false,
+};
+
+/// Set at startup from the environment, and again when the configuration is reloaded.
+static HNSW_DEFAULTS: RwLock<HnswParams> = RwLock::new(BUILTIN_HNSW);
+
+impl HnswParams {
+    /// Reads the server-wide defaults from `HNSW_MAX_NB_CONNECTION`, `HNSW_EF_SEARCH`,
+    /// `HNSW_MAX_ELEMENTS`, `HNSW_EF_CONSTRUCTION`, `HNSW_MAX_LAYER`,
+    /// `HNSW_EXTEND_CANDIDATES` and `HNSW_KEEP_PRUNED`.
+    fn defaults_from_env() -> Result<Self, String> {
+        fn var<T: std::str::FromStr>(key: &str, default: T) -> Result<T, String> {
+            match std::env::var(key) {
+                Ok(v) => v.parse().map_err(|_| format!("invalid {} {:?}", key, v)),
+                Err(_) => Ok(default),
+            }
+        }
+        let builtin = BUILTIN_HNSW;
+        Ok(Self {
+            max_nb_connection: var("HNSW_MAX_NB_CONNECTION", builtin.max_nb_connection)?,
+            ef_search: var("HNSW_EF_SEARCH", builtin.ef_search)?,
+            max_elements: var("HNSW_MAX_ELEMENTS", builtin.max_elements)?,
+            ef_construction: var("HNSW_EF_CONSTRUCTION", builtin.ef_construction)?,
+            max_layer: var("HNSW_MAX_LAYER", builtin.max_layer)?,
+            extend_candidates: var("HNSW_EXTEND_CANDIDATES", builtin.extend_candidates)?,
+            keep_pruned: var("HNSW_KEEP_PRUNED", builtin.keep_pruned)?,
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        // hnsw_rs exits the process on more than 256 connections.
+        if !(1..=256).contains(&self.max_nb_connection) {
+            return Err("hnsw.max_nb_connection must be between 1 and 256".to_string());
+        }
+        if self.ef_search == 0 {
+            return Err("hnsw.ef_search must be greater than 0".to_string());
+        }
+        if self.max_elements == 0 {
+            return Err("hnsw.max_elements must be greater than 0".to_string());
+        }
+        if self.ef_construction == 0 {
+            return Err("hnsw.ef_construction must be greater than 0".to_string());
+        }
+        // hnsw_rs silently caps the layers at 16.
+        if !(1..=16).contains(&self.max_layer) {
+            return Err("hnsw.max_layer must be between 1 and 16".to_string());
+        }
+        Ok(())
+    }
 }
 
+impl Default for HnswParams {
+    fn default() -> Self {
+        HNSW_DEFAULTS.read().unwrap().clone()
+    }
+}
+
+/// A point's access tags.
+type Tags = Vec<String>;
+
 #[derive(Clone, Serialize, Deserialize)]
 struct VectorRecord {
     id: u64,
     vector: Vec<f32>,
     payload: serde_json::Value,
+    /// Access tags, kept apart from the payload; see `auth::Grants`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Tags,
+}
+
+/// The points a read may return: those passing its payload `filter` and, for a key with tag
+/// grants, carrying a granted tag.
+#[derive(Clone, Copy, Default)]
+struct Visible<'r> {
+    filter: Option<&'r Filter>,
+    grants: Option<&'r Grants>,
+}
+
+impl<'r> Visible<'r> {
+    fn new(filter: Option<&'r Filter>, grants: Option<&'r Grants>) -> Self {
+        Self { filter, grants }
+    }
+
+    fn allows(&self, record: &VectorRecord) -> bool {
+        self.grants.is_none_or(|g| g.allows(&record.tags))
+            && self.filter.is_none_or(|f| f.matches(&record.payload))
+    }
+
+    /// Whether `record` may be read at all, whatever the filter.
+    fn may_read(&self, record: &VectorRecord) -> bool {
+        self.grants.is_none_or(|g| g.allows(&record.tags))
+    }
+
+    /// Whether `id` may be written: it is free or holds a point that may be read.
+    fn may_write(&self, coll: &Collection, id: u64) -> bool {
+        coll.get(id).is_none_or(|r| self.may_read(r))
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct GrowthEvent {
+    ts_ms: u64,
+    from: usize,
+    to: usize,
+    points: usize,
+}
+
+/// Builds a graph holding `records`, calling `progress` with the number of points inserted so far
+/// before each one. Returns `None` as soon as `progress` returns false.
+fn build_index<'a>(
+    config: &CollectionConfig,
+    records: &[VectorRecord],
+    mut progress: impl FnMut(usize) -> bool,
+) -> Option<(VectorIndex<'a>, IdMapper)> {
+    let index = VectorIndex::new(config);
+    let mut ids = IdMapper::default();
+    for (n, record) in records.iter().enumerate() {
+        if !progress(n) {
+            return None;
+        }
+        index.insert(&record.vector, ids.assign(record.id));
+    }
+    progress(records.len());
+    Some((index, ids))
+}
+
+/// Serialized size of a payload, as counted in `Collection::payload_bytes`.
+fn payload_size(payload: &serde_json::Value) -> usize {
+    serde_json::to_vec(payload).map_or(0, |json| json.len())
+}
+
+/// Moves the top-level values of `payload` over `config.blob_threshold_bytes` to the blob files
+/// of `wal`. What indexes, dedup and chunks read stays, and so does a value that cannot be
+/// written, with a warning.
+fn offload_blobs(config: &CollectionConfig, wal: Option<&Wal>, payload: &mut serde_json::Value) {
+    let (Some(threshold), Some(wal)) = (config.blob_threshold_bytes, wal) else {
+        return;
+    };
+    let looked_up = config.looked_up();
+    let keep = |k: &str| looked_up.iter().any(|f| f.split('.').next() == Some(k));
+    let put = |json: &[u8]| wal.blobs().put(json);
+    if let Err(e) = payload::offload(payload, threshold, keep, put) {
+        log::warn!("could not move a payload value to a blob file: {}", e);
+    }
+}
+
+/// Reads the values of the top-level fields `wanted` picks back in from the blob files of `wal`.
+/// A value that cannot be read stays a reference, with an error logged.
+fn fetch_blobs(wal: Option<&Wal>, payload: &mut serde_json::Value, wanted: impl Fn(&str) -> bool) {
+    let Some(wal) = wal else {
+        return;
+    };
+    if let Err(e) = payload::inline(payload, wanted, |id| wal.blobs().get(id)) {
+        log::error!("could not read a payload value from its blob file: {}", e);
+    }
+}
+
+#[derive(Serialize)]
+struct PointResult {
+    id: u64,
+    status: &'static str, // "created", "updated", "deduplicated" or "failed"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl PointResult {
+    fn ok(id: u64, status: &'static str) -> Self {
+        Self {
+            id,
+            status,
+            reason: None,
+        }
+    }
+
+    fn failed(id: u64, reason: String) -> Self {
+        Self {
+            id,
+            status: "failed",
+            reason: Some(reason),
+        }
+    }
+}
+
+/// Collection revisions come from one counter, so a collection re-created under the same name
+/// never reuses a revision of the one it replaced.
+fn next_revision() -> u64 {
+    static REVISION: AtomicU64 = AtomicU64::new(0);
+    REVISION.fetch_add(1, Ordering::Relaxed)
 }
 
+/// Fewest superseded entries worth a checkpoint; below it replay is fast anyway.
+const WAL_CHECKPOINT_MIN_STALE: usize = 1000;
+
+/// Filtered searches whose indexed conditions leave at most this many points score them all
+/// instead of walking the graph.
+const EXACT_SEARCH_MAX_CANDIDATES: usize = 4096;
+
 struct Collection<'a> {
     config: CollectionConfig,
+    dim: usize,
     records: Vec<VectorRecord>,
-    hnsw_l2: Option<Arc<Hnsw<'a, f32, DistL2>>>,
-    hnsw_cosine: Option<Arc<Hnsw<'a, f32, DistCosine>>>,
+    // Id -> index into `records`, which holds exactly one record per live id.
+    positions: HashMap<u64, usize>,
+    // Graph slots; deleted and superseded points stay in the graph and are filtered at search time.
+    ids: IdMapper,
+    // Content hash -> id and id -> content hash, maintained only when dedup is configured.
+    content_hashes: HashMap<u64, u64>,
+    hash_of: HashMap<u64, u64>,
+    /// Serialized size of all payloads in `records`, kept in step for `memory_estimate`.
+    payload_bytes: usize,
+    /// Changes on every write to the points, graph or config; versions the collection status.
+    revision: u64,
+    /// Value -> ids for the fields in `config.indexes`, kept in step with `records`.
+    payload_index: PayloadIndex,
+    /// Parent -> chunks when `config.chunks` is set, kept in step with `records`.
+    chunk_index: ChunkIndex,
+    growth_events: Vec<GrowthEvent>,
+    /// Pacing of the writes `config.write_throttle` allows.
+    throttle: Throttle,
+    /// Mirror of all writes, attached for the duration of a migration.
+    shadow: Option<Shadow>,
+    /// Present when `STORAGE_DIR` is set; writes queue in `unlogged` until `persist`.
+    wal: Option<Wal>,
+    unlogged: Vec<WalEntry>,
+    index: VectorIndex<'a>,
 }
 
 impl<'a> Collection<'a> {
     fn new(config: CollectionConfig, dim: usize) -> Self {
-        let hnsw_l2 = if config.distance == "l2" {
-            Some(Arc::new(Hnsw::new(
-                config.hnsw.max_nb_connection,
-                16,                // efConstruction
-                dim,               // vector dimension
-                config.hnsw.max_elements,
-                DistL2 {},
-            )))
+        let index = VectorIndex::new(&config);
+        let payload_index = PayloadIndex::new(&config.indexes);
+        let chunk_index = ChunkIndex::new(config.chunks.as_ref());
+        Self {
+            config,
+            dim,
+            records: Vec::new(),
+            positions: HashMap::new(),
+            ids: IdMapper::default(),
+            content_hashes: HashMap::new(),
+            hash_of: HashMap::new(),
+            payload_bytes: 0,
+            revision: next_revision(),
+            payload_index,
+            chunk_index,
+            growth_events: Vec::new(),
+            throttle: Throttle::default(),
+            shadow: None,
+            wal: None,
+            unlogged: Vec::new(),
+            index,
+        }
+    }
+
+    /// Rebuilds a collection from stored records, e.g. replayed out of its WAL or a snapshot.
+    fn restore(
+        config: CollectionConfig,
+        dim: usize,
+        records: Vec<VectorRecord>,
+    ) -> Result<Self, String> {
+        Self::restore_with_progress(config, dim, records, |_| {})
+    }
+
+    /// [`restore`](Self::restore), calling `progress` with the number of points indexed so far.
+    fn restore_with_progress(
+        config: CollectionConfig,
+        dim: usize,
+        records: Vec<VectorRecord>,
+        progress: impl FnMut(usize),
+    ) -> Result<Self, String> {
+        let mut coll = Collection::new(config, dim);
+        for r in &records {
+            coll.check_vector(&r.vector)
+                .map_err(|e| format!("point {}: {}", r.id, e))?;
+        }
+        for r in records {
+            coll.put_record(r);
+        }
+        let needed = coll.records.len();
+        if needed > coll.config.hnsw.max_elements && !coll.index.is_flat() {
+            coll.ensure_capacity_with(needed, progress);
         } else {
-            None
+            coll.rebuild_with(progress);
+        }
+        if let Some(dedup) = coll.config.dedup.as_ref().filter(|_| !coll.config.frozen) {
+            for r in &coll.records {
+                let hash = dedup::content_hash(&r.vector, &r.payload, &dedup.fields);
+                coll.content_hashes.insert(hash, r.id);
+                coll.hash_of.insert(r.id, hash);
+            }
+        }
+        Ok(coll)
+    }
+
+    /// Starts logging to `wal`, first writing out the points the collection already holds.
+    /// Values over the blob threshold, e.g. restored from a snapshot, move to its blob files.
+    fn attach_wal(&mut self, wal: Wal) -> Result<(), String> {
+        for r in &mut self.records {
+            offload_blobs(&self.config, Some(&wal), &mut r.payload);
+        }
+        self.payload_bytes = self.records.iter().map(|r| payload_size(&r.payload)).sum();
+        self.unlogged = self.records.iter().cloned().map(WalEntry::Upsert).collect();
+        self.wal = Some(wal);
+        self.persist()
+    }
+
+    /// Appends the writes made since the last call to the WAL, if there is one, and syncs it
+    /// when the fsync policy is `always`.
+    fn persist(&mut self) -> Result<(), String> {
+        let entries = std::mem::take(&mut self.unlogged);
+        match &mut self.wal {
+            Some(wal) if !entries.is_empty() => {
+                wal.append(&entries)?;
+                match self.config.fsync {
+                    FsyncPolicy::Always => wal.sync(),
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Rewrites the WAL to just the live points once it holds at least as many superseded entries
+    /// as live ones, and at least `WAL_CHECKPOINT_MIN_STALE`. Returns the entries dropped, if it
+    /// did.
+    fn checkpoint(&mut self) -> Result<Option<usize>, String> {
+        self.persist()?;
+        let Some(wal) = &self.wal else {
+            return Ok(None);
         };
+        let stale = wal.entries().saturating_sub(self.records.len());
+        if stale < self.records.len().max(WAL_CHECKPOINT_MIN_STALE) {
+            return Ok(None);
+        }
+        self.rewrite_wal()?;
+        Ok(Some(stale))
+    }
 
-        let hnsw_cosine = if config.distance == "cosine" {
-            Some(Arc::new(Hnsw::new(
-                config.hnsw.max_nb_connection,
-                16,                // efConstruction
-                dim,               // vector dimension
-                config.hnsw.max_elements,
-                DistCosine {},
-            )))
-        } else {
-            None
+    /// Writes out anything not yet logged and forces the WAL to disk.
+    fn flush(&mut self) -> Result<(), String> {
+        self.persist()?;
+        match &mut self.wal {
+            Some(wal) => wal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Under the `interval` fsync policy, how long until the WAL must next be synced: zero when
+    /// a write has waited the whole interval, the interval itself when none waits.
+    fn fsync_wait(&self) -> Option<Duration> {
+        let FsyncPolicy::Interval { interval_ms } = self.config.fsync else {
+            return None;
         };
+        let interval = Duration::from_millis(interval_ms);
+        let wal = self.wal.as_ref()?;
+        Some(match wal.unsynced_since() {
+            Some(since) => interval.saturating_sub(since.elapsed()),
+            None => interval,
+        })
+    }
 
-        Self {
-            config,
-            records: Vec::new(),
-            hnsw_l2,
-            hnsw_cosine,
+    /// Makes room for `incoming` more points: rebuilds to reclaim dead slots when that is enough,
+    /// and otherwise doubles the graph capacity. Without a graph there is no capacity, and dead
+    /// slots are reclaimed once they outnumber the live ones.
+    fn ensure_capacity(&mut self, incoming: usize) {
+        self.ensure_capacity_with(incoming, |_| {});
+    }
+
+    /// [`ensure_capacity`](Self::ensure_capacity), reporting the progress of any rebuild.
+    fn ensure_capacity_with(&mut self, incoming: usize, progress: impl FnMut(usize)) {
+        if self.index.is_flat() {
+            if self.ids.slots() - self.ids.len() > self.ids.len() {
+                self.rebuild_with(progress);
+            }
+            return;
+        }
+        let capacity = self.config.hnsw.max_elements;
+        if self.ids.slots() + incoming <= capacity {
+            return;
         }
+        if self.ids.len() + incoming <= capacity {
+            self.rebuild_with(progress);
+            return;
+        }
+        let grown = (capacity * 2).max(self.ids.len() + incoming);
+        log::info!("growing collection capacity from {} to {}", capacity, grown);
+        self.config.hnsw.max_elements = grown;
+        self.rebuild_with(progress);
+        self.growth_events.push(GrowthEvent {
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            from: capacity,
+            to: grown,
+            points: self.ids.len(),
+        });
+    }
+
+    /// Re-creates the graph from the stored records, dropping superseded and deleted slots.
+    fn rebuild(&mut self) {
+        self.rebuild_with(|_| {});
+    }
+
+    /// [`rebuild`](Self::rebuild), calling `progress` with the number of points indexed so far.
+    fn rebuild_with(&mut self, mut progress: impl FnMut(usize)) {
+        let built = build_index(&self.config, &self.records, |n| {
+            progress(n);
+            true
+        });
+        let (index, ids) = built.expect("not cancelled");
+        self.ids = ids;
+        self.index = index;
+        self.revision = next_revision();
+    }
+
+    /// Rebuilds the graph without dead slots and rewrites the WAL to just the live points.
+    /// Returns the number of slots reclaimed.
+    fn reindex(&mut self) -> Result<usize, String> {
+        let dead = self.ids.slots() - self.ids.len();
+        self.rebuild();
+        self.rewrite_wal()?;
+        Ok(dead)
+    }
+
+    /// Compacts the collection for serving reads only: rebuilds the graph sized to exactly the
+    /// live points, rewrites the WAL and drops the state only writes use. Returns the number of
+    /// slots reclaimed.
+    fn freeze(&mut self) -> Result<usize, String> {
+        let dead = self.ids.slots() - self.ids.len();
+        self.config.frozen = true;
+        self.config.hnsw.max_elements = self.records.len().max(1);
+        self.rebuild();
+        self.records.shrink_to_fit();
+        self.positions.shrink_to_fit();
+        self.content_hashes = HashMap::new();
+        self.hash_of = HashMap::new();
+        self.rewrite_wal()?;
+        Ok(dead)
+    }
+
+    fn check_writable(&self) -> Result<(), String> {
+        if self.config.frozen {
+            return Err("collection is frozen".to_string());
+        }
+        Ok(())
+    }
+
+    fn rewrite_wal(&mut self) -> Result<(), String> {
+        if let Some(wal) = &mut self.wal {
+            let entries: Vec<WalEntry> =
+                self.records.iter().cloned().map(WalEntry::Upsert).collect();
+            wal.rewrite(&entries)?;
+        }
+        Ok(())
+    }
+
+    /// Swaps in an index built in the background from `built_from`, then catches it up with the
+    /// writes made while it was being built.
+    fn install(&mut self, built: (VectorIndex<'a>, IdMapper), built_from: &[VectorRecord]) {
+        let (index, mut ids) = built;
+        let built: HashMap<u64, &[f32]> = built_from
+            .iter()
+            .map(|r| (r.id, r.vector.as_slice()))
+            .collect();
+        for id in built.keys() {
+            if !self.positions.contains_key(id) {
+                ids.remove(*id);
+            }
+        }
+        for r in &self.records {
+            if built.get(&r.id) != Some(&r.vector.as_slice()) {
+                index.insert(&r.vector, ids.assign(r.id));
+            }
+        }
+        self.ids = ids;
+        self.index = index;
+        self.revision = next_revision();
+        self.ensure_capacity(0);
+    }
+
+    /// Stores `record`, replacing the one with the same id. Returns whether there was one.
+    fn put_record(&mut self, record: VectorRecord) -> bool {
+        if let Some(&i) = self.positions.get(&record.id) {
+            let old = &self.records[i];
+            self.payload_index.remove(old.id, &old.payload);
+            self.chunk_index.remove(old.id, &old.payload);
+            self.payload_bytes -= payload_size(&old.payload);
+        }
+        self.payload_index.insert(record.id, &record.payload);
+        self.chunk_index.insert(record.id, &record.payload);
+        self.payload_bytes += payload_size(&record.payload);
+        self.revision = next_revision();
+        match self.positions.get(&record.id) {
+            Some(&i) => {
+                self.records[i] = record;
+                true
+            }
+            None => {
+                self.positions.insert(record.id, self.records.len());
+                self.records.push(record);
+                false
+            }
+        }
+    }
+
+    fn remove_record(&mut self, id: u64) -> Option<VectorRecord> {
+        let i = self.positions.remove(&id)?;
+        let record = self.records.swap_remove(i);
+        self.payload_index.remove(id, &record.payload);
+        self.chunk_index.remove(id, &record.payload);
+        self.payload_bytes -= payload_size(&record.payload);
+        self.revision = next_revision();
+        if let Some(moved) = self.records.get(i) {
+            self.positions.insert(moved.id, i);
+        }
+        Some(record)
+    }
+
+    /// Indexes payload `field` as `kind`, replacing any index already declared on it.
+    fn create_index(&mut self, field: &str, kind: IndexKind) {
+        self.config.indexes.insert(field.to_string(), kind);
+        self.payload_index.declare(field, kind);
+        for r in &self.records {
+            self.payload_index.insert(r.id, &r.payload);
+        }
+        self.revision = next_revision();
+    }
+
+    /// Up to `limit` `visible` points with ids from `offset_id` on, in id order; plus the id to
+    /// continue from when more remain.
+    fn scroll(
+        &self,
+        offset_id: u64,
+        limit: usize,
+        visible: Visible,
+    ) -> (Vec<&VectorRecord>, Option<u64>) {
+        let candidates = visible
+            .filter
+            .and_then(|f| self.payload_index.candidates(f));
+        let mut page: Vec<&VectorRecord> = self
+            .records
+            .iter()
+            .filter(|r| r.id >= offset_id)
+            .filter(|r| candidates.as_ref().is_none_or(|c| c.contains(&r.id)))
+            .filter(|r| visible.allows(r))
+            .collect();
+        page.sort_unstable_by_key(|r| r.id);
+        let next = page.get(limit).map(|r| r.id);
+        page.truncate(limit);
+        (page, next)
+    }
+
+    /// Copies the current points into a new collection built with `config`.
+    fn clone_with(&self, config: CollectionConfig) -> Collection<'a> {
+        let mut clone = Collection::new(config, self.dim);
+        let ids = self.records.iter().map(|r| r.id).collect();
+        let vectors = self.records.iter().map(|r| r.vector.clone()).collect();
+        let inlined = |r: &VectorRecord| self.inlined(&r.payload);
+        let payloads = self.records.iter().map(inlined).collect();
+        let tags = self.records.iter().map(|r| r.tags.clone()).collect();
+        clone.upsert(ids, vectors, payloads, tags, Visible::default());
+        clone
     }
 
-    fn upsert(&mut self, ids: Vec<u64>, vectors: Vec<Vec<f32>>, payloads: Vec<serde_json::Value>) {
+    /// Inserts the points, reporting per input point whether it was created, updated,
+    /// deduplicated against a stored point (whose id is reported) or rejected. Points the writer
+    /// cannot see are neither replaced nor deduplicated against.
+    fn upsert(
+        &mut self,
+        ids: Vec<u64>,
+        vectors: Vec<Vec<f32>>,
+        payloads: Vec<serde_json::Value>,
+        tags: Vec<Tags>,
+        visible: Visible,
+    ) -> Vec<PointResult> {
+        self.ensure_capacity(ids.len());
+        let mut payloads = self.seal(payloads);
+        for payload in &mut payloads {
+            offload_blobs(&self.config, self.wal.as_ref(), payload);
+        }
+        let mut results = Vec::with_capacity(ids.len());
         for (i, id) in ids.iter().enumerate() {
+            if let Err(reason) = self.check_vector(&vectors[i]) {
+                results.push(PointResult::failed(*id, reason));
+                continue;
+            }
+            if !visible.may_write(self, *id) {
+                let reason = "id is taken by a point the API key cannot see".to_string();
+                results.push(PointResult::failed(*id, reason));
+                continue;
+            }
+            if let Some(dedup) = &self.config.dedup {
+                let hash = dedup::content_hash(&vectors[i], &payloads[i], &dedup.fields);
+                let duplicate = self.content_hashes.get(&hash).copied();
+                if let Some(existing) = duplicate.filter(|&e| visible.may_write(self, e)) {
+                    if dedup.mode == DedupMode::Merge {
+                        let record = self.positions.get(&existing).map(|&i| &mut self.records[i]);
+                        if let Some(record) = record {
+                            self.payload_index.remove(existing, &record.payload);
+                            self.chunk_index.remove(existing, &record.payload);
+                            self.payload_bytes -= payload_size(&record.payload);
+                            dedup::merge_payload(&mut record.payload, &payloads[i]);
+                            self.payload_index.insert(existing, &record.payload);
+                            self.chunk_index.insert(existing, &record.payload);
+                            self.payload_bytes += payload_size(&record.payload);
+                            self.revision = next_revision();
+                            if self.wal.is_some() {
+                                self.unlogged.push(WalEntry::Upsert(record.clone()));
+                            }
+                        }
+                    }
+                    results.push(PointResult::ok(existing, "deduplicated"));
+                    continue;
+                }
+                self.forget_hash(*id);
+                self.content_hashes.insert(hash, *id);
+                self.hash_of.insert(*id, hash);
+            }
             let record = VectorRecord {
                 id: *id,
                 vector: vectors[i].clone(),
                 payload: payloads[i].clone(),
+                tags: tags[i].clone(),
+            };
+            let slot = self.ids.assign(*id);
+            self.index.insert(&vectors[i], slot);
+            if self.wal.is_some() {
+                self.unlogged.push(WalEntry::Upsert(record.clone()));
+            }
+            let existed = self.put_record(record);
+            results.push(PointResult::ok(*id, if existed { "updated" } else { "created" }));
+        }
+        results
+    }
+
+    /// Seals the encrypted fields of `payloads` before they are stored.
+    fn seal(&self, mut payloads: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+        if let Some(cipher) = cipher::field_key() {
+            for payload in &mut payloads {
+                payload::seal_fields(payload, &self.config.encrypted_fields, cipher);
+            }
+        }
+        payloads
+    }
+
+    /// `payload` as a caller sees it: narrowed by `selector`, then with the blob values it kept
+    /// read back in and the encrypted fields opened when the caller may `decrypt` them.
+    fn shown(
+        &self,
+        payload: &serde_json::Value,
+        selector: Option<&PayloadSelector>,
+        decrypt: bool,
+    ) -> serde_json::Value {
+        let mut shown = match selector {
+            Some(selector) => selector.apply(payload),
+            None => payload.clone(),
+        };
+        fetch_blobs(self.wal.as_ref(), &mut shown, |_| true);
+        if let Some(cipher) = cipher::field_key().filter(|_| decrypt) {
+            payload::open_fields(&mut shown, &self.config.encrypted_fields, cipher);
+        }
+        shown
+    }
+
+    /// `payload` with its blob values read back in, for copies made outside the collection.
+    fn inlined(&self, payload: &serde_json::Value) -> serde_json::Value {
+        let mut payload = payload.clone();
+        fetch_blobs(self.wal.as_ref(), &mut payload, |_| true);
+        payload
+    }
+
+    /// The points with their blob values read back in, e.g. for a snapshot.
+    fn exported(&self) -> Vec<VectorRecord> {
+        let export = |r: &VectorRecord| VectorRecord {
+            payload: self.inlined(&r.payload),
+            ..r.clone()
+        };
+        self.records.iter().map(export).collect()
+    }
+
+    /// Whether some point keeps the top-level field of dotted `field` in a blob file.
+    fn offloaded(&self, field: &str) -> bool {
+        let top = field.split('.').next().unwrap_or_default();
+        let blob = |r: &VectorRecord| r.payload.get(top).and_then(payload::blob_ref).is_some();
+        self.records.iter().any(blob)
+    }
+
+    /// Replaces the vectors of existing `visible` points, keeping their payloads. Other ids fail.
+    fn update_vectors(
+        &mut self,
+        ids: Vec<u64>,
+        vectors: Vec<Vec<f32>>,
+        visible: Visible,
+    ) -> Vec<PointResult> {
+        let exists: Vec<bool> = ids
+            .iter()
+            .map(|&id| self.get(id).is_some_and(|r| visible.may_read(r)))
+            .collect();
+        let (mut found_ids, mut found_vectors, mut payloads) = (Vec::new(), Vec::new(), Vec::new());
+        let mut tags = Vec::new();
+        for ((id, vector), exists) in ids.iter().zip(vectors).zip(&exists) {
+            if *exists {
+                found_ids.push(*id);
+                found_vectors.push(vector);
+                let record = self.get(*id);
+                payloads.push(record.map(|r| r.payload.clone()).unwrap_or_default());
+                tags.push(record.map(|r| r.tags.clone()).unwrap_or_default());
+            }
+        }
+        let mut updated = self
+            .upsert(found_ids, found_vectors, payloads, tags, visible)
+            .into_iter();
+        ids.iter()
+            .zip(exists)
+            .map(|(id, exists)| {
+                if exists {
+                    updated.next().expect("one result per existing id")
+                } else {
+                    PointResult::failed(*id, "point not found".to_string())
+                }
+            })
+            .collect()
+    }
+
+    /// Sets payload `field` of the points in `values`, leaving their vectors and the graph alone.
+    /// Returns the ids updated; unknown ids and points that are not `visible` are skipped.
+    fn set_payload_field(
+        &mut self,
+        field: &str,
+        values: Vec<(u64, serde_json::Value)>,
+        visible: Visible,
+    ) -> Vec<u64> {
+        let mut updated = Vec::new();
+        for (id, value) in values {
+            let Some(&i) = self.positions.get(&id) else {
+                continue;
             };
-            if let Some(hnsw) = &self.hnsw_l2 {
-                hnsw.insert((vectors[i].as_slice(), *id as usize));
+            if !visible.may_read(&self.records[i]) {
+                continue;
+            }
+            let record = &mut self.records[i];
+            self.payload_index.remove(id, &record.payload);
+            self.chunk_index.remove(id, &record.payload);
+            self.payload_bytes -= payload_size(&record.payload);
+            let top = field.split('.').next().unwrap_or_default();
+            fetch_blobs(self.wal.as_ref(), &mut record.payload, |k| k == top);
+            payload::set_path(&mut record.payload, field, value);
+            if let Some(cipher) = cipher::field_key() {
+                payload::seal_fields(&mut record.payload, &self.config.encrypted_fields, cipher);
+            }
+            offload_blobs(&self.config, self.wal.as_ref(), &mut record.payload);
+            self.payload_index.insert(id, &record.payload);
+            self.chunk_index.insert(id, &record.payload);
+            self.payload_bytes += payload_size(&record.payload);
+            self.revision = next_revision();
+            if self.wal.is_some() {
+                self.unlogged.push(WalEntry::Upsert(record.clone()));
+            }
+            if let Some(dedup) = &self.config.dedup {
+                let hash = dedup::content_hash(&record.vector, &record.payload, &dedup.fields);
+                self.forget_hash(id);
+                self.content_hashes.entry(hash).or_insert(id);
+                self.hash_of.insert(id, hash);
+            }
+            updated.push(id);
+        }
+        updated
+    }
+
+    fn check_vector(&self, vector: &[f32]) -> Result<(), String> {
+        if vector.len() != self.dim {
+            return Err(format!(
+                "vector has dimension {}, collection expects {}",
+                vector.len(),
+                self.dim
+            ));
+        }
+        if vector.iter().any(|x| !x.is_finite()) {
+            return Err("vector contains NaN or infinite values".to_string());
+        }
+        if self.config.distance == "dot" && distance::norm(vector) > distance::MAX_DOT_NORM {
+            return Err(format!(
+                "vector has norm {}; dot distance needs vectors normalized to at most 1",
+                distance::norm(vector)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Number of live points.
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Rough heap usage in bytes: the records with their payloads' JSON size, the graph's copy
+    /// of every slot's vector and its layer-0 neighbour lists, and the id maps. Runs in constant
+    /// time, since the payload size is kept up to date by the writes.
+    fn memory_estimate(&self) -> usize {
+        let vector = self.dim * std::mem::size_of::<f32>();
+        let records = self.records.len() * (std::mem::size_of::<VectorRecord>() + vector)
+            + self.payload_bytes;
+        // hnsw_rs keeps up to 2 * max_nb_connection neighbours on layer 0, about 16 bytes each.
+        let graph = if self.index.is_flat() {
+            0
+        } else {
+            self.ids.slots() * (vector + 2 * self.config.hnsw.max_nb_connection * 16)
+        };
+        let maps = (self.positions.len() + 2 * self.ids.slots()) * 2 * std::mem::size_of::<u64>();
+        records + graph + maps
+    }
+
+    fn forget_hash(&mut self, id: u64) {
+        if let Some(hash) = self.hash_of.remove(&id) {
+            if self.content_hashes.get(&hash) == Some(&id) {
+                self.content_hashes.remove(&hash);
+            }
+        }
+    }
+
+    fn get(&self, id: u64) -> Option<&VectorRecord> {
+        self.positions.get(&id).map(|&i| &self.records[i])
+    }
+
+    /// Removes the given ids, returning for each whether it existed. Points that are not
+    /// `visible` are left alone, as if they did not exist.
+    fn delete(&mut self, ids: &[u64], visible: Visible) -> Vec<bool> {
+        let mut found = HashSet::new();
+        for id in ids {
+            if !visible.may_write(self, *id) || self.remove_record(*id).is_none() {
+                continue;
+            }
+            found.insert(*id);
+            self.forget_hash(*id);
+            self.ids.remove(*id);
+            if self.wal.is_some() {
+                self.unlogged.push(WalEntry::Delete { delete: *id });
+            }
+        }
+        ids.iter().map(|id| found.contains(id)).collect()
+    }
+
+    /// Nearest `visible` live points, walking the graph at `ef_search`. The filter is applied
+    /// while walking the graph, so selective filters do not cut the result short. When indexed
+    /// fields narrow the filter down to a few points, or the collection is `flat`, those are
+    /// scored directly instead.
+    fn search_ef(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        visible: Visible,
+        ef_search: usize,
+    ) -> Vec<(u64, f32)> {
+        let candidates = visible
+            .filter
+            .and_then(|f| self.payload_index.candidates(f));
+        if let Some(candidates) = &candidates {
+            if candidates.len() <= EXACT_SEARCH_MAX_CANDIDATES || self.index.is_flat() {
+                let records = candidates.iter().filter_map(|&id| self.get(id));
+                return self.exact_search(query, top_k, records, visible);
+            }
+        }
+        if self.index.is_flat() {
+            return self.exact_search(query, top_k, self.records.iter(), visible);
+        }
+        let unrestricted = visible.filter.is_none() && visible.grants.is_none();
+        let live = |slot: &usize| match self.ids.external(*slot) {
+            Some(id) => {
+                candidates.as_ref().is_none_or(|c| c.contains(&id))
+                    && (unrestricted || self.get(id).is_some_and(|r| visible.allows(r)))
             }
-            if let Some(hnsw) = &self.hnsw_cosine {
-                hnsw.insert((vectors[i].as_slice(), *id as usize));
+            None => false,
+        };
+        self.index
+            .search(query, top_k, ef_search, &live)
+            .into_iter()
+            .filter_map(|n| self.ids.external(n.d_id).map(|id| (id, n.distance)))
+            .collect()
+    }
+
+    /// `search_ef` at the collection's `ef_search` unless `params` overrides it, or with
+    /// `params.exact`, every stored point scored for exact results. Hits whose `score` is below
+    /// `score_threshold` are dropped.
+    fn search_with(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        visible: Visible,
+        params: &SearchParams,
+        score_threshold: Option<f32>,
+    ) -> Vec<(u64, f32)> {
+        let mut results = if params.exact {
+            self.exact_search(query, top_k, self.records.iter(), visible)
+        } else {
+            let ef_search = params.ef_search.unwrap_or(self.config.hnsw.ef_search);
+            self.search_ef(query, top_k, visible, ef_search)
+        };
+        if let Some(threshold) = score_threshold {
+            results.retain(|&(_, distance)| self.score(distance) >= threshold);
+        }
+        results
+    }
+
+    /// `distance` as a score where higher is better, see `distance::score`.
+    fn score(&self, distance: f32) -> f32 {
+        distance::score(&self.config.distance, distance)
+    }
+
+    /// The `top_k` parents nearest to `query`, searching for more chunks until enough parents
+    /// are found or the collection is exhausted.
+    fn search_parents(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        visible: Visible,
+        params: &SearchParams,
+        score_threshold: Option<f32>,
+        options: &GroupByParent,
+    ) -> Vec<chunks::Group> {
+        let parent_of = |id| {
+            self.get(id)
+                .and_then(|r| self.chunk_index.parent(&r.payload))
+        };
+        let mut k = (top_k * options.hits_per_parent.max(1) * 2)
+            .min(self.len())
+            .max(1);
+        loop {
+            let results = self.search_with(query, k, visible, params, score_threshold);
+            let groups = chunks::group(&results, parent_of, options, top_k);
+            if groups.len() >= top_k || results.len() < k || k >= self.len() {
+                return groups;
             }
-            self.records.push(record);
+            k = (k * 2).min(self.len());
         }
     }
 
-    fn search(&self, query: Vec<f32>, top_k: usize) -> Vec<(u64, f32)> {
-        if let Some(hnsw) = &self.hnsw_l2 {
-            let res = hnsw.search(query.as_slice(), top_k, self.config.hnsw.ef_search);
-            return res.into_iter().map(|n| (n.d_id as u64, n.distance)).collect();
+    /// The `top_k` points nearest to `examples`, searching for `query` or, with the best-score
+    /// strategy, near each positive. The examples' own ids are left out.
+    fn recommend(
+        &self,
+        query: &[f32],
+        examples: &Examples,
+        top_k: usize,
+        visible: Visible,
+        params: &SearchParams,
+        score_threshold: Option<f32>,
+    ) -> Vec<(u64, f32)> {
+        let k = top_k + examples.exclude.len();
+        let mut results = match examples.strategy {
+            Strategy::AverageVector => self.search_with(query, k, visible, params, score_threshold),
+            Strategy::BestScore => {
+                let nearest = |vectors: &[Vec<f32>], vector: &[f32]| {
+                    vectors
+                        .iter()
+                        .map(|v| self.index.distance(v, vector))
+                        .fold(f32::INFINITY, f32::min)
+                };
+                let candidates: HashSet<u64> = examples
+                    .positive
+                    .iter()
+                    .flat_map(|p| self.search_with(p, k, visible, params, None))
+                    .map(|(id, _)| id)
+                    .collect();
+                let mut ranked: Vec<(bool, u64, f32)> = candidates
+                    .into_iter()
+                    .filter_map(|id| self.get(id))
+                    .map(|r| {
+                        let positive = nearest(&examples.positive, &r.vector);
+                        let negative = nearest(&examples.negative, &r.vector);
+                        (negative < positive, r.id, positive)
+                    })
+                    .filter(|&(_, _, d)| score_threshold.is_none_or(|t| self.score(d) >= t))
+                    .collect();
+                ranked.sort_by(|a, b| a.0.cmp(&b.0).then(a.2.total_cmp(&b.2)));
+                ranked.into_iter().map(|(_, id, d)| (id, d)).collect()
+            }
+        };
+        results.retain(|(id, _)| !examples.exclude.contains(id));
+        results.truncate(top_k);
+        results
+    }
+
+    /// Ids of up to `n` chunks before and after `record`: its siblings in position order when
+    /// the collection has a `chunks` config, and otherwise the nearest stored ids, for corpora
+    /// that number chunks consecutively.
+    fn context_ids(&self, record: &VectorRecord, n: usize) -> (Vec<u64>, Vec<u64>) {
+        if self.config.chunks.is_some() {
+            return self.chunk_index.siblings(record.id, &record.payload, n);
         }
-        if let Some(hnsw) = &self.hnsw_cosine {
-            let res = hnsw.search(query.as_slice(), top_k, self.config.hnsw.ef_search);
-            return res.into_iter().map(|n| (n.d_id as u64, n.distance)).collect();
+        let stored = |id: &u64| self.positions.contains_key(id);
+        let before = (record.id.saturating_sub(n as u64)..record.id)
+            .filter(stored)
+            .collect();
+        let after = (record.id.saturating_add(1)..=record.id.saturating_add(n as u64))
+            .filter(stored)
+            .collect();
+        (before, after)
+    }
+
+    /// Brute-force search over the `visible` points of `records`, using the same distance as the
+    /// graph.
+    fn exact_search<'r>(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        records: impl Iterator<Item = &'r VectorRecord>,
+        visible: Visible,
+    ) -> Vec<(u64, f32)> {
+        let mut hits: Vec<(u64, f32)> = records
+            .filter(|r| visible.allows(r))
+            .map(|r| (r.id, self.index.distance(query, &r.vector)))
+            .collect();
+        // Only the nearest `top_k` need sorting, which matters when scanning a whole collection.
+        if hits.len() > top_k && top_k > 0 {
+            hits.select_nth_unstable_by(top_k - 1, |a, b| a.1.total_cmp(&b.1));
+            hits.truncate(top_k);
         }
-        vec![]
+        hits.sort_by(|a, b| a.1.total_cmp(&b.1));
+        hits.truncate(top_k);
+        hits
     }
 }
 
+type SharedCollection<'a> = Arc<RwLock<Collection<'a>>>;
+
 struct AppState<'a> {
-    collections: Mutex<HashMap<String, Collection<'a>>>,
+    /// The map is locked only to look up, add or remove collections. Each collection has its own
+    /// lock, so searches run in parallel and writes block only their own collection.
+    collections: RwLock<HashMap<String, SharedCollection<'a>>>,
+    /// Bumped whenever the set of collections changes; versions `GET /collections`.
+    catalog_version: AtomicU64,
+    /// Damaged storage found at startup, served at `GET /recovery`.
+    recovery: Mutex<Vec<Recovery>>,
+    audit: AuditLog,
+    query_log: QueryLog,
+    id_gen: IdGenerator,
+    response: ResponseOptions,
+    limits: Limits,
+    storage: Storage,
+    snapshots: Snapshots,
+    operations: Operations,
+    /// Opened on first use; locked after `collections` when both are needed.
+    feedback: Mutex<HashMap<String, FeedbackLog>>,
+}
+
+impl<'a> AppState<'a> {
+    fn collection(&self, name: &str) -> Option<SharedCollection<'a>> {
+        self.collections.read().unwrap().get(name).cloned()
+    }
 }
 
 #[derive(Deserialize)]
@@ -108,87 +1313,3021 @@ struct CreateCollectionBody {
     name: String,
     config: CollectionConfig,
     dim: usize,
+    /// Succeed without changes when the collection already exists, instead of returning 409.
+    #[serde(default)]
+    if_not_exists: bool,
+}
+
+#[derive(Serialize)]
+struct CreateCollectionResponse {
+    created: bool,
 }
 
 async fn create_collection<'a>(
+    req: HttpRequest,
     data: web::Data<AppState<'a>>,
     body: web::Json<CreateCollectionBody>,
 ) -> impl Responder {
-    let mut collections = data.collections.lock().unwrap();
-    collections.insert(
-        body.name.clone(),
-        Collection::new(body.config.clone(), body.dim),
-    );
-    HttpResponse::Ok().finish()
+    if let Err(e) = validate_collection_name(&body.name)
+        .and_then(|_| body.config.validate(body.dim))
+    {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let mut collections = data.collections.write().unwrap();
+    if collections.contains_key(&body.name) {
+        if body.if_not_exists {
+            return HttpResponse::Ok().json(CreateCollectionResponse { created: false });
+        }
+        return HttpResponse::Conflict().body("Collection already exists");
+    }
+    let mut coll = Collection::new(body.config.clone(), body.dim);
+    let manifest = Manifest {
+        name: body.name.clone(),
+        dim: body.dim,
+        config: body.config.clone(),
+    };
+    match data.storage.create(&manifest) {
+        Ok(Some(wal)) => coll.wal = Some(wal),
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("could not create storage for {}: {}", body.name, e);
+            return HttpResponse::InternalServerError().body("Could not create collection storage");
+        }
+    }
+    collections.insert(body.name.clone(), Arc::new(RwLock::new(coll)));
+    data.catalog_version.fetch_add(1, Ordering::SeqCst);
+    data.audit.record(&req, Some(&body.name), None);
+    HttpResponse::Ok().json(CreateCollectionResponse { created: true })
 }
 
-#[derive(Deserialize)]
-struct UpsertBody {
-    ids: Vec<u64>,
+#[derive(Serialize)]
+struct CollectionInfo<'c> {
+    name: &'c str,
+    dim: usize,
+    points: usize,
+    /// Graph slots still held by deleted or overwritten points; a reindex frees them.
+    deleted: usize,
+    memory_bytes: usize,
+    config: &'c CollectionConfig,
+    growth_events: &'c [GrowthEvent],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    throttle: Option<ThrottleStatus>,
+}
+
+async fn collection_info<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let Some(coll) = data.collection(path.as_str()) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let coll = coll.read().unwrap();
+    let throttle = coll.throttle.status(coll.config.write_throttle.as_ref());
+    // The throttle backlog drains without writes, so it is versioned by content.
+    let version = match &throttle {
+        Some(status) => format!(
+            "{}-{}",
+            etag::versioned(coll.revision),
+            etag::of_content(status)
+        ),
+        None => etag::versioned(coll.revision),
+    };
+    if let Some(resp) = etag::not_modified(&req, &version) {
+        return resp;
+    }
+    let info = CollectionInfo {
+        name: &path,
+        dim: coll.dim,
+        points: coll.len(),
+        deleted: coll.ids.slots() - coll.ids.len(),
+        memory_bytes: coll.memory_estimate(),
+        config: &coll.config,
+        growth_events: &coll.growth_events,
+        throttle,
+    };
+    etag::ok(&version).json(info)
+}
+
+async fn delete_collection<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let mut collections = data.collections.write().unwrap();
+    let Some(coll) = collections.remove(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    data.catalog_version.fetch_add(1, Ordering::SeqCst);
+    // Waits for requests already using the collection, then closes the WAL and feedback log
+    // before their directory goes away.
+    coll.write().unwrap().wal = None;
+    data.feedback.lock().unwrap().remove(&name);
+    if let Err(e) = data.storage.remove(&name) {
+        log::error!("could not remove storage for {}: {}", name, e);
+        return HttpResponse::InternalServerError()
+            .body("Collection was dropped but its files could not be moved to the trash");
+    }
+    data.audit.record(&req, Some(&name), None);
+    HttpResponse::NoContent().finish()
+}
+
+async fn list_trash<'a>(data: web::Data<AppState<'a>>) -> impl Responder {
+    match data.storage.list_trash() {
+        Ok(trash) => HttpResponse::Ok().json(trash),
+        Err(e) => {
+            log::error!("could not list the trash: {}", e);
+            HttpResponse::InternalServerError().body("Could not list the trash")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct UntrashQuery {
+    /// Restore under this name instead of the original one.
+    #[serde(default)]
+    name: Option<String>,
+}
+
+async fn restore_trashed<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    query: web::Query<UntrashQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let (manifest, records) = match data.storage.read_trashed(&id) {
+        Ok(Some(trashed)) => trashed,
+        Ok(None) => return HttpResponse::NotFound().body("Not found in the trash"),
+        Err(e) => {
+            log::error!("could not read {} from the trash: {}", id, e);
+            return HttpResponse::InternalServerError().body("Could not read the collection");
+        }
+    };
+    let name = query.into_inner().name.unwrap_or(manifest.name);
+    if let Err(e) = validate_collection_name(&name) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if data.collection(&name).is_some() {
+        return HttpResponse::Conflict().body("Collection already exists");
+    }
+    let mut coll = match Collection::restore(manifest.config, manifest.dim, records) {
+        Ok(coll) => coll,
+        Err(e) => {
+            log::error!("could not rebuild {} from the trash: {}", id, e);
+            return HttpResponse::InternalServerError().body("Could not rebuild the collection");
+        }
+    };
+    // Checked again: the collection was rebuilt without holding the catalog.
+    let mut collections = data.collections.write().unwrap();
+    if collections.contains_key(&name) {
+        return HttpResponse::Conflict().body("Collection already exists");
+    }
+    let manifest = Manifest {
+        name: name.clone(),
+        dim: coll.dim,
+        config: coll.config.clone(),
+    };
+    match data.storage.untrash(&id, &manifest) {
+        Ok(Some(wal)) => coll.wal = Some(wal),
+        Ok(None) => return HttpResponse::NotFound().body("Not found in the trash"),
+        Err(e) => {
+            log::error!("could not restore {} from the trash: {}", id, e);
+            return HttpResponse::InternalServerError().body("Could not restore the collection");
+        }
+    }
+    let points = coll.len();
+    collections.insert(name.clone(), Arc::new(RwLock::new(coll)));
+    data.catalog_version.fetch_add(1, Ordering::SeqCst);
+    data.audit.record(&req, Some(&name), Some(points));
+    HttpResponse::Ok().json(CloneResponse { points })
+}
+
+async fn purge_trashed<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match data.storage.purge_trashed(&path) {
+        Ok(true) => {
+            data.audit.record(&req, None, None);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound().body("Not found in the trash"),
+        Err(e) => {
+            log::error!("could not purge {} from the trash: {}", path, e);
+            HttpResponse::InternalServerError().body("Could not purge the collection")
+        }
+    }
+}
+
+/// The ids (`None` for generated ones), vectors, payloads and tags of the points to upsert.
+type UpsertColumns = (
+    Vec<Option<u64>>,
+    Vec<Vec<f32>>,
+    Vec<serde_json::Value>,
+    Vec<Tags>,
+);
+
+/// Either parallel arrays (`ids`, `vectors`, `payloads`, `tags`) or a list of `points`.
+enum UpsertBody {
+    Batch(BatchUpsert),
+    Points(Vec<PointInput>),
+}
+
+#[derive(Deserialize)]
+struct BatchUpsert {
+    /// Omit to have the server generate ids.
+    #[serde(default)]
+    ids: Option<Vec<u64>>,
+    vectors: Vec<Vec<f32>>,
+    payloads: Vec<serde_json::Value>,
+    /// Access tags of each point; omit for untagged points.
+    #[serde(default)]
+    tags: Option<Vec<Tags>>,
+}
+
+#[derive(Deserialize)]
+struct PointInput {
+    /// Omit to have the server generate an id.
+    #[serde(default)]
+    id: Option<u64>,
+    vector: Vec<f32>,
+    #[serde(default)]
+    payload: serde_json::Value,
+    #[serde(default)]
+    tags: Tags,
+}
+
+#[derive(Deserialize)]
+struct PointsUpsert {
+    points: Vec<PointInput>,
+}
+
+// Picks the shape by the presence of `points`, so errors describe the shape the client meant
+// instead of serde's generic "did not match any variant".
+impl<'de> Deserialize<'de> for UpsertBody {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let value = serde_json::Value::deserialize(deserializer)?;
+        if value.get("points").is_some() {
+            serde_json::from_value::<PointsUpsert>(value)
+                .map(|b| UpsertBody::Points(b.points))
+                .map_err(D::Error::custom)
+        } else {
+            serde_json::from_value(value)
+                .map(UpsertBody::Batch)
+                .map_err(D::Error::custom)
+        }
+    }
+}
+
+impl UpsertBody {
+    fn len(&self) -> usize {
+        match self {
+            UpsertBody::Batch(b) => b.vectors.len(),
+            UpsertBody::Points(points) => points.len(),
+        }
+    }
+
+    /// The parallel arrays must line up, otherwise points would be paired with the wrong data.
+    fn check_lengths(&self) -> Result<(), String> {
+        let UpsertBody::Batch(batch) = self else {
+            return Ok(());
+        };
+        let vectors = batch.vectors.len();
+        if let Some(ids) = &batch.ids {
+            if ids.len() != vectors {
+                return Err(format!(
+                    "ids has {} entries but vectors has {}",
+                    ids.len(),
+                    vectors
+                ));
+            }
+        }
+        if batch.payloads.len() != vectors {
+            return Err(format!(
+                "payloads has {} entries but vectors has {}",
+                batch.payloads.len(),
+                vectors
+            ));
+        }
+        if let Some(tags) = batch.tags.as_ref().filter(|t| t.len() != vectors) {
+            return Err(format!(
+                "tags has {} entries but vectors has {}",
+                tags.len(),
+                vectors
+            ));
+        }
+        Ok(())
+    }
+
+    /// Splits into parallel arrays, with `None` for ids the server should generate.
+    fn into_columns(self) -> UpsertColumns {
+        match self {
+            UpsertBody::Batch(b) => {
+                let ids = match b.ids {
+                    Some(ids) => ids.into_iter().map(Some).collect(),
+                    None => vec![None; b.vectors.len()],
+                };
+                let tags = b.tags.unwrap_or_else(|| vec![Vec::new(); b.vectors.len()]);
+                (ids, b.vectors, b.payloads, tags)
+            }
+            UpsertBody::Points(points) => {
+                let mut ids = Vec::with_capacity(points.len());
+                let mut vectors = Vec::with_capacity(points.len());
+                let mut payloads = Vec::with_capacity(points.len());
+                let mut tags = Vec::with_capacity(points.len());
+                for p in points {
+                    ids.push(p.id);
+                    vectors.push(p.vector);
+                    payloads.push(p.payload);
+                    tags.push(p.tags);
+                }
+                (ids, vectors, payloads, tags)
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct UpsertResponse {
+    results: Vec<PointResult>,
+    /// Live points in the collection after the upsert.
+    points: usize,
+}
+
+#[derive(Deserialize)]
+struct ValidateQuery {
+    /// Check the request against the collection and limits without executing it.
+    #[serde(default)]
+    validate: bool,
+}
+
+#[derive(Serialize)]
+struct ValidationReport {
+    valid: bool,
+    errors: Vec<String>,
+}
+
+/// 200 when there are no errors, 422 otherwise, with every error listed.
+fn validation_response(errors: Vec<String>) -> HttpResponse {
+    let mut resp = if errors.is_empty() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::UnprocessableEntity()
+    };
+    resp.json(ValidationReport {
+        valid: errors.is_empty(),
+        errors,
+    })
+}
+
+fn upsert_errors(coll: &Collection, limits: &Limits, body: UpsertBody) -> Vec<String> {
+    let mut errors: Vec<String> = coll.check_writable().err().into_iter().collect();
+    errors.extend(body.check_lengths().err());
+    errors.extend(limits.check_batch("point", body.len()).err());
+    let (ids, vectors, _, _) = body.into_columns();
+    for (i, vector) in vectors.iter().enumerate() {
+        if let Err(e) = coll.check_vector(vector) {
+            errors.push(match ids.get(i).copied().flatten() {
+                Some(id) => format!("point {} (id {}): {}", i, id, e),
+                None => format!("point {}: {}", i, e),
+            });
+        }
+    }
+    errors
+}
+
+async fn upsert_vectors<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    query: web::Query<ValidateQuery>,
+    body: web::Json<UpsertBody>,
+) -> impl Responder {
+    if query.validate {
+        let Some(coll) = data.collection(path.as_str()) else {
+            return HttpResponse::NotFound().body("Collection not found");
+        };
+        let coll = coll.read().unwrap();
+        return validation_response(upsert_errors(&coll, &data.limits, body.into_inner()));
+    }
+    if let Err(e) = body.check_lengths() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if let Err(e) = data.limits.check_batch("point", body.len()) {
+        return HttpResponse::UnprocessableEntity().body(e);
+    }
+    let name = path.into_inner();
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    if let Some(resp) = wait_for_throttle(&coll, body.len()).await {
+        return resp;
+    }
+    let mut coll = coll.write().unwrap();
+    if let Err(e) = coll.check_writable() {
+        return HttpResponse::Conflict().body(e);
+    }
+    let (ids, vectors, payloads, tags) = body.into_inner().into_columns();
+    let grants = auth::grants(&req);
+    if let Some(grants) = &grants {
+        if !tags.iter().all(|tags| grants.covers(tags)) {
+            return HttpResponse::Forbidden()
+                .body("tags: every point must carry only tags the API key is granted");
+        }
+    }
+    let ids: Vec<u64> = ids
+        .into_iter()
+        .map(|id| id.unwrap_or_else(|| data.id_gen.next_id()))
+        .collect();
+    let count = ids.len();
+    let mirror = coll.shadow.clone().map(|shadow| {
+        let write = (ids.clone(), vectors.clone(), payloads.clone(), tags.clone());
+        (shadow, write)
+    });
+    let visible = Visible::new(None, grants.as_ref());
+    let results = coll.upsert(ids, vectors, payloads, tags, visible);
+    let mirror = mirror.map(|(shadow, (ids, vectors, payloads, tags))| {
+        let write = applied_upsert(ids, vectors, payloads, tags, &results);
+        (shadow, write)
+    });
+    let points = coll.len();
+    if let Err(e) = coll.persist() {
+        log::error!("could not persist upsert to {}: {}", name, e);
+        return HttpResponse::InternalServerError()
+            .body("Points were applied but could not be persisted");
+    }
+    drop(coll);
+    if let Some((shadow, write)) = mirror {
+        mirror_write(&data, &shadow, write);
+    }
+    data.audit.record(&req, Some(&name), Some(count));
+    HttpResponse::Ok().json(UpsertResponse { results, points })
+}
+
+/// The points of an upsert the primary applied, to mirror to its shadow.
+fn applied_upsert(
+    ids: Vec<u64>,
     vectors: Vec<Vec<f32>>,
     payloads: Vec<serde_json::Value>,
+    tags: Vec<Tags>,
+    results: &[PointResult],
+) -> MirrorWrite {
+    let mut write = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let points = ids.into_iter().zip(vectors).zip(payloads).zip(tags);
+    for ((((id, vector), payload), tags), result) in points.zip(results) {
+        if result.status != "failed" {
+            write.0.push(id);
+            write.1.push(vector);
+            write.2.push(payload);
+            write.3.push(tags);
+        }
+    }
+    MirrorWrite::Upsert(write.0, write.1, write.2, write.3)
+}
+
+/// Holds a write of `points` back as long as the collection's write throttle asks, or returns
+/// the response rejecting it.
+async fn wait_for_throttle(coll: &SharedCollection<'_>, points: usize) -> Option<HttpResponse> {
+    let admitted = {
+        let coll = coll.read().unwrap();
+        let throttle = coll.config.write_throttle.as_ref();
+        coll.throttle.admit(throttle, points)
+    };
+    match admitted {
+        Ok(delay) => {
+            if !delay.is_zero() {
+                actix_web::rt::time::sleep(delay).await;
+            }
+            None
+        }
+        Err(Rejection::Busy(retry_after)) => Some(
+            HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after.as_secs() + 1))
+                .body("Write throttle is saturated; retry later"),
+        ),
+        Err(Rejection::TooLarge(max)) => Some(HttpResponse::UnprocessableEntity().body(format!(
+            "A write of {} points exceeds the {} the collection's write throttle admits at once",
+            points, max
+        ))),
+    }
+}
+
+async fn get_point<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<(String, u64)>,
+) -> impl Responder {
+    let (name, id) = path.into_inner();
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let coll = coll.read().unwrap();
+    let grants = auth::grants(&req);
+    let visible = Visible::new(None, grants.as_ref());
+    let Some(record) = coll.get(id).filter(|r| visible.may_read(r)) else {
+        return HttpResponse::NotFound().body("Point not found");
+    };
+    let version = etag::of_content(record);
+    if let Some(resp) = etag::not_modified(&req, &version) {
+        return resp;
+    }
+    etag::ok(&version).json(record)
+}
+
+#[derive(Deserialize)]
+struct GetPointsBody {
+    ids: Vec<u64>,
+    /// Defaults to true.
+    #[serde(default)]
+    with_vector: Option<bool>,
+    #[serde(default)]
+    payload_selector: Option<PayloadSelector>,
+}
+
+#[derive(Serialize)]
+struct StoredPoint {
+    id: u64,
+    payload: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vector: Option<Vec<f32>>,
+}
+
+impl StoredPoint {
+    /// Point `r` showing `payload`, e.g. its payload as [`Collection::shown`] returns it.
+    fn new(r: &VectorRecord, with_vector: bool, payload: serde_json::Value) -> Self {
+        Self {
+            id: r.id,
+            payload,
+            vector: with_vector.then(|| r.vector.clone()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GetPointsResponse {
+    /// Found points, in request order.
+    points: Vec<StoredPoint>,
+    not_found: Vec<u64>,
+}
+
+async fn get_points<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<GetPointsBody>,
+) -> impl Responder {
+    if let Err(e) = data.limits.check_batch("id", body.ids.len()) {
+        return HttpResponse::UnprocessableEntity().body(e);
+    }
+    let Some(coll) = data.collection(path.as_str()) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let coll = coll.read().unwrap();
+    let mut resp = GetPointsResponse {
+        points: Vec::new(),
+        not_found: Vec::new(),
+    };
+    let grants = auth::grants(&req);
+    let visible = Visible::new(None, grants.as_ref());
+    let decrypt = auth::decrypts(&req);
+    for id in &body.ids {
+        match coll.get(*id).filter(|r| visible.may_read(r)) {
+            Some(r) => resp.points.push(StoredPoint::new(
+                r,
+                body.with_vector.unwrap_or(true),
+                coll.shown(&r.payload, body.payload_selector.as_ref(), decrypt),
+            )),
+            None => resp.not_found.push(*id),
+        }
+    }
+    HttpResponse::Ok().json(resp)
+}
+
+#[derive(Deserialize)]
+struct ScrollBody {
+    /// Defaults to 100.
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Start of the page; pass the previous response's `next_offset` to continue.
+    #[serde(default)]
+    offset_id: u64,
+    #[serde(default)]
+    filter: Option<Filter>,
+    #[serde(default)]
+    with_vector: bool,
+    #[serde(default)]
+    payload_selector: Option<PayloadSelector>,
+}
+
+#[derive(Serialize)]
+struct ScrollResponse {
+    points: Vec<StoredPoint>,
+    /// `null` on the last page.
+    next_offset: Option<u64>,
+}
+
+async fn scroll_points<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<ScrollBody>,
+) -> impl Responder {
+    let limit = body.limit.unwrap_or(100);
+    if let Err(e) = data
+        .limits
+        .check_batch("point", limit)
+        .and_then(|_| data.limits.check_filter(body.filter.as_ref()))
+    {
+        return HttpResponse::UnprocessableEntity().body(e);
+    }
+    let Some(coll) = data.collection(path.as_str()) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let coll = coll.read().unwrap();
+    let grants = auth::grants(&req);
+    let visible = Visible::new(body.filter.as_ref(), grants.as_ref());
+    let (page, next_offset) = coll.scroll(body.offset_id, limit, visible);
+    let decrypt = auth::decrypts(&req);
+    let points = page
+        .into_iter()
+        .map(|r| {
+            let payload = coll.shown(&r.payload, body.payload_selector.as_ref(), decrypt);
+            StoredPoint::new(r, body.with_vector, payload)
+        })
+        .collect();
+    HttpResponse::Ok().json(ScrollResponse {
+        points,
+        next_offset,
+    })
+}
+
+#[derive(Deserialize)]
+struct DeleteBody {
+    ids: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct DeleteResult {
+    id: u64,
+    status: &'static str, // "deleted" or "not_found"
+}
+
+#[derive(Serialize)]
+struct DeleteResponse {
+    deleted: usize,
+    results: Vec<DeleteResult>,
+}
+
+async fn delete_points<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<DeleteBody>,
+) -> impl Responder {
+    if let Err(e) = data.limits.check_batch("id", body.ids.len()) {
+        return HttpResponse::UnprocessableEntity().body(e);
+    }
+    let name = path.into_inner();
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let mut coll = coll.write().unwrap();
+    if let Err(e) = coll.check_writable() {
+        return HttpResponse::Conflict().body(e);
+    }
+    let grants = auth::grants(&req);
+    let found = coll.delete(&body.ids, Visible::new(None, grants.as_ref()));
+    if let Err(e) = coll.persist() {
+        log::error!("could not persist delete from {}: {}", name, e);
+        return HttpResponse::InternalServerError()
+            .body("Points were deleted but the deletion could not be persisted");
+    }
+    let shadow = coll.shadow.clone();
+    drop(coll);
+    if let Some(shadow) = shadow {
+        let deleted = body.ids.iter().zip(&found).filter(|(_, found)| **found);
+        let ids = deleted.map(|(id, _)| *id).collect();
+        mirror_write(&data, &shadow, MirrorWrite::Delete(ids));
+    }
+    data.audit.record(&req, Some(&name), Some(body.ids.len()));
+    let results: Vec<DeleteResult> = body
+        .ids
+        .iter()
+        .zip(found)
+        .map(|(id, found)| DeleteResult {
+            id: *id,
+            status: if found { "deleted" } else { "not_found" },
+        })
+        .collect();
+    HttpResponse::Ok().json(DeleteResponse {
+        deleted: results.iter().filter(|r| r.status == "deleted").count(),
+        results,
+    })
+}
+
+#[derive(Deserialize)]
+struct UpdateVectorsBody {
+    ids: Vec<u64>,
+    vectors: Vec<Vec<f32>>,
+}
+
+/// Re-embeds existing points: replaces their vectors and keeps their payloads.
+async fn update_vectors<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<UpdateVectorsBody>,
+) -> impl Responder {
+    if body.ids.len() != body.vectors.len() {
+        return HttpResponse::BadRequest().body(format!(
+            "ids has {} entries but vectors has {}",
+            body.ids.len(),
+            body.vectors.len()
+        ));
+    }
+    if let Err(e) = data.limits.check_batch("point", body.ids.len()) {
+        return HttpResponse::UnprocessableEntity().body(e);
+    }
+    let name = path.into_inner();
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    if let Some(resp) = wait_for_throttle(&coll, body.ids.len()).await {
+        return resp;
+    }
+    let mut coll = coll.write().unwrap();
+    if let Err(e) = coll.check_writable() {
+        return HttpResponse::Conflict().body(e);
+    }
+    let UpdateVectorsBody { ids, vectors } = body.into_inner();
+    let count = ids.len();
+    let grants = auth::grants(&req);
+    let results = coll.update_vectors(ids, vectors, Visible::new(None, grants.as_ref()));
+    let points = coll.len();
+    if let Err(e) = coll.persist() {
+        log::error!("could not persist vector update to {}: {}", name, e);
+        return HttpResponse::InternalServerError()
+            .body("Vectors were updated but could not be persisted");
+    }
+    let mirror = coll.shadow.clone().map(|shadow| {
+        let mut write = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for r in results.iter().filter(|r| r.status == "updated") {
+            if let Some(record) = coll.get(r.id) {
+                write.0.push(record.id);
+                write.1.push(record.vector.clone());
+                write.2.push(coll.inlined(&record.payload));
+                write.3.push(record.tags.clone());
+            }
+        }
+        let write = MirrorWrite::Upsert(write.0, write.1, write.2, write.3);
+        (shadow, write)
+    });
+    drop(coll);
+    if let Some((shadow, write)) = mirror {
+        mirror_write(&data, &shadow, write);
+    }
+    data.audit.record(&req, Some(&name), Some(count));
+    HttpResponse::Ok().json(UpsertResponse { results, points })
+}
+
+/// Mapping lines applied under one lock acquisition.
+const BACKFILL_BATCH: usize = 10_000;
+
+/// Line errors reported in a backfill response at most.
+const BACKFILL_MAX_ERRORS: usize = 20;
+
+#[derive(Deserialize)]
+struct BackfillQuery {
+    /// Dotted payload key to set.
+    field: String,
+}
+
+/// One line of a backfill mapping.
+#[derive(Deserialize)]
+struct BackfillLine {
+    id: u64,
+    value: serde_json::Value,
+}
+
+#[derive(Serialize, Default)]
+struct BackfillResponse {
+    updated: usize,
+    not_found: usize,
+    invalid: usize,
+    /// The first invalid lines, e.g. `line 3: missing field `id``.
+    errors: Vec<String>,
+}
+
+/// Sets a payload field on existing points from an uploaded NDJSON mapping of
+/// `{"id": .., "value": ..}` lines, streamed and applied in batches so vectors need not be resent.
+async fn backfill_payload<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    query: web::Query<BackfillQuery>,
+    mut body: web::Payload,
+) -> impl Responder {
+    let name = path.into_inner();
+    if query.field.split('.').any(str::is_empty) {
+        return HttpResponse::BadRequest().body(format!("Invalid field {:?}", query.field));
+    }
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    if let Err(e) = coll.read().unwrap().check_writable() {
+        return HttpResponse::Conflict().body(e);
+    }
+    let encrypted = coll.read().unwrap().config.encrypted_fields.clone();
+    if payload::within(&query.field, &encrypted) && !encrypted.contains(&query.field) {
+        let message = format!("{} is inside an encrypted field", query.field);
+        return HttpResponse::BadRequest().body(message);
+    }
+    let grants = auth::grants(&req);
+    let mut summary = BackfillResponse::default();
+    let mut pending = Vec::new();
+    let mut batch = Vec::new();
+    let mut line_no = 0;
+    loop {
+        let chunk = match body.next().await {
+            Some(Ok(chunk)) => Some(chunk),
+            Some(Err(e)) => return HttpResponse::BadRequest().body(format!("body: {}", e)),
+            None => None,
+        };
+        let done = chunk.is_none();
+        pending.extend_from_slice(chunk.as_deref().unwrap_or_default());
+        let complete = match pending.iter().rposition(|&b| b == b'\n') {
+            _ if done => pending.len(),
+            Some(end) => end + 1,
+            None => 0,
+        };
+        let complete: Vec<u8> = pending.drain(..complete).collect();
+        // An empty remainder is no line at all, while a lone newline is one empty line.
+        let lines = match complete.strip_suffix(b"\n") {
+            _ if complete.is_empty() => None,
+            Some(lines) => Some(lines),
+            None => Some(complete.as_slice()),
+        };
+        for line in lines.into_iter().flat_map(|l| l.split(|&b| b == b'\n')) {
+            line_no += 1;
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            match serde_json::from_slice::<BackfillLine>(line) {
+                Ok(entry) => batch.push((entry.id, entry.value)),
+                Err(e) => {
+                    summary.invalid += 1;
+                    if summary.errors.len() < BACKFILL_MAX_ERRORS {
+                        summary.errors.push(format!("line {}: {}", line_no, e));
+                    }
+                }
+            }
+        }
+        if batch.len() >= BACKFILL_BATCH || (done && !batch.is_empty()) {
+            let mut values = std::mem::take(&mut batch);
+            while !values.is_empty() {
+                let (count, admitted) = {
+                    let coll = coll.read().unwrap();
+                    let throttle = coll.config.write_throttle.as_ref();
+                    let max = throttle.map_or(usize::MAX, ThrottleConfig::max_batch);
+                    let count = values.len().min(max);
+                    (count, coll.throttle.admit(throttle, count))
+                };
+                match admitted {
+                    Ok(delay) if delay.is_zero() => {}
+                    Ok(delay) => actix_web::rt::time::sleep(delay).await,
+                    // A backfill waits its turn instead of failing partway through.
+                    Err(Rejection::Busy(retry_after)) => {
+                        actix_web::rt::time::sleep(retry_after).await;
+                        continue;
+                    }
+                    // Pieces are sized to the throttle, so this is a bug rather than a bad request.
+                    Err(Rejection::TooLarge(max)) => {
+                        log::error!(
+                            "backfill piece of {} points exceeds the throttle's {} for {}",
+                            count,
+                            max,
+                            name
+                        );
+                        return HttpResponse::InternalServerError().body(format!(
+                            "Backfill stopped after {} updated points",
+                            summary.updated
+                        ));
+                    }
+                }
+                let piece: Vec<_> = values.drain(..count).collect();
+                let mut coll = coll.write().unwrap();
+                if let Err(e) = coll.check_writable() {
+                    return HttpResponse::Conflict().body(e);
+                }
+                let visible = Visible::new(None, grants.as_ref());
+                let updated = coll.set_payload_field(&query.field, piece, visible);
+                if let Err(e) = coll.persist() {
+                    log::error!("could not persist payload backfill to {}: {}", name, e);
+                    return HttpResponse::InternalServerError()
+                        .body("Payloads were updated but could not be persisted");
+                }
+                let mirror = coll.shadow.clone().map(|shadow| {
+                    let mut write = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+                    for record in updated.iter().filter_map(|&id| coll.get(id)) {
+                        write.0.push(record.id);
+                        write.1.push(record.vector.clone());
+                        write.2.push(coll.inlined(&record.payload));
+                        write.3.push(record.tags.clone());
+                    }
+                    let write = MirrorWrite::Upsert(write.0, write.1, write.2, write.3);
+                    (shadow, write)
+                });
+                drop(coll);
+                if let Some((shadow, write)) = mirror {
+                    mirror_write(&data, &shadow, write);
+                }
+                summary.updated += updated.len();
+                summary.not_found += count - updated.len();
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    data.audit.record(&req, Some(&name), Some(summary.updated));
+    HttpResponse::Ok().json(summary)
+}
+
+enum MirrorWrite {
+    Upsert(Vec<u64>, Vec<Vec<f32>>, Vec<serde_json::Value>, Vec<Tags>),
+    Delete(Vec<u64>),
+}
+
+/// Applies a write that already succeeded on the primary to its shadow target. Local targets are
+/// written synchronously; remote ones in the background. Called after the primary's lock is
+/// released, so collections shadowing each other cannot deadlock.
+fn mirror_write(data: &AppState, shadow: &Shadow, write: MirrorWrite) {
+    if let Some(url) = &shadow.target.url {
+        let (op, body) = match write {
+            MirrorWrite::Upsert(ids, vectors, payloads, tags) => (
+                "upsert",
+                serde_json::json!({
+                    "ids": ids,
+                    "vectors": vectors,
+                    "payloads": payloads,
+                    "tags": tags
+                }),
+            ),
+            MirrorWrite::Delete(ids) => ("delete", serde_json::json!({ "ids": ids })),
+        };
+        shadow.send_remote(url, op, body);
+        return;
+    }
+    let Some(target) = data.collection(&shadow.target.collection) else {
+        shadow.record(Err(format!(
+            "target collection {:?} not found",
+            shadow.target.collection
+        )));
+        return;
+    };
+    let mut target = target.write().unwrap();
+    if let Err(e) = target.check_writable() {
+        shadow.record(Err(format!("target collection: {}", e)));
+        return;
+    }
+    match write {
+        MirrorWrite::Upsert(ids, vectors, payloads, tags) => {
+            let total = ids.len();
+            let failed = target
+                .upsert(ids, vectors, payloads, tags, Visible::default())
+                .iter()
+                .filter(|r| r.status == "failed")
+                .count();
+            shadow.record(if failed == 0 {
+                target.persist()
+            } else {
+                Err(format!("{} of {} points failed", failed, total))
+            });
+        }
+        MirrorWrite::Delete(ids) => {
+            target.delete(&ids, Visible::default());
+            shadow.record(target.persist());
+        }
+    }
+}
+
+async fn attach_shadow<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<ShadowTarget>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let target = body.into_inner();
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let dim = coll.read().unwrap().dim;
+    if target.url.is_none() {
+        if target.collection == name {
+            return HttpResponse::BadRequest().body("A collection cannot shadow itself");
+        }
+        let Some(t) = data.collection(&target.collection) else {
+            return HttpResponse::BadRequest().body("Shadow target collection not found");
+        };
+        let t = t.read().unwrap();
+        if t.dim != dim {
+            return HttpResponse::BadRequest().body(format!(
+                "shadow target has dimension {}, collection has {}",
+                t.dim, dim
+            ));
+        }
+        if let Err(e) = t.check_writable() {
+            return HttpResponse::BadRequest().body(format!("shadow target: {}", e));
+        }
+    }
+    let shadow = Shadow::new(target);
+    let status = shadow.status();
+    coll.write().unwrap().shadow = Some(shadow);
+    data.audit.record(&req, Some(&name), None);
+    HttpResponse::Ok().json(status)
+}
+
+async fn shadow_status<'a>(data: web::Data<AppState<'a>>, path: web::Path<String>) -> impl Responder {
+    match data.collection(&path.into_inner()) {
+        None => HttpResponse::NotFound().body("Collection not found"),
+        Some(coll) => match &coll.read().unwrap().shadow {
+            None => HttpResponse::NotFound().body("No shadow attached"),
+            Some(shadow) => HttpResponse::Ok().json(shadow.status()),
+        },
+    }
+}
+
+async fn detach_shadow<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let mut coll = coll.write().unwrap();
+    match coll.shadow.take() {
+        None => HttpResponse::NotFound().body("No shadow attached"),
+        Some(shadow) => {
+            data.audit.record(&req, Some(&name), None);
+            HttpResponse::Ok().json(shadow.status())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IndexBody {
+    /// Payload field to index; dots address nested objects.
+    field: String,
+    #[serde(rename = "type")]
+    kind: IndexKind,
+}
+
+#[derive(Serialize)]
+struct IndexResponse {
+    field: String,
+    #[serde(rename = "type")]
+    kind: IndexKind,
+    points: usize,
+}
+
+async fn create_index<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<IndexBody>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let IndexBody { field, kind } = body.into_inner();
+    if let Err(e) = payload_index::validate_field(&field) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let mut coll = coll.write().unwrap();
+    if payload::within(&field, &coll.config.encrypted_fields) {
+        return HttpResponse::BadRequest().body(format!("{} is encrypted", field));
+    }
+    if coll.offloaded(&field) {
+        return HttpResponse::BadRequest().body(format!("{} is kept in blob files", field));
+    }
+    coll.create_index(&field, kind);
+    let manifest = Manifest {
+        name: name.clone(),
+        dim: coll.dim,
+        config: coll.config.clone(),
+    };
+    if let Err(e) = data.storage.update(&manifest) {
+        log::error!("could not update manifest for {}: {}", name, e);
+        return HttpResponse::InternalServerError().body("Index was built but could not be saved");
+    }
+    let points = coll.len();
+    data.audit.record(&req, Some(&name), Some(points));
+    HttpResponse::Ok().json(IndexResponse {
+        field,
+        kind,
+        points,
+    })
+}
+
+#[derive(Deserialize)]
+struct CloneBody {
+    /// Name of the new collection.
+    name: String,
+    /// HNSW parameters for the copy; defaults to the source collection's.
+    #[serde(default)]
+    hnsw: Option<HnswParams>,
+    /// Index type of the copy, e.g. a `flat` one to measure the source's recall against;
+    /// defaults to the source collection's.
+    #[serde(default)]
+    index_type: Option<IndexType>,
+}
+
+#[derive(Serialize)]
+struct CloneResponse {
+    points: usize,
+}
+
+async fn clone_collection<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<CloneBody>,
+) -> impl Responder {
+    let source = path.into_inner();
+    if let Err(e) = validate_collection_name(&body.name) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if data.collection(&body.name).is_some() {
+        return HttpResponse::Conflict().body("Collection already exists");
+    }
+    let Some(coll) = data.collection(&source) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let coll = coll.read().unwrap();
+    let mut config = coll.config.clone();
+    // Clones of a frozen collection are writable.
+    config.frozen = false;
+    if let Some(hnsw) = &body.hnsw {
+        config.hnsw = hnsw.clone();
+    }
+    if let Some(index_type) = body.index_type {
+        config.index_type = index_type;
+    }
+    if let Err(e) = config.validate(coll.dim) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let manifest = Manifest {
+        name: body.name.clone(),
+        dim: coll.dim,
+        config: config.clone(),
+    };
+    let mut clone = coll.clone_with(config);
+    drop(coll);
+    // Checked again: the clone was built without holding the catalog.
+    let mut collections = data.collections.write().unwrap();
+    if collections.contains_key(&body.name) {
+        return HttpResponse::Conflict().body("Collection already exists");
+    }
+    let stored = data
+        .storage
+        .create(&manifest)
+        .and_then(|wal| wal.map_or(Ok(()), |wal| clone.attach_wal(wal)));
+    if let Err(e) = stored {
+        log::error!("could not create storage for {}: {}", body.name, e);
+        return HttpResponse::InternalServerError().body("Could not create collection storage");
+    }
+    let points = clone.len();
+    collections.insert(body.name.clone(), Arc::new(RwLock::new(clone)));
+    data.catalog_version.fetch_add(1, Ordering::SeqCst);
+    data.audit.record(&req, Some(&body.name), Some(points));
+    HttpResponse::Ok().json(CloneResponse { points })
+}
+
+#[derive(Serialize)]
+struct ReindexResponse {
+    points: usize,
+    /// Graph slots held by deleted or overwritten points that were freed.
+    reclaimed: usize,
+}
+
+#[derive(Deserialize)]
+struct ReindexQuery {
+    /// Build the new graph off the request and return an operation to poll or cancel.
+    #[serde(default)]
+    background: bool,
+}
+
+async fn reindex_collection(
+    req: HttpRequest,
+    data: web::Data<AppState<'static>>,
+    path: web::Path<String>,
+    query: web::Query<ReindexQuery>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let mut coll = coll.write().unwrap();
+    if query.background {
+        let op = match data.operations.start("reindex", &name, coll.records.len()) {
+            Ok(op) => op,
+            Err(running) => {
+                return HttpResponse::Conflict().body(format!(
+                    "A reindex is already running as operation {}",
+                    running
+                ))
+            }
+        };
+        let job = ReindexJob {
+            config: coll.config.clone(),
+            records: coll.records.clone(),
+            index: coll.index.clone(),
+        };
+        drop(coll);
+        actix_web::rt::spawn(run_reindex(data.clone(), name.clone(), op.clone(), job));
+        data.audit.record(&req, Some(&name), None);
+        return HttpResponse::Accepted().json(op.status());
+    }
+    match coll.reindex() {
+        Ok(reclaimed) => {
+            data.audit.record(&req, Some(&name), None);
+            HttpResponse::Ok().json(ReindexResponse {
+                points: coll.len(),
+                reclaimed,
+            })
+        }
+        Err(e) => {
+            log::error!("could not rewrite the WAL of {}: {}", name, e);
+            HttpResponse::InternalServerError()
+                .body("Reindexed, but the WAL could not be rewritten")
+        }
+    }
+}
+
+/// Compacts the collection and makes it read-only; see `Collection::freeze`.
+async fn freeze_collection<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let Some(coll) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let mut coll = coll.write().unwrap();
+    if coll.config.frozen {
+        return HttpResponse::Conflict().body("Collection is already frozen");
+    }
+    let reclaimed = match coll.freeze() {
+        Ok(reclaimed) => reclaimed,
+        Err(e) => {
+            log::error!("could not rewrite the WAL of {}: {}", name, e);
+            return HttpResponse::InternalServerError()
+                .body("Frozen, but the WAL could not be rewritten");
+        }
+    };
+    let manifest = Manifest {
+        name: name.clone(),
+        dim: coll.dim,
+        config: coll.config.clone(),
+    };
+    if let Err(e) = data.storage.update(&manifest) {
+        log::error!("could not update manifest for {}: {}", name, e);
+        return HttpResponse::InternalServerError()
+            .body("Frozen, but the manifest could not be saved");
+    }
+    data.audit.record(&req, Some(&name), None);
+    HttpResponse::Ok().json(ReindexResponse {
+        points: coll.len(),
+        reclaimed,
+    })
+}
+
+struct ReindexJob {
+    config: CollectionConfig,
+    records: Vec<VectorRecord>,
+    /// Graph in use when the job started; the result is discarded if it was replaced since.
+    index: VectorIndex<'static>,
+}
+
+async fn run_reindex(
+    data: web::Data<AppState<'static>>,
+    name: String,
+    op: Arc<Operation>,
+    job: ReindexJob,
+) {
+    let ReindexJob {
+        config,
+        records,
+        index: started_on,
+    } = job;
+    let progress = op.clone();
+    let built = web::block(move || {
+        let index = build_index(&config, &records, |n| {
+            progress.set_done(n);
+            !progress.is_cancelled()
+        });
+        index.map(|index| (index, records))
+    })
+    .await;
+    let (index, records) = match built {
+        Ok(Some(built)) => built,
+        Ok(None) => return op.finish(OpState::Cancelled, None),
+        Err(e) => return op.finish(OpState::Failed, Some(e.to_string())),
+    };
+    if op.is_cancelled() {
+        return op.finish(OpState::Cancelled, None);
+    }
+    let coll = data.collection(&name);
+    let mut coll = coll.as_ref().map(|c| c.write().unwrap());
+    let Some(coll) = coll.as_mut().filter(|c| c.index.same(&started_on)) else {
+        let reason = "the collection was dropped or rebuilt during the build";
+        return op.finish(OpState::Failed, Some(reason.to_string()));
+    };
+    coll.install(index, &records);
+    match coll.rewrite_wal() {
+        Ok(()) => op.finish(OpState::Completed, None),
+        Err(e) => {
+            log::error!("could not rewrite the WAL of {}: {}", name, e);
+            let reason = format!("could not rewrite the WAL: {}", e);
+            op.finish(OpState::Failed, Some(reason));
+        }
+    }
+}
+
+async fn recovery_report<'a>(data: web::Data<AppState<'a>>) -> impl Responder {
+    HttpResponse::Ok().json(&*data.recovery.lock().unwrap())
+}
+
+async fn list_operations<'a>(data: web::Data<AppState<'a>>) -> impl Responder {
+    HttpResponse::Ok().json(data.operations.list())
+}
+
+async fn operation_status<'a>(
+    data: web::Data<AppState<'a>>,
+    path: web::Path<u64>,
+) -> impl Responder {
+    match data.operations.get(path.into_inner()) {
+        Some(op) => HttpResponse::Ok().json(op.status()),
+        None => HttpResponse::NotFound().body("Operation not found"),
+    }
+}
+
+async fn cancel_operation<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<u64>,
+) -> impl Responder {
+    let Some(op) = data.operations.cancel(path.into_inner()) else {
+        return HttpResponse::NotFound().body("Operation not found");
+    };
+    data.audit.record(&req, Some(&op.collection), None);
+    HttpResponse::Accepted().json(op.status())
+}
+
+#[derive(Serialize)]
+struct SnapshotResponse {
+    /// File name under `SNAPSHOT_DIR`, to pass to restore.
+    snapshot: String,
+    points: usize,
+}
+
+async fn snapshot_collection<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let snapshot = {
+        let Some(coll) = data.collection(&name) else {
+            return HttpResponse::NotFound().body("Collection not found");
+        };
+        let coll = coll.read().unwrap();
+        Snapshot {
+            manifest: Manifest {
+                name: name.clone(),
+                dim: coll.dim,
+                config: coll.config.clone(),
+            },
+            records: coll.exported(),
+        }
+    };
+    let points = snapshot.records.len();
+    let snapshots = data.snapshots.clone();
+    match web::block(move || snapshots.write(&snapshot)).await {
+        Ok(Ok(snapshot)) => {
+            data.audit.record(&req, Some(&name), Some(points));
+            HttpResponse::Ok().json(SnapshotResponse { snapshot, points })
+        }
+        Ok(Err(e)) => {
+            log::error!("could not snapshot {}: {}", name, e);
+            HttpResponse::InternalServerError().body("Could not write snapshot")
+        }
+        Err(_) => HttpResponse::InternalServerError().body("Could not write snapshot"),
+    }
+}
+
+#[derive(Deserialize)]
+struct RestoreBody {
+    /// File name returned by the snapshot endpoint.
+    snapshot: String,
+}
+
+/// Creates the collection `{name}` from a snapshot, which may have been taken under another name.
+async fn restore_collection<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<RestoreBody>,
+) -> impl Responder {
+    let name = path.into_inner();
+    if let Err(e) = validate_collection_name(&name) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let snapshots = data.snapshots.clone();
+    let file_name = body.into_inner().snapshot;
+    let snapshot = match web::block(move || snapshots.read(&file_name)).await {
+        Ok(Ok(Some(snapshot))) => snapshot,
+        Ok(Ok(None)) => return HttpResponse::NotFound().body("Snapshot not found"),
+        Ok(Err(e)) => return HttpResponse::BadRequest().body(e),
+        Err(_) => return HttpResponse::InternalServerError().body("Could not read snapshot"),
+    };
+    let Snapshot { manifest, records } = snapshot;
+    if let Err(e) = manifest.config.validate(manifest.dim) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let mut coll = match Collection::restore(manifest.config, manifest.dim, records) {
+        Ok(coll) => coll,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let mut collections = data.collections.write().unwrap();
+    if collections.contains_key(&name) {
+        return HttpResponse::Conflict().body("Collection already exists");
+    }
+    let manifest = Manifest {
+        name: name.clone(),
+        dim: coll.dim,
+        config: coll.config.clone(),
+    };
+    let stored = data
+        .storage
+        .create(&manifest)
+        .and_then(|wal| wal.map_or(Ok(()), |wal| coll.attach_wal(wal)));
+    if let Err(e) = stored {
+        log::error!("could not create storage for {}: {}", name, e);
+        return HttpResponse::InternalServerError().body("Could not create collection storage");
+    }
+    let points = coll.len();
+    collections.insert(name.clone(), Arc::new(RwLock::new(coll)));
+    data.catalog_version.fetch_add(1, Ordering::SeqCst);
+    data.audit.record(&req, Some(&name), Some(points));
+    HttpResponse::Ok().json(CloneResponse { points })
+}
+
+#[derive(Serialize)]
+struct VerifyResponse {
+    snapshot: String,
+    #[serde(flatten)]
+    verification: snapshot::Verification,
+}
+
+/// Reads a snapshot and checks its checksums without restoring it; a damaged one gets 422.
+async fn verify_snapshot<'a>(
+    data: web::Data<AppState<'a>>,
+    body: web::Json<RestoreBody>,
+) -> impl Responder {
+    let file_name = body.into_inner().snapshot;
+    if let Err(e) = Snapshots::check_name(&file_name) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let snapshots = data.snapshots.clone();
+    let name = file_name.clone();
+    match web::block(move || snapshots.verify(&name)).await {
+        Ok(Ok(Some(verification))) => HttpResponse::Ok().json(VerifyResponse {
+            snapshot: file_name,
+            verification,
+        }),
+        Ok(Ok(None)) => HttpResponse::NotFound().body("Snapshot not found"),
+        Ok(Err(e)) => {
+            log::warn!("snapshot failed verification: {}", e);
+            HttpResponse::UnprocessableEntity().body(e)
+        }
+        Err(_) => HttpResponse::InternalServerError().body("Could not read snapshot"),
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchBody {
+    query: Vec<f32>,
+    top_k: usize,
+    #[serde(default)]
+    with_payload: bool,
+    /// Implies `with_payload`.
+    #[serde(default)]
+    payload_selector: Option<PayloadSelector>,
+    /// Defaults to the server-wide `SEARCH_VERBOSITY`.
+    #[serde(default)]
+    verbosity: Option<Verbosity>,
+    /// Wrap the hits as `{ "result": .., "took_ms": .. }`; defaults to `RESPONSE_ENVELOPE`.
+    #[serde(default)]
+    envelope: Option<bool>,
+    /// Only return points whose payload matches.
+    #[serde(default)]
+    filter: Option<Filter>,
+    /// Adds each hit's point in another collection as `joined`.
+    #[serde(default)]
+    join: Option<Join>,
+    /// Returns parents of chunks instead of the chunks; needs the collection's `chunks` config.
+    #[serde(default)]
+    group_by_parent: Option<GroupByParent>,
+    /// Neighbouring chunks returned on each side of every hit, see `Collection::context_ids`.
+    #[serde(default)]
+    context: usize,
+    #[serde(default)]
+    params: SearchParams,
+    /// Drops hits whose `score` is below it.
+    #[serde(default)]
+    score_threshold: Option<f32>,
+    /// Set for `POST /collections/{name}/recommend`, whose `query` is built from the examples.
+    #[serde(skip)]
+    recommend: Option<Examples>,
+}
+
+/// Per-request search overrides, trading latency for recall without changing the collection.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SearchParams {
+    /// Replaces the collection's `hnsw.ef_search`.
+    #[serde(default)]
+    ef_search: Option<usize>,
+    /// Scores every stored point instead of walking the graph, for ground-truth results.
+    #[serde(default)]
+    exact: bool,
+}
+
+impl SearchParams {
+    fn validate(&self) -> Result<(), String> {
+        if self.ef_search == Some(0) {
+            return Err("params.ef_search must be greater than 0".to_string());
+        }
+        if self.exact && self.ef_search.is_some() {
+            return Err("params.ef_search has no effect with params.exact".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct ScoredPoint {
+    id: u64,
+    distance: f32,
+    /// `distance` as a score where higher is better, e.g. the cosine similarity.
+    score: f32,
+    payload: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vector: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<Context>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    joined: Option<serde_json::Value>,
+}
+
+/// The chunks around a hit in its parent, in position order.
+#[derive(Serialize)]
+struct Context {
+    before: Vec<ContextChunk>,
+    after: Vec<ContextChunk>,
+}
+
+#[derive(Serialize)]
+struct ContextChunk {
+    id: u64,
+    payload: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct ParentHit {
+    parent_id: u64,
+    /// The parent's aggregated distance.
+    distance: f32,
+    score: f32,
+    /// Matching chunks found for the parent.
+    chunks: usize,
+    hits: Vec<ScoredPoint>,
+}
+
+/// Rejects parent grouping on collections without a `chunks` config, and oversized context.
+fn check_chunk_options(coll: &Collection, body: &SearchBody) -> Result<(), String> {
+    if body.context > chunks::MAX_CONTEXT {
+        return Err(format!("context must be at most {}", chunks::MAX_CONTEXT));
+    }
+    if coll.config.chunks.is_none() && body.group_by_parent.is_some() {
+        return Err("group_by_parent needs the collection's chunks config".to_string());
+    }
+    if body
+        .group_by_parent
+        .as_ref()
+        .is_some_and(|g| g.hits_per_parent == 0)
+    {
+        return Err("group_by_parent.hits_per_parent must be greater than 0".to_string());
+    }
+    Ok(())
+}
+
+fn search_errors(coll: &Collection, limits: &Limits, body: &SearchBody) -> Vec<String> {
+    let mut errors: Vec<String> = limits.check_top_k(body.top_k).err().into_iter().collect();
+    errors.extend(limits.check_filter(body.filter.as_ref()).err());
+    if let Err(e) = coll.check_vector(&body.query) {
+        errors.push(format!("query: {}", e));
+    }
+    if (body.join.is_some() || body.context > 0) && body.verbosity == Some(Verbosity::Ids) {
+        errors.push("join and context: not available with verbosity ids".to_string());
+    }
+    errors.extend(check_chunk_options(coll, body).err());
+    errors.extend(body.params.validate().err());
+    errors
+}
+
+async fn search_vectors<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    query: web::Query<ValidateQuery>,
+    body: web::Json<SearchBody>,
+) -> impl Responder {
+    let started = Instant::now();
+    let Some(coll) = data.collection(path.as_str()) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    if query.validate {
+        let coll = coll.read().unwrap();
+        return validation_response(search_errors(&coll, &data.limits, &body));
+    }
+    search_response(&req, &data, &path, &coll, &body, started)
 }
 
-async fn upsert_vectors<'a>(
+/// Searches for points like the `positive` examples and unlike the `negative` ones, with the
+/// other fields of a search body.
+async fn recommend_points<'a>(
+    req: HttpRequest,
     data: web::Data<AppState<'a>>,
     path: web::Path<String>,
-    body: web::Json<UpsertBody>,
+    body: web::Json<RecommendBody>,
 ) -> impl Responder {
-    let mut collections = data.collections.lock().unwrap();
-    if let Some(coll) = collections.get_mut(&path.into_inner()) {
-        coll.upsert(body.ids.clone(), body.vectors.clone(), body.payloads.clone());
-        HttpResponse::Ok().finish()
-    } else {
-        HttpResponse::NotFound().body("Collection not found")
+    let started = Instant::now();
+    let RecommendBody {
+        positive,
+        negative,
+        strategy,
+        search,
+    } = body.into_inner();
+    if positive.is_empty() {
+        return HttpResponse::BadRequest().body("positive must name at least one example");
+    }
+    if search.contains_key("query") {
+        return HttpResponse::BadRequest().body("query: recommend builds it from the examples");
+    }
+    if search.contains_key("group_by_parent") {
+        return HttpResponse::BadRequest().body("group_by_parent: not available with recommend");
+    }
+    let Some(shared) = data.collection(&path) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let coll = shared.read().unwrap();
+    let grants = auth::grants(&req);
+    let visible = Visible::new(None, grants.as_ref());
+    let mut exclude = HashSet::new();
+    let mut resolve = |side: &str, examples: Vec<Example>| {
+        examples
+            .into_iter()
+            .map(|example| match example {
+                Example::Id(id) => match coll.get(id).filter(|r| visible.may_read(r)) {
+                    Some(record) => {
+                        exclude.insert(id);
+                        Ok(record.vector.clone())
+                    }
+                    None => Err(format!("{}: point {} not found", side, id)),
+                },
+                Example::Vector(vector) => coll
+                    .check_vector(&vector)
+                    .map(|_| vector)
+                    .map_err(|e| format!("{}: {}", side, e)),
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let positive = resolve("positive", positive);
+    let resolved = positive.and_then(|p| Ok((p, resolve("negative", negative)?)));
+    let (positive, negative) = match resolved {
+        Ok(resolved) => resolved,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let examples = Examples {
+        strategy,
+        positive,
+        negative,
+        exclude,
+    };
+    let query = examples.query(coll.config.distance == "dot");
+    drop(coll);
+    let mut search = search;
+    search.insert("query".to_string(), query.into());
+    let mut search: SearchBody = match serde_json::from_value(search.into()) {
+        Ok(search) => search,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    search.recommend = Some(examples);
+    search_response(&req, &data, &path, &shared, &search, started)
+}
+
+/// Runs `body` against `coll`, the collection called `name`, and shapes the hits as it asks.
+/// A join's collection is only read once `coll` is unlocked.
+fn search_response(
+    req: &HttpRequest,
+    data: &AppState,
+    name: &str,
+    coll: &SharedCollection,
+    body: &SearchBody,
+    started: Instant,
+) -> HttpResponse {
+    let verbosity = body.verbosity.unwrap_or(data.response.verbosity);
+    if verbosity == Verbosity::Ids && (body.join.is_some() || body.context > 0) {
+        return HttpResponse::BadRequest()
+            .body("join and context: not available with verbosity ids");
+    }
+    let join_scope = body.join.as_ref().and_then(|join| {
+        auth::scope(req).and_then(|scope| scope.refuses_collection(&join.collection))
+    });
+    if let Some(message) = join_scope {
+        return HttpResponse::Forbidden().body(message);
+    }
+    let target = match &body.join {
+        Some(join) => match data.collection(&join.collection) {
+            Some(target) => Some((join, target)),
+            None => return HttpResponse::NotFound().body("Join collection not found"),
+        },
+        None => None,
+    };
+    let coll = coll.read().unwrap();
+    if let Err(e) = data
+        .limits
+        .check_top_k(body.top_k)
+        .and_then(|_| data.limits.check_filter(body.filter.as_ref()))
+    {
+        return HttpResponse::UnprocessableEntity().body(e);
+    }
+    if let Err(e) = coll.check_vector(&body.query) {
+        return HttpResponse::BadRequest().body(format!("query: {}", e));
+    }
+    if let Err(e) = check_chunk_options(&coll, body).and_then(|_| body.params.validate()) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let envelope = body.envelope.unwrap_or(data.response.envelope);
+    let grants = auth::grants(req);
+    let filter = Visible::new(body.filter.as_ref(), grants.as_ref());
+    let mut params = body.params.clone();
+    if let Some(degraded) = req.extensions().get::<Degraded>() {
+        let ef_search = params.ef_search.unwrap_or(coll.config.hnsw.ef_search);
+        params.ef_search = Some(ef_search.min(degraded.ef_search));
+        params.exact = false;
+    }
+    let (params, threshold) = (&params, body.score_threshold);
+    let groups = body
+        .group_by_parent
+        .as_ref()
+        .map(|g| coll.search_parents(&body.query, body.top_k, filter, params, threshold, g));
+    let results = match (&groups, &body.recommend) {
+        (Some(groups), _) => groups.iter().flat_map(|g| g.hits.iter().copied()).collect(),
+        (None, Some(examples)) => {
+            coll.recommend(&body.query, examples, body.top_k, filter, params, threshold)
+        }
+        (None, None) => coll.search_with(&body.query, body.top_k, filter, params, threshold),
+    };
+    if data.query_log.enabled() {
+        data.query_log.record(&QueryEntry {
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            query_id: request_id::get(req),
+            collection: name,
+            top_k: body.top_k,
+            query: data.query_log.with_vectors.then_some(body.query.as_slice()),
+            filter: body.filter.as_ref(),
+            ids: results.iter().map(|(id, _)| *id).collect(),
+            took_ms: started.elapsed().as_secs_f64() * 1000.0,
+        });
+    }
+    let with_payload = body.with_payload || body.payload_selector.is_some();
+    let objects = with_payload || target.is_some() || groups.is_some() || body.context > 0;
+    match verbosity {
+        Verbosity::Ids => match groups {
+            Some(groups) => {
+                let ids: Vec<u64> = groups.iter().map(|g| g.parent_id).collect();
+                response::respond(ids, envelope, started)
+            }
+            None => {
+                let ids: Vec<u64> = results.into_iter().map(|(id, _)| id).collect();
+                response::respond(ids, envelope, started)
+            }
+        },
+        Verbosity::Scores if !objects => {
+            let pairs: Vec<(u64, f32)> = results
+                .into_iter()
+                .map(|(id, distance)| (id, coll.score(distance)))
+                .collect();
+            response::respond(pairs, envelope, started)
+        }
+        Verbosity::Scores | Verbosity::Full => {
+            let full = verbosity == Verbosity::Full;
+            let decrypt = auth::decrypts(req);
+            let selector = body.payload_selector.as_ref();
+            let select = |payload: &serde_json::Value| coll.shown(payload, selector, decrypt);
+            let mut keys = Vec::new();
+            let mut points: Vec<ScoredPoint> = results
+                .into_iter()
+                .map(|(id, distance)| {
+                    let record = coll.get(id);
+                    if let Some((join, _)) = &target {
+                        keys.push(record.and_then(|r| join.key(&r.payload)));
+                    }
+                    let context = record.filter(|_| body.context > 0).map(|r| {
+                        let (mut before, mut after) = coll.context_ids(r, body.context);
+                        let readable = |id: &u64| coll.get(*id).is_some_and(|r| filter.may_read(r));
+                        before.retain(readable);
+                        after.retain(readable);
+                        let chunk = |id| {
+                            let payload = coll.get(id).map(|r| select(&r.payload));
+                            ContextChunk { id, payload }
+                        };
+                        Context {
+                            before: before.into_iter().map(chunk).collect(),
+                            after: after.into_iter().map(chunk).collect(),
+                        }
+                    });
+                    ScoredPoint {
+                        id,
+                        distance,
+                        score: coll.score(distance),
+                        payload: record
+                            .filter(|_| with_payload || full)
+                            .map(|r| select(&r.payload)),
+                        vector: record.filter(|_| full).map(|r| r.vector.clone()),
+                        context,
+                        joined: None,
+                    }
+                })
+                .collect();
+            let metric = coll.config.distance.clone();
+            drop(coll);
+            if let Some((join, target)) = target {
+                let target = target.read().unwrap();
+                for (point, key) in points.iter_mut().zip(keys) {
+                    point.joined = Some(join.payload(&target, key, grants.as_ref(), decrypt));
+                }
+            }
+            let Some(groups) = groups else {
+                return response::respond(points, envelope, started);
+            };
+            let mut points = points.into_iter();
+            let parents: Vec<ParentHit> = groups
+                .into_iter()
+                .map(|g| ParentHit {
+                    parent_id: g.parent_id,
+                    distance: g.distance,
+                    score: distance::score(&metric, g.distance),
+                    chunks: g.chunks,
+                    hits: points.by_ref().take(g.hits.len()).collect(),
+                })
+                .collect();
+            response::respond(parents, envelope, started)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TemplateInfo<'t> {
+    template: &'t Template,
+    parameters: Vec<String>,
+}
+
+async fn list_templates<'a>(
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let Some(coll) = data.collection(path.as_str()) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let coll = coll.read().unwrap();
+    let templates: BTreeMap<&String, TemplateInfo> = coll
+        .config
+        .templates
+        .iter()
+        .map(|(name, t)| {
+            let info = TemplateInfo {
+                template: t,
+                parameters: template::parameters(t),
+            };
+            (name, info)
+        })
+        .collect();
+    HttpResponse::Ok().json(templates)
+}
+
+/// Stores a named search. Templates without parameters are checked as complete searches.
+async fn put_template<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<(String, String)>,
+    body: web::Json<Template>,
+) -> impl Responder {
+    let (name, template_name) = path.into_inner();
+    let t = body.into_inner();
+    if let Err(e) = template::validate_name(&template_name).and_then(|_| template::check(&t)) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if template::parameters(&t).is_empty() {
+        if let Err(e) = template_search(&t, Vec::new(), &serde_json::Map::new()) {
+            return HttpResponse::BadRequest().body(e);
+        }
+    }
+    update_config(&req, &data, &name, |config| {
+        config.templates.insert(template_name, t);
+        None
+    })
+}
+
+async fn delete_template<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (name, template_name) = path.into_inner();
+    update_config(&req, &data, &name, |config| {
+        let used_by = config
+            .experiments
+            .iter()
+            .find(|(_, e)| e.buckets.iter().any(|b| b.template == template_name));
+        if let Some((experiment, _)) = used_by {
+            return Some(
+                HttpResponse::Conflict()
+                    .body(format!("Template is used by experiment {}", experiment)),
+            );
+        }
+        match config.templates.remove(&template_name) {
+            Some(_) => None,
+            None => Some(HttpResponse::NotFound().body("Template not found")),
+        }
+    })
+}
+
+/// Applies `change` to a collection's config and saves its manifest. `change` returns the error
+/// response when the change cannot be made.
+fn update_config(
+    req: &HttpRequest,
+    data: &AppState,
+    name: &str,
+    change: impl FnOnce(&mut CollectionConfig) -> Option<HttpResponse>,
+) -> HttpResponse {
+    let Some(coll) = data.collection(name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let mut coll = coll.write().unwrap();
+    if let Some(resp) = change(&mut coll.config) {
+        return resp;
+    }
+    coll.revision = next_revision();
+    let manifest = Manifest {
+        name: name.to_string(),
+        dim: coll.dim,
+        config: coll.config.clone(),
+    };
+    if let Err(e) = data.storage.update(&manifest) {
+        log::error!("could not update manifest for {}: {}", name, e);
+        return HttpResponse::InternalServerError()
+            .body("Config was changed but could not be saved");
+    }
+    data.audit.record(req, Some(name), None);
+    HttpResponse::NoContent().finish()
+}
+
+/// Sets the collection's `write_throttle`; writes already held back keep their place.
+async fn put_throttle<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<ThrottleConfig>,
+) -> impl Responder {
+    let throttle = body.into_inner();
+    if let Err(e) = throttle.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    update_config(&req, &data, &path, |config| {
+        config.write_throttle = Some(throttle);
+        None
+    })
+}
+
+async fn put_fsync<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+    body: web::Json<FsyncPolicy>,
+) -> impl Responder {
+    let fsync = body.into_inner();
+    if let Err(e) = fsync.validate() {
+        return HttpResponse::BadRequest().body(e);
     }
+    update_config(&req, &data, &path, |config| {
+        config.fsync = fsync;
+        None
+    })
+}
+
+async fn delete_throttle<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    update_config(&req, &data, &path, |config| {
+        match config.write_throttle.take() {
+            Some(_) => None,
+            None => Some(HttpResponse::NotFound().body("Collection has no write throttle")),
+        }
+    })
 }
 
 #[derive(Deserialize)]
-struct SearchBody {
+struct TemplateSearchBody {
+    query: Vec<f32>,
+    #[serde(default)]
+    params: serde_json::Map<String, serde_json::Value>,
+}
+
+async fn search_template<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<(String, String)>,
+    body: web::Json<TemplateSearchBody>,
+) -> impl Responder {
+    let started = Instant::now();
+    let (name, template_name) = path.into_inner();
+    let Some(shared) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let coll = shared.read().unwrap();
+    let Some(t) = coll.config.templates.get(&template_name) else {
+        return HttpResponse::NotFound().body("Template not found");
+    };
+    let TemplateSearchBody { query, params } = body.into_inner();
+    let search = template_search(t, query, &params);
+    drop(coll);
+    match search {
+        Ok(search) => search_response(&req, &data, &name, &shared, &search, started),
+        Err(e) => HttpResponse::UnprocessableEntity().body(e),
+    }
+}
+
+async fn list_experiments<'a>(
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match data.collection(path.as_str()) {
+        Some(coll) => HttpResponse::Ok().json(&coll.read().unwrap().config.experiments),
+        None => HttpResponse::NotFound().body("Collection not found"),
+    }
+}
+
+async fn put_experiment<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<(String, String)>,
+    body: web::Json<Experiment>,
+) -> impl Responder {
+    let (name, experiment_name) = path.into_inner();
+    let experiment = body.into_inner();
+    update_config(&req, &data, &name, |config| {
+        if let Err(e) = config.check_experiment(&experiment_name, &experiment) {
+            return Some(HttpResponse::BadRequest().body(e));
+        }
+        config.experiments.insert(experiment_name, experiment);
+        None
+    })
+}
+
+async fn delete_experiment<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (name, experiment_name) = path.into_inner();
+    update_config(&req, &data, &name, |config| {
+        match config.experiments.remove(&experiment_name) {
+            Some(_) => None,
+            None => Some(HttpResponse::NotFound().body("Experiment not found")),
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct ExperimentSearchBody {
+    /// Stable per-user value, e.g. a hashed user id, that picks the bucket.
+    user_key: String,
+    query: Vec<f32>,
+    #[serde(default)]
+    params: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Runs the template of the caller's bucket; the bucket is returned in `X-Experiment-Bucket`.
+async fn search_experiment<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<(String, String)>,
+    body: web::Json<ExperimentSearchBody>,
+) -> impl Responder {
+    let started = Instant::now();
+    let (name, experiment_name) = path.into_inner();
+    let Some(shared) = data.collection(&name) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    let coll = shared.read().unwrap();
+    let Some(experiment) = coll.config.experiments.get(&experiment_name) else {
+        return HttpResponse::NotFound().body("Experiment not found");
+    };
+    let ExperimentSearchBody {
+        user_key,
+        query,
+        params,
+    } = body.into_inner();
+    let bucket = experiment.assign(&experiment_name, &user_key);
+    // Templates in use by an experiment cannot be deleted.
+    let t = &coll.config.templates[&bucket.template];
+    let search = template_search(t, query, &params);
+    let bucket = bucket.name.clone();
+    drop(coll);
+    let mut resp = match search {
+        Ok(search) => search_response(&req, &data, &name, &shared, &search, started),
+        Err(e) => HttpResponse::UnprocessableEntity().body(e),
+    };
+    if let Ok(value) = HeaderValue::from_str(&bucket) {
+        resp.headers_mut().insert(experiment::HEADER, value);
+    }
+    resp
+}
+
+/// The search `t` describes for `query` and `params`.
+fn template_search(
+    t: &Template,
     query: Vec<f32>,
+    params: &serde_json::Map<String, serde_json::Value>,
+) -> Result<SearchBody, String> {
+    let mut rendered = template::render(t, params)?;
+    rendered.insert("query".to_string(), query.into());
+    serde_json::from_value(serde_json::Value::Object(rendered)).map_err(|e| e.to_string())
+}
+
+#[derive(Deserialize)]
+struct WhatIfBody {
+    queries: Vec<Vec<f32>>,
     top_k: usize,
+    #[serde(default)]
+    filter: Option<Filter>,
+    parameter_sets: Vec<ParameterSet>,
 }
 
-async fn search_vectors<'a>(
+#[derive(Serialize)]
+struct WhatIfResponse {
+    queries: usize,
+    /// Mean latency of the exact searches recall is measured against.
+    exact_mean_ms: f64,
+    outcomes: Vec<Outcome>,
+}
+
+/// Replays `queries` under each parameter set and reports recall against exact search and
+/// latency, without changing the collection.
+async fn what_if(
+    data: web::Data<AppState<'static>>,
+    path: web::Path<String>,
+    body: web::Json<WhatIfBody>,
+) -> impl Responder {
+    let body = body.into_inner();
+    if let Err(e) = data
+        .limits
+        .check_top_k(body.top_k)
+        .and_then(|_| data.limits.check_batch("query", body.queries.len()))
+        .and_then(|_| data.limits.check_filter(body.filter.as_ref()))
+    {
+        return HttpResponse::UnprocessableEntity().body(e);
+    }
+    if body.parameter_sets.is_empty() || body.parameter_sets.len() > what_if::MAX_PARAMETER_SETS {
+        return HttpResponse::BadRequest().body(format!(
+            "parameter_sets must have 1 to {} entries",
+            what_if::MAX_PARAMETER_SETS
+        ));
+    }
+    for (i, parameters) in body.parameter_sets.iter().enumerate() {
+        if let Err(e) = parameters.validate() {
+            return HttpResponse::BadRequest().body(format!("parameter_sets[{}]: {}", i, e));
+        }
+    }
+    let Some(coll) = data.collection(path.as_str()) else {
+        return HttpResponse::NotFound().body("Collection not found");
+    };
+    // Replaying many queries can take a while, so it runs off the worker thread.
+    let evaluated = web::block(move || {
+        let coll = coll.read().unwrap();
+        for (i, query) in body.queries.iter().enumerate() {
+            coll.check_vector(query)
+                .map_err(|e| format!("queries[{}]: {}", i, e))?;
+        }
+        let filter = Visible::new(body.filter.as_ref(), None);
+        let mut exact_ms = 0.0;
+        let exact: Vec<Vec<(u64, f32)>> = body
+            .queries
+            .iter()
+            .map(|query| {
+                let started = Instant::now();
+                let hits = coll.exact_search(query, body.top_k, coll.records.iter(), filter);
+                exact_ms += started.elapsed().as_secs_f64() * 1000.0;
+                hits
+            })
+            .collect();
+        let outcomes = body
+            .parameter_sets
+            .into_iter()
+            .map(|parameters| {
+                let ef_search = parameters.ef_search.unwrap_or(coll.config.hnsw.ef_search);
+                let mut samples = Samples::default();
+                for (query, exact) in body.queries.iter().zip(&exact) {
+                    let started = Instant::now();
+                    let found = coll.search_ef(query, body.top_k, filter, ef_search);
+                    samples.record(&found, exact, started.elapsed());
+                }
+                samples.finish(parameters)
+            })
+            .collect();
+        Ok::<_, String>(WhatIfResponse {
+            queries: body.queries.len(),
+            exact_mean_ms: exact_ms / body.queries.len().max(1) as f64,
+            outcomes,
+        })
+    })
+    .await;
+    match evaluated {
+        Ok(Ok(resp)) => HttpResponse::Ok().json(resp),
+        Ok(Err(e)) => HttpResponse::BadRequest().body(e),
+        Err(_) => HttpResponse::InternalServerError().body("What-if evaluation failed"),
+    }
+}
+
+#[derive(Deserialize)]
+struct FeedbackBody {
+    /// `X-Request-Id` of the search the feedback is about.
+    query_id: String,
+    #[serde(default)]
+    clicked: Vec<u64>,
+    #[serde(default)]
+    accepted: Vec<u64>,
+}
+
+async fn record_feedback<'a>(
     data: web::Data<AppState<'a>>,
     path: web::Path<String>,
-    body: web::Json<SearchBody>,
+    body: web::Json<FeedbackBody>,
 ) -> impl Responder {
-    let collections = data.collections.lock().unwrap();
-    if let Some(coll) = collections.get(&path.into_inner()) {
-        let results = coll.search(body.query.clone(), body.top_k);
-        HttpResponse::Ok().json(results)
-    } else {
-        HttpResponse::NotFound().body("Collection not found")
+    let name = path.into_inner();
+    let FeedbackBody {
+        query_id,
+        clicked,
+        accepted,
+    } = body.into_inner();
+    if query_id.is_empty() || query_id.len() > 128 {
+        return HttpResponse::BadRequest().body("query_id must be 1-128 characters");
+    }
+    if clicked.is_empty() && accepted.is_empty() {
+        return HttpResponse::BadRequest().body("feedback needs clicked or accepted ids");
+    }
+    if let Err(e) = data
+        .limits
+        .check_batch("id", clicked.len() + accepted.len())
+    {
+        return HttpResponse::UnprocessableEntity().body(e);
+    }
+    // Held so the collection cannot be deleted while its log is written.
+    let collections = data.collections.read().unwrap();
+    if !collections.contains_key(&name) {
+        return HttpResponse::NotFound().body("Collection not found");
+    }
+    let event = FeedbackEvent {
+        ts_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        query_id,
+        clicked,
+        accepted,
+    };
+    let recorded = with_feedback_log(&data, &name, |log| log.record(&event));
+    match recorded {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            log::error!("could not record feedback for {}: {}", name, e);
+            HttpResponse::InternalServerError().body("Could not record feedback")
+        }
+    }
+}
+
+async fn export_feedback<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let name = path.into_inner();
+    // Held so the collection cannot be deleted while its log is written.
+    let collections = data.collections.read().unwrap();
+    if !collections.contains_key(&name) {
+        return HttpResponse::NotFound().body("Collection not found");
     }
+    let exported = with_feedback_log(&data, &name, |log| {
+        let version = etag::versioned(log.recorded);
+        match etag::not_modified(&req, &version) {
+            Some(resp) => Ok(resp),
+            None => Ok(etag::ok(&version)
+                .content_type("application/x-ndjson")
+                .body(log.export()?)),
+        }
+    });
+    exported.unwrap_or_else(|e| {
+        HttpResponse::InternalServerError().body(format!("Failed to read feedback: {}", e))
+    })
+}
+
+/// Runs `f` on the feedback log of collection `name`, opening it if needed.
+fn with_feedback_log<T>(
+    data: &AppState,
+    name: &str,
+    f: impl FnOnce(&mut FeedbackLog) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut logs = data.feedback.lock().unwrap();
+    let log = match logs.entry(name.to_string()) {
+        Entry::Occupied(e) => e.into_mut(),
+        Entry::Vacant(e) => e.insert(FeedbackLog::open(data.storage.feedback_path(name))?),
+    };
+    f(log)
 }
 
-async fn list_collections<'a>(data: web::Data<AppState<'a>>) -> impl Responder {
-    let collections = data.collections.lock().unwrap();
+async fn list_collections<'a>(req: HttpRequest, data: web::Data<AppState<'a>>) -> impl Responder {
+    let collections = data.collections.read().unwrap();
+    let version = etag::versioned(data.catalog_version.load(Ordering::SeqCst));
+    if let Some(resp) = etag::not_modified(&req, &version) {
+        return resp;
+    }
     let names: Vec<String> = collections.keys().cloned().collect();
-    HttpResponse::Ok().json(names)
+    etag::ok(&version).json(names)
+}
+
+async fn export_audit<'a>(req: HttpRequest, data: web::Data<AppState<'a>>) -> impl Responder {
+    let Some(path) = data.audit.path() else {
+        return HttpResponse::NotFound().body("Audit log is disabled");
+    };
+    // The log is append-only, so its size and mtime identify its contents.
+    let version = std::fs::metadata(path).map(|m| {
+        let mtime = m
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos());
+        format!("{}-{}", m.len(), mtime)
+    });
+    if let Some(resp) = version
+        .as_deref()
+        .ok()
+        .and_then(|v| etag::not_modified(&req, v))
+    {
+        return resp;
+    }
+    let mut resp = match &version {
+        Ok(v) => etag::ok(v),
+        Err(_) => HttpResponse::Ok(),
+    };
+    match std::fs::read(path) {
+        Ok(contents) => resp.content_type("application/x-ndjson").body(contents),
+        Err(e) => {
+            HttpResponse::InternalServerError().body(format!("Failed to read audit log: {}", e))
+        }
+    }
+}
+
+fn api_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/collections", auth::read(web::get().to(list_collections)))
+        .route("/collections", auth::manage(web::post().to(create_collection)))
+        .route("/collections/{name}", auth::read(web::get().to(collection_info)))
+        .route("/collections/{name}", auth::manage(web::delete().to(delete_collection)))
+        .route("/collections/{name}/upsert", auth::write(web::post().to(upsert_vectors)))
+        .route("/collections/{name}/vectors", auth::write(web::post().to(update_vectors)))
+        .route("/collections/{name}/backfill", auth::write(web::post().to(backfill_payload)))
+        .route("/collections/{name}/points/{id}", auth::read(web::get().to(get_point)))
+        .route("/collections/{name}/points/get", auth::read(web::post().to(get_points)))
+        .route("/collections/{name}/scroll", auth::read(web::post().to(scroll_points)))
+        .route("/collections/{name}/feedback", auth::write(web::post().to(record_feedback)))
+        .route("/collections/{name}/feedback", auth::read(web::get().to(export_feedback)))
+        .route("/collections/{name}/search", auth::search(web::post().to(search_vectors)))
+        .route("/collections/{name}/recommend", auth::search(web::post().to(recommend_points)))
+        .route("/collections/{name}/templates", auth::read(web::get().to(list_templates)))
+        .route("/collections/{name}/templates/{template}", auth::manage(web::put().to(put_template)))
+        .route("/collections/{name}/templates/{template}", auth::manage(web::delete().to(delete_template)))
+        .route("/collections/{name}/templates/{template}/search", auth::search(web::post().to(search_template)))
+        .route("/collections/{name}/experiments", auth::read(web::get().to(list_experiments)))
+        .route("/collections/{name}/experiments/{experiment}", auth::manage(web::put().to(put_experiment)))
+        .route("/collections/{name}/experiments/{experiment}", auth::manage(web::delete().to(delete_experiment)))
+        .route("/collections/{name}/experiments/{experiment}/search", auth::search(web::post().to(search_experiment)))
+        .route("/collections/{name}/delete", auth::write(web::post().to(delete_points)))
+        .route("/collections/{name}/index", auth::manage(web::put().to(create_index)))
+        .route("/collections/{name}/clone", auth::manage(web::post().to(clone_collection)))
+        .route("/trash", auth::admin(web::get().to(list_trash)))
+        .route("/trash/{id}/restore", auth::admin(web::post().to(restore_trashed)))
+        .route("/trash/{id}", auth::admin(web::delete().to(purge_trashed)))
+        .service(
+            web::scope("")
+                .wrap(from_fn(listen::admin_only))
+                .wrap(from_fn(auth::admin_only))
+                .configure(admin_routes),
+        );
+}
+
+/// Operational routes, served only on the admin listener when one is configured.
+fn admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/collections/{name}/shadow", web::put().to(attach_shadow))
+        .route("/collections/{name}/shadow", web::get().to(shadow_status))
+        .route("/collections/{name}/shadow", web::delete().to(detach_shadow))
+        .route("/collections/{name}/reindex", web::post().to(reindex_collection))
+        .route("/collections/{name}/what-if", web::post().to(what_if))
+        .route("/collections/{name}/freeze", web::post().to(freeze_collection))
+        .route("/collections/{name}/throttle", web::put().to(put_throttle))
+        .route("/collections/{name}/throttle", web::delete().to(delete_throttle))
+        .route("/collections/{name}/fsync", web::put().to(put_fsync))
+        .route("/operations", web::get().to(list_operations))
+        .route("/operations/{id}", web::get().to(operation_status))
+        .route("/operations/{id}", web::delete().to(cancel_operation))
+        .route("/collections/{name}/snapshot", web::post().to(snapshot_collection))
+        .route("/collections/{name}/restore", web::post().to(restore_collection))
+        .route("/snapshots/verify", web::post().to(verify_snapshot))
+        .route("/recovery", web::get().to(recovery_report))
+        .route("/memory", web::get().to(memory_status))
+        .route("/usage", web::get().to(usage_report))
+        .route("/usage/reset", web::post().to(reset_usage))
+        .route("/reload", web::post().to(reload))
+        .route("/tokens", web::post().to(mint_token))
+        .route("/audit", web::get().to(export_audit));
+}
+
+async fn memory_status(guard: web::Data<MemoryGuard>) -> impl Responder {
+    HttpResponse::Ok().json(guard.status())
+}
+
+async fn usage_report(usage: web::Data<Usage>) -> impl Responder {
+    HttpResponse::Ok().json(usage.report())
+}
+
+/// Ends the accounting period, answering with its usage.
+async fn reset_usage(usage: web::Data<Usage>) -> impl Responder {
+    HttpResponse::Ok().json(usage.reset())
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    collection: String,
+    actions: Vec<auth::Action>,
+    /// Defaults to 15 minutes.
+    ttl_secs: Option<u64>,
+    /// Point tags the holder may see; unset means every point.
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct TokenResponse {
+    token: String,
+    /// Names the token in `GET /usage`.
+    key_id: String,
+    expires_ms: u64,
+}
+
+/// Mints a token for clients that should not hold an API key: the given actions on one
+/// collection, until it expires.
+async fn mint_token<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    api_keys: web::Data<ApiKeys>,
+    tokens: web::Data<Tokens>,
+    body: web::Json<TokenRequest>,
+) -> impl Responder {
+    let body = body.into_inner();
+    if !api_keys.enabled() {
+        return HttpResponse::BadRequest()
+            .body("Tokens need authentication; set API_KEY or API_KEYS");
+    }
+    if data.collection(&body.collection).is_none() {
+        return HttpResponse::NotFound().body("Collection not found");
+    }
+    if body.actions.is_empty() {
+        return HttpResponse::BadRequest()
+            .body("actions must name at least one of search, read or write");
+    }
+    let ttl = match body.ttl_secs {
+        Some(secs) => Duration::from_secs(secs),
+        None => token::DEFAULT_TTL,
+    };
+    if ttl.is_zero() || ttl > tokens.max_ttl {
+        return HttpResponse::BadRequest().body(format!(
+            "ttl_secs must be between 1 and {}",
+            tokens.max_ttl.as_secs()
+        ));
+    }
+    data.audit.record(&req, Some(&body.collection), None);
+    let claims = Claims::new(body.collection, body.actions, body.tags, ttl);
+    let token = tokens.mint(&claims);
+    HttpResponse::Ok().json(TokenResponse {
+        key_id: auth::KeyId::of(&token).0,
+        token,
+        expires_ms: claims.expires * 1000,
+    })
+}
+
+#[derive(Serialize)]
+struct ReloadResponse {
+    config_file: Option<String>,
+    log_level: String,
+    api_keys: usize,
+    hnsw: HnswParams,
+}
+
+/// Reads the config file again and applies the settings that can change without a restart:
+/// `RUST_LOG`, the API keys and the HNSW defaults of new collections. When any of them is
+/// invalid the running settings are all kept.
+fn reload_config(api_keys: &ApiKeys) -> Result<ReloadResponse, String> {
+    let config_file = config::reload()?;
+    let keys = ApiKeys::from_env()?;
+    let hnsw = HnswParams::defaults_from_env()
+        .and_then(|hnsw| hnsw.validate().map(|_| hnsw))
+        .map_err(|e| format!("HNSW defaults: {}", e))?;
+    let log_level = logging::reload();
+    if api_keys.enabled() && !keys.enabled() {
+        log::warn!("API_KEY and API_KEYS are no longer set; requests are not authenticated");
+    }
+    api_keys.replace(keys);
+    *HNSW_DEFAULTS.write().unwrap() = hnsw.clone();
+    log::info!("Reloaded configuration");
+    Ok(ReloadResponse {
+        config_file: config_file.map(|p| p.display().to_string()),
+        log_level: log_level.to_string(),
+        api_keys: api_keys.count(),
+        hnsw,
+    })
+}
+
+/// `POST /reload`: what SIGHUP does, reporting the settings now in effect.
+async fn reload<'a>(
+    req: HttpRequest,
+    data: web::Data<AppState<'a>>,
+    api_keys: web::Data<ApiKeys>,
+) -> impl Responder {
+    match reload_config(&api_keys) {
+        Ok(reloaded) => {
+            data.audit.record(&req, None, None);
+            HttpResponse::Ok().json(reloaded)
+        }
+        Err(e) => {
+            log::error!("could not reload the configuration: {}", e);
+            HttpResponse::InternalServerError().body(format!("Configuration not reloaded: {}", e))
+        }
+    }
+}
+
+/// Reloads the configuration on every SIGHUP.
+#[cfg(unix)]
+async fn reload_on_hangup(api_keys: web::Data<ApiKeys>) {
+    use actix_web::rt::signal::unix::{signal, SignalKind};
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            log::warn!("cannot reload on SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(e) = reload_config(&api_keys) {
+            log::error!("could not reload the configuration: {}", e);
+        }
+    }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
-    let port: u16 = env::var("PORT").unwrap_or_else(|_| "5202".to_string()).parse().unwrap();
+    let config_file =
+        config::load().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    logging::init();
+    if let Some(path) = config_file {
+        log::info!("Read configuration from {}", path.display());
+    }
+    let hnsw_defaults = HnswParams::defaults_from_env()
+        .and_then(|hnsw| hnsw.validate().map(|_| hnsw))
+        .map_err(|e| format!("HNSW defaults: {}", e))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    *HNSW_DEFAULTS.write().unwrap() = hnsw_defaults;
+    let listeners = Listeners::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let tls = Tls::from_env(listeners.public)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
 
+    let field_key = cipher::Cipher::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    if let Some(key) = field_key {
+        cipher::set_field_key(key);
+    }
+    let storage = Storage::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let pending = storage.pending().map_err(std::io::Error::other)?;
+    let startup = web::Data::new(startup::Startup::new(
+        pending.iter().map(|p| (p.name.clone(), p.wal_bytes)),
+    ));
     let state = web::Data::new(AppState {
-        collections: Mutex::new(HashMap::new()),
+        collections: RwLock::new(HashMap::new()),
+        catalog_version: AtomicU64::new(0),
+        recovery: Mutex::new(Vec::new()),
+        audit: AuditLog::from_env()?,
+        query_log: QueryLog::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        id_gen: IdGenerator::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        response: ResponseOptions::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        limits: Limits::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        storage,
+        snapshots: Snapshots::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        operations: Operations::default(),
+        feedback: Mutex::new(HashMap::new()),
     });
+    let allowlist = web::Data::new(
+        Allowlist::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+    );
+    let api_keys = web::Data::new(
+        ApiKeys::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+    );
+    if !api_keys.enabled() {
+        log::warn!("API_KEY and API_KEYS are unset; requests are not authenticated");
+    }
+    let memory_guard = web::Data::new(
+        MemoryGuard::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+    );
+    let usage = web::Data::new(
+        Usage::from_env().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+    );
+    let tokens = web::Data::new(
+        Tokens::from_env().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+    );
+
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    log::info!("Server running on {}://{}", scheme, listeners.public);
+    if let Some(admin) = listeners.admin {
+        log::info!("Admin listener on {}", admin);
+    }
+    let listen_data = web::Data::new(listeners.clone());
+    let https_policy = web::Data::new(tls.as_ref().map(HttpsPolicy::from));
+    let json_config = web::JsonConfig::default().limit(state.limits.max_body_bytes);
 
-    println!("Server running on 127.0.0.1:{}", port);
+    #[cfg(feature = "graphql")]
+    let schema = web::Data::new(graphql::schema(state.clone()));
 
-    HttpServer::new(move || {
-        App::new()
+    let shutdown_state = state.clone();
+    let loading = startup.clone();
+    let reload_keys = api_keys.clone();
+    let server = HttpServer::new(move || {
+        let app = App::new()
             .app_data(state.clone())
-            .route("/collections", web::get().to(list_collections))
-            .route("/collections", web::post().to(create_collection))
-            .route("/collections/{name}/upsert", web::post().to(upsert_vectors))
-            .route("/collections/{name}/search", web::post().to(search_vectors))
-    })
-    .bind(("127.0.0.1", port))?
-    .run()
-    .await
+            .app_data(allowlist.clone())
+            .app_data(listen_data.clone())
+            .app_data(api_keys.clone())
+            .app_data(memory_guard.clone())
+            .app_data(usage.clone())
+            .app_data(tokens.clone())
+            .app_data(https_policy.clone())
+            .app_data(json_config.clone())
+            .app_data(startup.clone())
+            .wrap(from_fn(startup::gate))
+            .wrap(from_fn(memory::enforce))
+            .wrap(from_fn(usage::track))
+            .wrap(from_fn(auth::enforce))
+            .wrap(from_fn(network::enforce))
+            .wrap(from_fn(error::to_json))
+            .wrap_fn(|req, srv| {
+                let id = request_id::assign(&req);
+                let fut = request_id::sync_scope(id.clone(), || srv.call(req));
+                request_id::scope(id.clone(), async move {
+                    let mut res = fut.await?;
+                    if let Ok(value) = HeaderValue::from_str(&id) {
+                        res.headers_mut().insert(request_id::HEADER, value);
+                    }
+                    Ok(res)
+                })
+            })
+            .wrap(from_fn(tls::enforce_https))
+            .wrap(Logger::new(
+                r#"%a "%r" %s %b %Dms request_id=%{x-request-id}o"#,
+            ))
+            .route("/startup", auth::read(web::get().to(startup::progress)))
+            .route("/readyz", web::get().to(startup::ready))
+            .service(
+                web::scope(api_version::PREFIX)
+                    .wrap(from_fn(api_version::negotiate))
+                    .configure(api_routes),
+            );
+        #[cfg(feature = "graphql")]
+        let app = app.app_data(schema.clone()).configure(graphql::routes);
+        // Unversioned routes are kept as a compatibility shim for pre-/v1 clients.
+        app.service(
+            web::scope("")
+                .wrap(from_fn(api_version::negotiate))
+                .configure(api_routes),
+        )
+    });
+    let mut server = match &tls {
+        Some(tls) => server.bind_rustls_0_23(listeners.public, tls.config.clone())?,
+        None => server.bind(listeners.public)?,
+    };
+    if let Some(admin) = listeners.admin {
+        server = match &tls {
+            Some(tls) => server.bind_rustls_0_23(admin, tls.config.clone())?,
+            None => server.bind(admin)?,
+        };
+    }
+    if let Some(redirect) = tls.as_ref().and_then(|tls| tls.redirect) {
+        server = server.bind(redirect)?;
+        log::info!("Redirecting HTTP on {} to HTTPS", redirect);
+    }
+    if let Some(unix) = &listeners.unix {
+        #[cfg(unix)]
+        {
+            unix.remove_stale()?;
+            server = server.bind_uds(&unix.path)?;
+            unix.apply_mode()?;
+            log::info!("Listening on unix socket {}", unix.path.display());
+        }
+        #[cfg(not(unix))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("cannot listen on {}: unix sockets are not supported", unix.path.display()),
+        ));
+    }
+    let load_state = shutdown_state.clone();
+    std::thread::Builder::new()
+        .name("load-collections".to_string())
+        .spawn(move || load_collections(&load_state, &loading, pending))?;
+    #[cfg(unix)]
+    actix_web::rt::spawn(reload_on_hangup(reload_keys));
+    if shutdown_state.storage.enabled() {
+        actix_web::rt::spawn(sync_wals(shutdown_state.clone()));
+    }
+    if let Some(every) = shutdown_state.storage.checkpoint_every() {
+        actix_web::rt::spawn(checkpoint_wals(shutdown_state.clone(), every));
+    }
+    // Stops on SIGTERM or SIGINT once in-flight requests finish, then flushes what they wrote.
+    server.run().await?;
+    flush_all(&shutdown_state);
+    Ok(())
+}
+
+/// Loads the stored collections one by one while the server already answers `/startup` and
+/// `/readyz`, then marks startup complete. Storage that cannot be read at all ends the process, as
+/// it would have stopped startup.
+fn load_collections(data: &AppState, startup: &startup::Startup, pending: Vec<storage::Pending>) {
+    for (i, pending) in pending.iter().enumerate() {
+        startup.update(i, |p| p.state = startup::LoadState::Replaying);
+        if let Err(e) = load_collection(data, startup, i, pending) {
+            log::error!("could not load collection {}: {}", pending.name, e);
+            std::process::exit(1);
+        }
+        startup.log_progress();
+    }
+    startup.finish();
+}
+
+fn load_collection(
+    data: &AppState,
+    startup: &startup::Startup,
+    i: usize,
+    pending: &storage::Pending,
+) -> Result<(), String> {
+    let (stored, recovered) = data.storage.load_one(pending)?;
+    data.recovery.lock().unwrap().extend(recovered);
+    let Some((manifest, records, wal)) = stored else {
+        startup.update(i, |p| p.state = startup::LoadState::Quarantined);
+        return Ok(());
+    };
+    let points = records.len();
+    startup.update(i, |p| {
+        p.state = startup::LoadState::Indexing;
+        p.points = Some(points);
+    });
+    let progress = |n: usize| {
+        if n.is_multiple_of(1024) {
+            startup.update(i, |p| p.indexed = n);
+        }
+    };
+    match Collection::restore_with_progress(manifest.config, manifest.dim, records, progress) {
+        Ok(mut coll) => {
+            coll.wal = Some(wal);
+            let mut collections = data.collections.write().unwrap();
+            collections.insert(manifest.name, Arc::new(RwLock::new(coll)));
+            drop(collections);
+            data.catalog_version.fetch_add(1, Ordering::SeqCst);
+            startup.update(i, |p| {
+                p.state = startup::LoadState::Loaded;
+                p.indexed = points;
+            });
+        }
+        // One collection that cannot be rebuilt must not keep the others offline.
+        Err(e) => {
+            drop(wal);
+            let quarantined = data.storage.quarantine(&manifest.name, e)?;
+            data.recovery.lock().unwrap().push(quarantined);
+            startup.update(i, |p| p.state = startup::LoadState::Quarantined);
+        }
+    }
+    Ok(())
+}
+
+/// Syncs the WALs of collections with the `interval` fsync policy as their writes come due.
+async fn sync_wals(data: web::Data<AppState<'static>>) {
+    loop {
+        let collections: Vec<(String, SharedCollection)> = data
+            .collections
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, coll)| (name.clone(), coll.clone()))
+            .collect();
+        // Collections created meanwhile are picked up within a second.
+        let mut next = Duration::from_secs(1);
+        for (name, coll) in collections {
+            let wait = coll.read().unwrap().fsync_wait();
+            match wait {
+                Some(wait) if wait.is_zero() => {
+                    if let Err(e) = coll.write().unwrap().flush() {
+                        log::error!("could not sync the WAL of {}: {}", name, e);
+                    }
+                }
+                Some(wait) => next = next.min(wait),
+                None => {}
+            }
+        }
+        actix_web::rt::time::sleep(next).await;
+    }
+}
+
+/// Checkpoints the WAL of every collection that needs it, each `every`.
+async fn checkpoint_wals(data: web::Data<AppState<'static>>, every: Duration) {
+    let mut ticks = actix_web::rt::time::interval(every);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let collections: Vec<(String, SharedCollection)> = data
+            .collections
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, coll)| (name.clone(), coll.clone()))
+            .collect();
+        for (name, coll) in collections {
+            match coll.write().unwrap().checkpoint() {
+                Ok(Some(stale)) => {
+                    log::info!("checkpointed {}, dropping {} WAL entries", name, stale)
+                }
+                Ok(None) => {}
+                Err(e) => log::error!("could not checkpoint {}: {}", name, e),
+            }
+        }
+    }
+}
+
+/// Forces every collection's WAL and feedback log to disk before the process exits.
+fn flush_all(data: &AppState) {
+    let collections = data.collections.read().unwrap();
+    for (name, coll) in collections.iter() {
+        if let Err(e) = coll.write().unwrap().flush() {
+            log::error!("could not flush {}: {}", name, e);
+        }
+    }
+    for (name, log) in data.feedback.lock().unwrap().iter() {
+        if let Err(e) = log.sync() {
+            log::error!("could not flush the feedback log of {}: {}", name, e);
+        }
+    }
+    log::info!("flushed {} collections", collections.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn collection<'a>() -> Collection<'a> {
+        let config = serde_json::from_value(json!({"distance": "cosine"})).unwrap();
+        let mut coll = Collection::new(config, 2);
+        let vectors = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let payloads = vec![json!({}), json!({})];
+        let tags = vec![vec!["x".to_string()], vec!["y".to_string()]];
+        coll.upsert(vec![1, 2], vectors, payloads, tags, Visible::default());
+        coll
+    }
+
+    #[test]
+    fn writes_leave_hidden_points_alone() {
+        let mut coll = collection();
+        let grants = Grants::new(["x".to_string()]);
+        let visible = Visible::new(None, Some(&grants));
+        let results = coll.upsert(
+            vec![1, 2],
+            vec![vec![0.5, 0.5], vec![0.5, 0.5]],
+            vec![json!({}), json!({})],
+            vec![vec!["x".to_string()], vec!["x".to_string()]],
+            visible,
+        );
+        assert_eq!(results[0].status, "updated");
+        assert_eq!(results[1].status, "failed");
+        assert_eq!(coll.get(2).unwrap().tags, ["y"]);
+        let results = coll.update_vectors(vec![2], vec![vec![1.0, 1.0]], visible);
+        assert_eq!(results[0].reason.as_deref(), Some("point not found"));
+        let updated = coll.set_payload_field("a", vec![(2, json!(1))], visible);
+        assert!(updated.is_empty());
+        assert_eq!(coll.delete(&[1, 2], visible), [true, false]);
+        assert_eq!(coll.get(2).unwrap().vector, [0.0, 1.0]);
+        assert_eq!(coll.delete(&[2], Visible::default()), [true]);
+    }
+
+    #[test]
+    fn writes_need_granted_tags() {
+        let grants = Grants::new(["x".to_string()]);
+        assert!(grants.covers(&["x".to_string()]));
+        assert!(!grants.covers(&["x".to_string(), "y".to_string()]));
+        assert!(!grants.covers(&[]));
+    }
+
+    #[test]
+    fn encrypted_fields_are_stored_sealed() {
+        cipher::set_field_key(cipher::Cipher::new(&[7; 32]));
+        let config = json!({"distance": "cosine", "encrypted_fields": ["ssn"]});
+        let config: CollectionConfig = serde_json::from_value(config).unwrap();
+        config.validate(2).unwrap();
+        let mut coll = Collection::new(config, 2);
+        let payloads = vec![json!({"ssn": "1234", "lang": "en"})];
+        let vectors = vec![vec![1.0, 0.0]];
+        coll.upsert(vec![1], vectors, payloads, vec![vec![]], Visible::default());
+        let stored = &coll.get(1).unwrap().payload;
+        let sealed = stored["ssn"].as_str().unwrap();
+        assert!(cipher::is_sealed(sealed.as_bytes()));
+        assert_eq!(stored["lang"], "en");
+        assert_eq!(coll.shown(stored, None, true)["ssn"], "1234");
+        assert_eq!(coll.shown(stored, None, false)["ssn"], stored["ssn"]);
+        let updated = coll.set_payload_field("ssn", vec![(1, json!(42))], Visible::default());
+        assert_eq!(updated, [1]);
+        let stored = &coll.get(1).unwrap().payload;
+        assert!(stored["ssn"].is_string());
+        assert_eq!(coll.shown(stored, None, true)["ssn"], 42);
+    }
+
+    #[test]
+    fn encrypted_fields_cannot_be_looked_up() {
+        cipher::set_field_key(cipher::Cipher::new(&[7; 32]));
+        let check = |config: serde_json::Value| {
+            let config: CollectionConfig = serde_json::from_value(config).unwrap();
+            config.validate(2)
+        };
+        let indexed = json!({
+            "distance": "cosine",
+            "encrypted_fields": ["ssn"],
+            "indexes": {"ssn.last4": "keyword"}
+        });
+        assert!(check(indexed).is_err());
+        let nested = json!({"distance": "cosine", "encrypted_fields": ["a.b"]});
+        assert!(check(nested).is_err());
+        let chunks = json!({
+            "distance": "cosine",
+            "encrypted_fields": ["doc"],
+            "chunks": {"parent_field": "doc"}
+        });
+        assert!(check(chunks).is_err());
+    }
 }
@@ -0,0 +1,232 @@
+use crate::{error::ApiError, request_id::RequestId};
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{HeaderName, HeaderValue},
+        Method,
+    },
+    middleware::Next,
+    web, Error, HttpMessage, HttpResponse,
+};
+use serde::Serialize;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Memory use is re-read at most this often, when requests arrive.
+const SAMPLE_EVERY: Duration = Duration::from_millis(500);
+
+/// Error code of requests refused under hard pressure.
+const CODE: &str = "memory_pressure";
+
+/// Marks searches served with degraded parameters under soft pressure.
+const DEGRADED_HEADER: HeaderName = HeaderName::from_static("x-search-degraded");
+
+/// Route suffixes of searches, which are degraded under soft pressure.
+const SEARCHES: &[&str] = &["/search", "/recommend"];
+
+/// Route suffixes also refused under hard pressure: other per-request allocations, and writes
+/// that grow the collections.
+const GROWING: &[&str] = &[
+    "/graphql",
+    "/what-if",
+    "/upsert",
+    "/vectors",
+    "/backfill",
+    "/clone",
+    "/restore",
+];
+
+#[derive(Clone, Copy, PartialEq, Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Pressure {
+    Normal,
+    /// Over the soft limit: searches are degraded.
+    Soft,
+    /// Over the hard limit: searches and growing writes are refused.
+    Hard,
+}
+
+/// Stored on searches made under soft pressure.
+#[derive(Clone, Copy)]
+pub struct Degraded {
+    /// Most `ef_search` the search may use; exact scans are off too.
+    pub ef_search: usize,
+}
+
+/// Process memory limits, checked against the resident set size.
+pub struct MemoryGuard {
+    soft_limit: Option<u64>,
+    hard_limit: Option<u64>,
+    degraded_ef_search: usize,
+    sample: Mutex<Option<Sample>>,
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    at: Instant,
+    rss: u64,
+    pressure: Pressure,
+}
+
+#[derive(Serialize)]
+pub struct MemoryStatus {
+    /// `null` where the resident set size cannot be read.
+    pub rss_bytes: Option<u64>,
+    pub soft_limit_bytes: Option<u64>,
+    pub hard_limit_bytes: Option<u64>,
+    pub pressure: Pressure,
+}
+
+impl MemoryGuard {
+    /// Reads `MEMORY_SOFT_LIMIT_BYTES`, `MEMORY_HARD_LIMIT_BYTES` and
+    /// `MEMORY_DEGRADED_EF_SEARCH` (default 16). Without limits the guard is off.
+    pub fn from_env() -> Result<Self, String> {
+        let soft_limit = env_u64("MEMORY_SOFT_LIMIT_BYTES")?;
+        let hard_limit = env_u64("MEMORY_HARD_LIMIT_BYTES")?;
+        let degraded_ef_search = env_u64("MEMORY_DEGRADED_EF_SEARCH")?.unwrap_or(16) as usize;
+        if let (Some(soft), Some(hard)) = (soft_limit, hard_limit) {
+            if soft >= hard {
+                return Err(
+                    "MEMORY_SOFT_LIMIT_BYTES must be below MEMORY_HARD_LIMIT_BYTES".to_string(),
+                );
+            }
+        }
+        if degraded_ef_search == 0 {
+            return Err("MEMORY_DEGRADED_EF_SEARCH must be greater than 0".to_string());
+        }
+        let guard = Self {
+            soft_limit,
+            hard_limit,
+            degraded_ef_search,
+            sample: Mutex::new(None),
+        };
+        if guard.enabled() && read_rss().is_none() {
+            return Err("memory limits need /proc/self/status to measure memory use".to_string());
+        }
+        Ok(guard)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.soft_limit.is_some() || self.hard_limit.is_some()
+    }
+
+    /// The latest sample, re-read when older than `SAMPLE_EVERY`.
+    fn sample(&self) -> Option<Sample> {
+        let mut sample = self.sample.lock().unwrap();
+        if sample.is_some_and(|s| s.at.elapsed() < SAMPLE_EVERY) {
+            return *sample;
+        }
+        let rss = read_rss()?;
+        let pressure = if self.hard_limit.is_some_and(|limit| rss >= limit) {
+            Pressure::Hard
+        } else if self.soft_limit.is_some_and(|limit| rss >= limit) {
+            Pressure::Soft
+        } else {
+            Pressure::Normal
+        };
+        let previous = sample.map_or(Pressure::Normal, |s| s.pressure);
+        if pressure != previous {
+            log::warn!(
+                "memory pressure {:?} -> {:?} at {} bytes resident",
+                previous,
+                pressure,
+                rss
+            );
+        }
+        *sample = Some(Sample {
+            at: Instant::now(),
+            rss,
+            pressure,
+        });
+        *sample
+    }
+
+    pub fn status(&self) -> MemoryStatus {
+        let sample = self.sample();
+        MemoryStatus {
+            rss_bytes: sample.map(|s| s.rss),
+            soft_limit_bytes: self.soft_limit,
+            hard_limit_bytes: self.hard_limit,
+            pressure: sample.map_or(Pressure::Normal, |s| s.pressure),
+        }
+    }
+}
+
+fn env_u64(key: &str) -> Result<Option<u64>, String> {
+    match std::env::var(key) {
+        Ok(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} must be a non-negative integer, got {:?}", key, v)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The process's resident set size, from `VmRSS` in `/proc/self/status`.
+fn read_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// Refuses searches and growing writes with 503 and code `memory_pressure` while memory use is
+/// over the hard limit, and degrades searches over the soft limit, marking their responses
+/// with `X-Search-Degraded`.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let guard = req.app_data::<web::Data<MemoryGuard>>();
+    let Some(guard) = guard.filter(|g| g.enabled()).cloned() else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let path = req.path();
+    let search = SEARCHES.iter().any(|suffix| path.ends_with(suffix));
+    let growing = GROWING.iter().any(|suffix| path.ends_with(suffix));
+    if req.method() == Method::GET || !(search || growing) {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let Some(sample) = guard.sample() else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    match sample.pressure {
+        Pressure::Hard => {
+            let error = ApiError {
+                status: 503,
+                code: CODE.to_string(),
+                message: format!(
+                    "Memory use of {} bytes is over the limit of {}; retry later",
+                    sample.rss,
+                    guard.hard_limit.unwrap_or_default()
+                ),
+                request_id: req
+                    .extensions()
+                    .get::<RequestId>()
+                    .map(|r| r.0.clone())
+                    .unwrap_or_default(),
+            };
+            let resp = HttpResponse::ServiceUnavailable().json(error);
+            Ok(req.into_response(resp).map_into_right_body())
+        }
+        Pressure::Soft if search => {
+            req.extensions_mut().insert(Degraded {
+                ef_search: guard.degraded_ef_search,
+            });
+            let mut res = next.call(req).await?;
+            res.headers_mut()
+                .insert(DEGRADED_HEADER, HeaderValue::from_static(CODE));
+            Ok(res.map_into_left_body())
+        }
+        _ => Ok(next.call(req).await?.map_into_left_body()),
+    }
+}
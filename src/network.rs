@@ -0,0 +1,105 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use std::net::IpAddr;
+
+/// An address range such as `10.0.0.0/8` or `fd00::/8`. A bare address is a single-host range.
+#[derive(Clone, Copy)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address in CIDR {:?}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("invalid prefix length in CIDR {:?}", s))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Dual-stack sockets report IPv4 clients as `::ffff:a.b.c.d`.
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            v4 => v4,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(u32::from(net).into(), u32::from(ip).into(), self.prefix, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(u128::from(net), u128::from(ip), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_eq(a: u128, b: u128, prefix: u8, bits: u8) -> bool {
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    a >> shift == b >> shift
+}
+
+/// Client address ranges allowed to reach the server. Empty means no restriction.
+#[derive(Clone, Default)]
+pub struct Allowlist {
+    ranges: Vec<Cidr>,
+}
+
+impl Allowlist {
+    /// Reads `ALLOWED_CIDRS`, a comma-separated list of ranges.
+    pub fn from_env() -> Result<Self, String> {
+        let Ok(v) = std::env::var("ALLOWED_CIDRS") else {
+            return Ok(Self::default());
+        };
+        let ranges = v
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(Cidr::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("ALLOWED_CIDRS: {}", e))?;
+        Ok(Self { ranges })
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|r| r.contains(ip))
+    }
+}
+
+/// Rejects connections from outside the allowlist with 403. Uses the socket peer address rather
+/// than forwarding headers, which clients can set freely.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let allowed = match (req.app_data::<web::Data<Allowlist>>(), req.peer_addr()) {
+        (Some(list), Some(peer)) => list.allows(peer.ip()),
+        _ => true,
+    };
+    if !allowed {
+        let resp = HttpResponse::Forbidden().body("Client address is not allowed");
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
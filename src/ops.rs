@@ -0,0 +1,163 @@
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Finished operations kept for status queries; older ones are forgotten.
+const KEEP_FINISHED: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpState {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+/// A long-running background task, such as an index rebuild.
+pub struct Operation {
+    pub id: u64,
+    pub kind: &'static str,
+    pub collection: String,
+    total: usize,
+    done: AtomicUsize,
+    cancel: AtomicBool,
+    started: Instant,
+    outcome: Mutex<Outcome>,
+}
+
+#[derive(Clone)]
+struct Outcome {
+    state: OpState,
+    error: Option<String>,
+    /// Run time, fixed once the operation finishes.
+    took: Option<Duration>,
+}
+
+#[derive(Serialize)]
+pub struct OperationStatus {
+    pub id: u64,
+    pub kind: &'static str,
+    pub collection: String,
+    pub state: OpState,
+    pub done: usize,
+    pub total: usize,
+    pub elapsed_ms: u64,
+    /// Estimated time to completion, extrapolated from the progress so far.
+    pub eta_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl Operation {
+    pub fn set_done(&self, done: usize) {
+        self.done.store(done, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    pub fn finish(&self, state: OpState, error: Option<String>) {
+        *self.outcome.lock().unwrap() = Outcome {
+            state,
+            error,
+            took: Some(self.started.elapsed()),
+        };
+    }
+
+    pub fn state(&self) -> OpState {
+        self.outcome.lock().unwrap().state
+    }
+
+    pub fn status(&self) -> OperationStatus {
+        let Outcome { state, error, took } = self.outcome.lock().unwrap().clone();
+        let done = self.done.load(Ordering::Relaxed);
+        let elapsed = took.unwrap_or_else(|| self.started.elapsed());
+        let eta_ms = (state == OpState::Running && done > 0).then(|| {
+            let per_point = elapsed.as_secs_f64() / done as f64;
+            (per_point * self.total.saturating_sub(done) as f64 * 1000.0) as u64
+        });
+        OperationStatus {
+            id: self.id,
+            kind: self.kind,
+            collection: self.collection.clone(),
+            state,
+            done,
+            total: self.total,
+            elapsed_ms: elapsed.as_millis() as u64,
+            eta_ms,
+            error,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Operations {
+    next_id: AtomicU64,
+    ops: Mutex<BTreeMap<u64, Arc<Operation>>>,
+}
+
+impl Operations {
+    /// Registers a running operation over `total` items, unless one of the same kind is already
+    /// running on the collection.
+    pub fn start(
+        &self,
+        kind: &'static str,
+        collection: &str,
+        total: usize,
+    ) -> Result<Arc<Operation>, u64> {
+        let mut ops = self.ops.lock().unwrap();
+        if let Some(running) = ops.values().find(|op| {
+            op.kind == kind && op.collection == collection && op.state() == OpState::Running
+        }) {
+            return Err(running.id);
+        }
+        let finished: Vec<u64> = ops
+            .values()
+            .filter(|op| op.state() != OpState::Running)
+            .map(|op| op.id)
+            .collect();
+        let excess = finished.len().saturating_sub(KEEP_FINISHED - 1);
+        for id in &finished[..excess] {
+            ops.remove(id);
+        }
+        let op = Arc::new(Operation {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            kind,
+            collection: collection.to_string(),
+            total,
+            done: AtomicUsize::new(0),
+            cancel: AtomicBool::new(false),
+            started: Instant::now(),
+            outcome: Mutex::new(Outcome {
+                state: OpState::Running,
+                error: None,
+                took: None,
+            }),
+        });
+        ops.insert(op.id, op.clone());
+        Ok(op)
+    }
+
+    pub fn list(&self) -> Vec<OperationStatus> {
+        let ops = self.ops.lock().unwrap();
+        ops.values().map(|op| op.status()).collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Operation>> {
+        self.ops.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Asks a running operation to stop; it finishes as cancelled at its next check.
+    pub fn cancel(&self, id: u64) -> Option<Arc<Operation>> {
+        let op = self.get(id)?;
+        op.cancel.store(true, Ordering::Relaxed);
+        Some(op)
+    }
+}
@@ -0,0 +1,171 @@
+use crate::cipher::{self, Cipher};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Key of the reference [`offload`] leaves in place of a value: `{"$blob": id, "bytes": n}`.
+const BLOB: &str = "$blob";
+
+/// Chooses which top-level payload fields are returned: `{"include": [..]}` or `{"exclude": [..]}`.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadSelector {
+    Include(Vec<String>),
+    Exclude(Vec<String>),
+}
+
+impl PayloadSelector {
+    pub fn apply(&self, payload: &Value) -> Value {
+        let Value::Object(map) = payload else {
+            return payload.clone();
+        };
+        let keep = |k: &String| match self {
+            PayloadSelector::Include(fields) => fields.contains(k),
+            PayloadSelector::Exclude(fields) => !fields.contains(k),
+        };
+        Value::Object(
+            map.iter()
+                .filter(|(k, _)| keep(k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        )
+    }
+}
+
+/// A point id stored in a payload, as a number or a numeric string.
+pub fn as_id(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Sets the dotted `field` of `payload` to `value`, creating objects along the way and replacing
+/// anything in the way that is not one.
+pub fn set_path(payload: &mut Value, field: &str, value: Value) {
+    let mut target = payload;
+    for part in field.split('.') {
+        if !target.is_object() {
+            *target = Value::Object(serde_json::Map::new());
+        }
+        target = target
+            .as_object_mut()
+            .expect("just made an object")
+            .entry(part)
+            .or_insert(Value::Null);
+    }
+    *target = value;
+}
+
+/// Replaces each of the top-level `fields` of `payload` with the sealed JSON of its value, as a
+/// string. Values already sealed, e.g. copied from another collection, are kept as they are, and
+/// so are blob references, which only ever hold values sealed before they were moved out.
+pub fn seal_fields(payload: &mut Value, fields: &[String], cipher: &Cipher) {
+    let Value::Object(map) = payload else {
+        return;
+    };
+    for field in fields {
+        let Some(value) = map.get_mut(field) else {
+            continue;
+        };
+        let sealed = value
+            .as_str()
+            .is_some_and(|s| cipher::is_sealed(s.as_bytes()));
+        if sealed || blob_ref(value).is_some() {
+            continue;
+        }
+        let json = serde_json::to_vec(value).expect("payload values serialize");
+        *value = Value::String(String::from_utf8(cipher.seal(&json)).expect("sealed as base64"));
+    }
+}
+
+/// Opens the sealed `fields` of `payload` back into their values. Values that cannot be opened
+/// are left sealed.
+pub fn open_fields(payload: &mut Value, fields: &[String], cipher: &Cipher) {
+    let Value::Object(map) = payload else {
+        return;
+    };
+    for field in fields {
+        let Some(value) = map.get_mut(field) else {
+            continue;
+        };
+        let opened = value
+            .as_str()
+            .and_then(|s| cipher.open(s.as_bytes()).ok())
+            .and_then(|json| serde_json::from_slice(&json).ok());
+        if let Some(opened) = opened {
+            *value = opened;
+        }
+    }
+}
+
+/// Whether `field`, a dotted key, is one of the top-level `fields` or lies inside one.
+pub fn within(field: &str, fields: &[String]) -> bool {
+    let top = field.split('.').next().unwrap_or_default();
+    fields.iter().any(|f| f == top)
+}
+
+/// Moves the top-level values of `payload` whose JSON is over `threshold` bytes out with `put`,
+/// leaving a reference to the id it returns in their place. Fields `keep` picks stay.
+pub fn offload(
+    payload: &mut Value,
+    threshold: usize,
+    keep: impl Fn(&str) -> bool,
+    mut put: impl FnMut(&[u8]) -> Result<String, String>,
+) -> Result<(), String> {
+    let Value::Object(map) = payload else {
+        return Ok(());
+    };
+    for (field, value) in map.iter_mut() {
+        if keep(field) || blob_ref(value).is_some() {
+            continue;
+        }
+        let json = serde_json::to_vec(value).expect("payload values serialize");
+        if json.len() > threshold {
+            let id = put(&json)?;
+            *value = serde_json::json!({ BLOB: id, "bytes": json.len() });
+        }
+    }
+    Ok(())
+}
+
+/// Fetches the values [`offload`] moved out of the top-level fields `wanted` picks back in with
+/// `get`.
+pub fn inline(
+    payload: &mut Value,
+    wanted: impl Fn(&str) -> bool,
+    get: impl Fn(&str) -> Result<Vec<u8>, String>,
+) -> Result<(), String> {
+    let Value::Object(map) = payload else {
+        return Ok(());
+    };
+    for (field, value) in map.iter_mut() {
+        if let Some(id) = blob_ref(value)
+            .filter(|_| wanted(field))
+            .map(str::to_string)
+        {
+            let json = get(&id)?;
+            *value = serde_json::from_slice(&json).map_err(|e| format!("blob {}: {}", id, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// The blob id of a value [`offload`] moved out.
+pub fn blob_ref(value: &Value) -> Option<&str> {
+    let Value::Object(map) = value else {
+        return None;
+    };
+    match (map.len(), map.get(BLOB), map.get("bytes")) {
+        (2, Some(Value::String(id)), Some(Value::Number(_))) => Some(id),
+        _ => None,
+    }
+}
+
+/// The blob ids `payload` refers to.
+pub fn blob_refs(payload: &Value) -> impl Iterator<Item = &str> {
+    payload
+        .as_object()
+        .into_iter()
+        .flat_map(|map| map.values().filter_map(blob_ref))
+}
@@ -0,0 +1,365 @@
+use crate::filter::{Filter, Test};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Bound,
+};
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexKind {
+    /// Exact string values.
+    Keyword,
+    Integer,
+    Float,
+}
+
+/// Indexed payload fields, keyed by (dotted) field name, each mapping values to point ids.
+#[derive(Default)]
+pub struct PayloadIndex {
+    fields: HashMap<String, FieldIndex>,
+}
+
+struct FieldIndex {
+    values: Values,
+    /// Stored values the index cannot hold, such as numbers in a keyword index. While there are
+    /// any, lookups give no candidates, since filters may still match them.
+    unindexed: usize,
+}
+
+enum Values {
+    Keyword(HashMap<String, HashSet<u64>>),
+    /// Integers are stored as floats, the type range filters compare in.
+    Numeric(IndexKind, BTreeMap<Key, HashSet<u64>>),
+}
+
+/// Total order over f64 for use as a map key.
+#[derive(Clone, Copy, PartialEq)]
+struct Key(f64);
+
+impl Key {
+    /// Folds `-0.0` into `0.0`, which filters treat as equal but the total order does not.
+    fn new(x: f64) -> Self {
+        Key(if x == 0.0 { 0.0 } else { x })
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PayloadIndex {
+    pub fn new(declared: &BTreeMap<String, IndexKind>) -> Self {
+        let mut index = Self::default();
+        for (field, kind) in declared {
+            index.declare(field, *kind);
+        }
+        index
+    }
+
+    /// Adds an empty index on `field`, replacing any existing one. Points must be re-inserted to
+    /// populate it.
+    pub fn declare(&mut self, field: &str, kind: IndexKind) {
+        let values = match kind {
+            IndexKind::Keyword => Values::Keyword(HashMap::new()),
+            kind => Values::Numeric(kind, BTreeMap::new()),
+        };
+        let index = FieldIndex {
+            values,
+            unindexed: 0,
+        };
+        self.fields.insert(field.to_string(), index);
+    }
+
+    pub fn insert(&mut self, id: u64, payload: &Value) {
+        self.update(payload, true, |ids| {
+            ids.insert(id);
+        });
+    }
+
+    pub fn remove(&mut self, id: u64, payload: &Value) {
+        self.update(payload, false, |ids| {
+            ids.remove(&id);
+        });
+    }
+
+    /// Applies `apply` to the id set of every indexed value in `payload`, dropping sets it empties,
+    /// and counts the values that cannot be indexed in or out as `inserting` says.
+    fn update(
+        &mut self,
+        payload: &Value,
+        inserting: bool,
+        mut apply: impl FnMut(&mut HashSet<u64>),
+    ) {
+        for (field, index) in &mut self.fields {
+            for value in field_values(payload, field) {
+                let indexed = match &mut index.values {
+                    Values::Keyword(map) => match value {
+                        Value::String(s) => {
+                            let ids = map.entry(s.clone()).or_default();
+                            apply(ids);
+                            if ids.is_empty() {
+                                map.remove(s);
+                            }
+                            true
+                        }
+                        _ => false,
+                    },
+                    Values::Numeric(kind, map) => match numeric(*kind, value) {
+                        Some(x) => {
+                            let ids = map.entry(x).or_default();
+                            apply(ids);
+                            if ids.is_empty() {
+                                map.remove(&x);
+                            }
+                            true
+                        }
+                        None => false,
+                    },
+                };
+                if !indexed && inserting {
+                    index.unindexed += 1;
+                } else if !indexed {
+                    index.unindexed = index.unindexed.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    /// Ids that can possibly match `filter`, from its `must` conditions on indexed fields, or
+    /// `None` when no condition can be answered from an index.
+    pub fn candidates(&self, filter: &Filter) -> Option<HashSet<u64>> {
+        let mut result: Option<HashSet<u64>> = None;
+        for condition in &filter.must {
+            let Some(index) = self.fields.get(&condition.key) else {
+                continue;
+            };
+            let Some(ids) = index.lookup(&condition.test) else {
+                continue;
+            };
+            result = Some(match result {
+                Some(acc) => acc.intersection(&ids).copied().collect(),
+                None => ids,
+            });
+        }
+        result
+    }
+}
+
+impl FieldIndex {
+    /// The ids matching `test`, or `None` when the index cannot tell: some stored values were not
+    /// indexed, or the test compares against values of another type than the index holds.
+    fn lookup(&self, test: &Test) -> Option<HashSet<u64>> {
+        if self.unindexed > 0 {
+            return None;
+        }
+        let mut ids = HashSet::new();
+        match (&self.values, test) {
+            (Values::Keyword(map), Test::Match(v)) => {
+                ids.extend(map.get(v.as_str()?).into_iter().flatten());
+            }
+            (Values::Keyword(map), Test::In(values)) => {
+                for v in values {
+                    ids.extend(map.get(v.as_str()?).into_iter().flatten());
+                }
+            }
+            (Values::Numeric(_, map), Test::Match(v)) => {
+                ids.extend(map.get(&Key::new(v.as_f64()?)).into_iter().flatten());
+            }
+            (Values::Numeric(_, map), Test::In(values)) => {
+                for v in values {
+                    ids.extend(map.get(&Key::new(v.as_f64()?)).into_iter().flatten());
+                }
+            }
+            (Values::Numeric(_, map), Test::Range(range)) => {
+                let lower = match (range.gt, range.gte) {
+                    (Some(gt), _) => Bound::Excluded(Key::new(gt)),
+                    (None, Some(gte)) => Bound::Included(Key::new(gte)),
+                    (None, None) => Bound::Unbounded,
+                };
+                let upper = match (range.lt, range.lte) {
+                    (Some(lt), _) => Bound::Excluded(Key::new(lt)),
+                    (None, Some(lte)) => Bound::Included(Key::new(lte)),
+                    (None, None) => Bound::Unbounded,
+                };
+                if !is_valid_range(&lower, &upper) {
+                    return Some(ids);
+                }
+                for set in map.range((lower, upper)).map(|(_, set)| set) {
+                    ids.extend(set);
+                }
+            }
+            (Values::Keyword(_), Test::Range(_)) => return None,
+        }
+        Some(ids)
+    }
+}
+
+/// `BTreeMap::range` panics on inverted bounds, which an empty range filter can produce.
+fn is_valid_range(lower: &Bound<Key>, upper: &Bound<Key>) -> bool {
+    match (lower, upper) {
+        (Bound::Included(a), Bound::Included(b)) => a <= b,
+        (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => a < b,
+        _ => true,
+    }
+}
+
+pub fn validate_field(field: &str) -> Result<(), String> {
+    if field.split('.').any(str::is_empty) {
+        return Err(format!("invalid index field {:?}", field));
+    }
+    Ok(())
+}
+
+fn field_values<'v>(payload: &'v Value, field: &str) -> &'v [Value] {
+    match field.split('.').try_fold(payload, |v, part| v.get(part)) {
+        Some(Value::Array(items)) => items.as_slice(),
+        Some(other) => std::slice::from_ref(other),
+        None => &[],
+    }
+}
+
+/// The key of a stored value, for every number filters compare equal to it: integer indexes
+/// take floats with an integral value, such as `2.0`, too.
+fn numeric(kind: IndexKind, value: &Value) -> Option<Key> {
+    let x = value.as_f64()?;
+    match kind {
+        IndexKind::Integer if x.fract() != 0.0 => None,
+        _ => Some(Key::new(x)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn index(kind: IndexKind, payloads: &[Value]) -> PayloadIndex {
+        let mut index = PayloadIndex::new(&BTreeMap::from([("n".to_string(), kind)]));
+        for (id, payload) in payloads.iter().enumerate() {
+            index.insert(id as u64, payload);
+        }
+        index
+    }
+
+    fn filter(filter: Value) -> Filter {
+        serde_json::from_value(filter).unwrap()
+    }
+
+    /// Ids whose payload matches, by scanning them all.
+    fn scanned(payloads: &[Value], filter: &Filter) -> HashSet<u64> {
+        (0..payloads.len() as u64)
+            .filter(|&id| filter.matches(&payloads[id as usize]))
+            .collect()
+    }
+
+    /// The candidates must hold every point the filter matches, or be `None` for a full scan.
+    fn assert_complete(index: &PayloadIndex, payloads: &[Value], filter: &Filter) {
+        if let Some(candidates) = index.candidates(filter) {
+            let missing: Vec<_> = scanned(payloads, filter)
+                .difference(&candidates)
+                .copied()
+                .collect();
+            assert!(missing.is_empty(), "candidates miss {:?}", missing);
+        }
+    }
+
+    #[test]
+    fn keyword_lookup() {
+        let payloads = [
+            json!({"n": "a"}),
+            json!({"n": ["a", "b"]}),
+            json!({"n": "c"}),
+        ];
+        let index = index(IndexKind::Keyword, &payloads);
+        let matched = index.candidates(&filter(json!({"must": [{"key": "n", "match": "a"}]})));
+        assert_eq!(matched, Some(HashSet::from([0, 1])));
+        let any = index.candidates(&filter(json!({"must": [{"key": "n", "in": ["b", "c"]}]})));
+        assert_eq!(any, Some(HashSet::from([1, 2])));
+    }
+
+    #[test]
+    fn keyword_index_on_numbers_falls_back_to_scan() {
+        let payloads = [json!({"n": 1}), json!({"n": 2}), json!({"n": "2"})];
+        let index = index(IndexKind::Keyword, &payloads);
+        let f = filter(json!({"must": [{"key": "n", "match": 2}]}));
+        assert_eq!(index.candidates(&f), None);
+        assert_complete(&index, &payloads, &f);
+    }
+
+    #[test]
+    fn keyword_filter_on_other_type_falls_back_to_scan() {
+        let payloads = [json!({"n": "a"})];
+        let index = index(IndexKind::Keyword, &payloads);
+        assert_eq!(
+            index.candidates(&filter(json!({"must": [{"key": "n", "match": 1}]}))),
+            None
+        );
+        let mixed = filter(json!({"must": [{"key": "n", "in": ["a", 1]}]}));
+        assert_eq!(index.candidates(&mixed), None);
+    }
+
+    #[test]
+    fn integer_index_holds_integral_floats() {
+        let payloads = [json!({"n": 2}), json!({"n": 2.0}), json!({"n": 3})];
+        let index = index(IndexKind::Integer, &payloads);
+        let f = filter(json!({"must": [{"key": "n", "match": 2}]}));
+        assert_eq!(index.candidates(&f), Some(HashSet::from([0, 1])));
+        let f = filter(json!({"must": [{"key": "n", "match": 2.0}]}));
+        assert_eq!(index.candidates(&f), Some(HashSet::from([0, 1])));
+    }
+
+    #[test]
+    fn integer_index_with_fractions_falls_back_to_scan() {
+        let payloads = [json!({"n": 2}), json!({"n": 2.5})];
+        let index = index(IndexKind::Integer, &payloads);
+        let f = filter(json!({"must": [{"key": "n", "range": {"gte": 2, "lte": 3}}]}));
+        assert_eq!(index.candidates(&f), None);
+        assert_complete(&index, &payloads, &f);
+    }
+
+    #[test]
+    fn unindexed_values_stop_counting_once_removed() {
+        let payloads = [json!({"n": 1}), json!({"n": "x"})];
+        let mut index = index(IndexKind::Float, &payloads);
+        let f = filter(json!({"must": [{"key": "n", "match": 1}]}));
+        assert_eq!(index.candidates(&f), None);
+        index.remove(1, &payloads[1]);
+        assert_eq!(index.candidates(&f), Some(HashSet::from([0])));
+    }
+
+    #[test]
+    fn negative_zero_matches_zero() {
+        let payloads = [json!({"n": 0.0}), json!({"n": -0.0})];
+        let index = index(IndexKind::Float, &payloads);
+        for f in [
+            filter(json!({"must": [{"key": "n", "match": 0}]})),
+            filter(json!({"must": [{"key": "n", "range": {"lte": -0.0}}]})),
+        ] {
+            assert_eq!(index.candidates(&f), Some(HashSet::from([0, 1])));
+            assert_complete(&index, &payloads, &f);
+        }
+    }
+
+    #[test]
+    fn range_lookup_and_inverted_range() {
+        let payloads = [json!({"n": 1.5}), json!({"n": 4}), json!({"n": [7, 9]})];
+        let index = index(IndexKind::Float, &payloads);
+        let f = filter(json!({"must": [{"key": "n", "range": {"gt": 1.5, "lte": 8}}]}));
+        assert_eq!(index.candidates(&f), Some(HashSet::from([1, 2])));
+        let empty = filter(json!({"must": [{"key": "n", "range": {"gt": 5, "lt": 5}}]}));
+        assert_eq!(index.candidates(&empty), Some(HashSet::new()));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::filter::Filter;
+use serde::Serialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// One line of the query log. Carries nothing about the caller, and the query vector only when
+/// `QUERY_LOG_VECTORS` is set.
+#[derive(Serialize)]
+pub struct QueryEntry<'r> {
+    pub ts_ms: u64,
+    pub query_id: String,
+    pub collection: &'r str,
+    pub top_k: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<&'r [f32]>,
+    pub filter: Option<&'r Filter>,
+    pub ids: Vec<u64>,
+    pub took_ms: f64,
+}
+
+/// NDJSON log of searches for offline analysis, enabled by setting `QUERY_LOG` to a file path.
+/// The file is rotated to `{path}.1`, `{path}.2`, .. once it reaches `QUERY_LOG_MAX_BYTES`.
+pub struct QueryLog {
+    writer: Option<Mutex<Writer>>,
+    pub with_vectors: bool,
+}
+
+struct Writer {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    /// Rotated files kept besides the current one.
+    keep: usize,
+}
+
+impl QueryLog {
+    /// Reads `QUERY_LOG`, `QUERY_LOG_MAX_BYTES` (default 100 MiB), `QUERY_LOG_FILES` (rotated
+    /// files kept, default 5) and `QUERY_LOG_VECTORS` (default false).
+    pub fn from_env() -> Result<Self, String> {
+        let with_vectors = env_parse("QUERY_LOG_VECTORS", false)?;
+        let path = match std::env::var("QUERY_LOG") {
+            Ok(path) if !path.is_empty() => PathBuf::from(path),
+            _ => {
+                return Ok(Self {
+                    writer: None,
+                    with_vectors,
+                })
+            }
+        };
+        let max_bytes = env_parse("QUERY_LOG_MAX_BYTES", 100 * 1024 * 1024)?;
+        let keep = env_parse("QUERY_LOG_FILES", 5)?;
+        let file = open(&path).map_err(|e| format!("QUERY_LOG {}: {}", path.display(), e))?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            writer: Some(Mutex::new(Writer {
+                path,
+                file,
+                written,
+                max_bytes,
+                keep,
+            })),
+            with_vectors,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Appends a search. Failures are reported but never fail the search itself.
+    pub fn record(&self, entry: &QueryEntry) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let mut line = serde_json::to_vec(entry).expect("query entry serializes");
+        line.push(b'\n');
+        if let Err(e) = writer.lock().unwrap().write(&line) {
+            log::error!("[{}] failed to write query log: {}", entry.query_id, e);
+        }
+    }
+}
+
+impl Writer {
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn env_parse<T: std::str::FromStr>(var: &str, default: T) -> Result<T, String> {
+    match std::env::var(var) {
+        Ok(v) => v.parse().map_err(|_| format!("invalid {} {:?}", var, v)),
+        Err(_) => Ok(default),
+    }
+}
@@ -0,0 +1,77 @@
+use crate::distance;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+/// A point to recommend like or unlike: the id of a stored point or a raw vector.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Example {
+    Id(u64),
+    Vector(Vec<f32>),
+}
+
+/// How the examples become a search.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// One search around the mean of the positives, pushed away from the mean of the negatives.
+    #[default]
+    AverageVector,
+    /// One search per positive; candidates rank by their nearest positive, and those nearer to a
+    /// negative than to any positive come last.
+    BestScore,
+}
+
+/// `POST /collections/{name}/recommend`: `{"positive": [..], "negative": [..], "top_k": 10}`.
+#[derive(Deserialize)]
+pub struct RecommendBody {
+    #[serde(default)]
+    pub positive: Vec<Example>,
+    #[serde(default)]
+    pub negative: Vec<Example>,
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// The other fields, taken as they are in a search body.
+    #[serde(flatten)]
+    pub search: Map<String, Value>,
+}
+
+/// A recommendation's examples with ids resolved to their vectors.
+pub struct Examples {
+    pub strategy: Strategy,
+    pub positive: Vec<Vec<f32>>,
+    pub negative: Vec<Vec<f32>>,
+    /// Ids given as examples, which are left out of the hits.
+    pub exclude: HashSet<u64>,
+}
+
+impl Examples {
+    /// The query searched for: `2 * mean(positive) - mean(negative)`, or the positives' mean
+    /// without negatives. With `normalize`, scaled down to norm 1 when longer, which keeps the
+    /// ranking under inner-product distances.
+    pub fn query(&self, normalize: bool) -> Vec<f32> {
+        let mut query = mean(&self.positive);
+        if !self.negative.is_empty() {
+            for (q, n) in query.iter_mut().zip(mean(&self.negative)) {
+                *q = 2.0 * *q - n;
+            }
+        }
+        let norm = distance::norm(&query);
+        if normalize && norm > 1.0 {
+            query.iter_mut().for_each(|x| *x /= norm);
+        }
+        query
+    }
+}
+
+fn mean(vectors: &[Vec<f32>]) -> Vec<f32> {
+    let mut sum = vec![0.0; vectors.first().map_or(0, Vec::len)];
+    for vector in vectors {
+        for (s, x) in sum.iter_mut().zip(vector) {
+            *s += x;
+        }
+    }
+    sum.iter_mut().for_each(|s| *s /= vectors.len() as f32);
+    sum
+}
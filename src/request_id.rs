@@ -0,0 +1,58 @@
+use actix_web::{dev::ServiceRequest, http::header::HeaderName, HttpMessage, HttpRequest};
+use std::future::Future;
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    /// Id of the request the current task serves, read by the log format.
+    static CURRENT: String;
+}
+
+/// Id of the current request, stored in request extensions by [`assign`].
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+/// Reuses a well-formed client `X-Request-ID` or generates a new one, and stores it on the request.
+pub fn assign(req: &ServiceRequest) -> String {
+    let id = req
+        .headers()
+        .get(&HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| is_valid(v))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    req.extensions_mut().insert(RequestId(id.clone()));
+    id
+}
+
+/// Runs `fut`, the rest of the request's handling, with `id` as the current request id, so log
+/// lines written while it runs carry it. Work moved to other threads, e.g. by `web::block`, is
+/// outside it.
+pub async fn scope<F: Future>(id: String, fut: F) -> F::Output {
+    CURRENT.scope(id, fut).await
+}
+
+/// Runs the synchronous `f` with `id` as the current request id.
+pub fn sync_scope<T>(id: String, f: impl FnOnce() -> T) -> T {
+    CURRENT.sync_scope(id, f)
+}
+
+/// The id of the request being handled, if any.
+pub fn current() -> Option<String> {
+    CURRENT.try_with(String::clone).ok()
+}
+
+pub fn get(req: &HttpRequest) -> String {
+    req.extensions()
+        .get::<RequestId>()
+        .map(|r| r.0.clone())
+        .unwrap_or_default()
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
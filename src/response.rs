@@ -0,0 +1,61 @@
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// How much of each hit a search returns.
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// Just the ids, best first.
+    Ids,
+    /// `[id, score]` pairs, or objects when a payload is requested.
+    Scores,
+    /// Objects with distance, payload and vector.
+    Full,
+}
+
+/// Server-wide defaults, overridable per request.
+#[derive(Clone, Copy)]
+pub struct ResponseOptions {
+    pub verbosity: Verbosity,
+    pub envelope: bool,
+}
+
+impl ResponseOptions {
+    /// Reads `SEARCH_VERBOSITY` (ids, scores, full) and `RESPONSE_ENVELOPE` (true/false).
+    pub fn from_env() -> Result<Self, String> {
+        let verbosity = match std::env::var("SEARCH_VERBOSITY") {
+            Ok(v) => serde_json::from_value(serde_json::Value::String(v.clone()))
+                .map_err(|_| format!("invalid SEARCH_VERBOSITY {:?}", v))?,
+            Err(_) => Verbosity::Scores,
+        };
+        let envelope = match std::env::var("RESPONSE_ENVELOPE") {
+            Ok(v) => v
+                .parse()
+                .map_err(|_| format!("invalid RESPONSE_ENVELOPE {:?}", v))?,
+            Err(_) => false,
+        };
+        Ok(Self {
+            verbosity,
+            envelope,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<T> {
+    result: T,
+    took_ms: f64,
+}
+
+/// Serializes `result`, wrapped with the elapsed time since `started` when `envelope` is set.
+pub fn respond<T: Serialize>(result: T, envelope: bool, started: Instant) -> HttpResponse {
+    if envelope {
+        HttpResponse::Ok().json(Envelope {
+            result,
+            took_ms: started.elapsed().as_secs_f64() * 1000.0,
+        })
+    } else {
+        HttpResponse::Ok().json(result)
+    }
+}
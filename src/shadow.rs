@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+/// Where a collection's writes are mirrored to during a migration.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ShadowTarget {
+    pub collection: String,
+    /// Base URL of another instance (e.g. `http://10.0.0.5:5202`); omit for a local collection.
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+#[derive(Default)]
+struct ShadowStats {
+    mirrored: AtomicU64,
+    failed: AtomicU64,
+    pending: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+#[derive(Serialize)]
+pub struct ShadowStatus {
+    pub target: ShadowTarget,
+    /// Write requests successfully applied to the target.
+    pub mirrored: u64,
+    pub failed: u64,
+    /// Remote writes sent but not yet acknowledged.
+    pub lag: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Shadow {
+    pub target: ShadowTarget,
+    stats: Arc<ShadowStats>,
+}
+
+impl Shadow {
+    pub fn new(target: ShadowTarget) -> Self {
+        Self {
+            target,
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn status(&self) -> ShadowStatus {
+        ShadowStatus {
+            target: self.target.clone(),
+            mirrored: self.stats.mirrored.load(Ordering::Relaxed),
+            failed: self.stats.failed.load(Ordering::Relaxed),
+            lag: self.stats.pending.load(Ordering::Relaxed),
+            last_error: self.stats.last_error.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn record(&self, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                self.stats.mirrored.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                log::warn!("shadow write to {} failed: {}", self.target.collection, e);
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                *self.stats.last_error.lock().unwrap() = Some(e);
+            }
+        }
+    }
+
+    /// Sends `body` to `{url}/v1/collections/{collection}/{op}` in the background.
+    pub fn send_remote(&self, url: &str, op: &'static str, body: serde_json::Value) {
+        let endpoint = format!(
+            "{}/v1/collections/{}/{}",
+            url.trim_end_matches('/'),
+            self.target.collection,
+            op
+        );
+        let shadow = self.clone();
+        shadow.stats.pending.fetch_add(1, Ordering::Relaxed);
+        actix_web::rt::spawn(async move {
+            let result = match awc::Client::default()
+                .post(&endpoint)
+                .send_json(&body)
+                .await
+            {
+                Ok(resp) if resp.status().is_success() => Ok(()),
+                Ok(resp) => Err(format!("{} returned {}", endpoint, resp.status())),
+                Err(e) => Err(format!("{}: {}", endpoint, e)),
+            };
+            shadow.stats.pending.fetch_sub(1, Ordering::Relaxed);
+            shadow.record(result);
+        });
+    }
+}
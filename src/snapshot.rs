@@ -0,0 +1,293 @@
+use crate::{
+    cipher::{self, Cipher},
+    storage::{self, Manifest},
+    VectorRecord,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A collection's definition and points in one file. The graph is not stored: it borrows from
+/// the loader in hnsw_rs, so restore rebuilds it from the records instead.
+pub struct Snapshot {
+    pub manifest: Manifest,
+    pub records: Vec<VectorRecord>,
+}
+
+/// On-disk form of a snapshot: each part kept as written, with the checksum of its bytes, so a
+/// damaged file is caught before anything is restored from it.
+#[derive(Serialize, Deserialize)]
+struct Archive {
+    checksums: Checksums,
+    manifest: Box<RawValue>,
+    records: Box<RawValue>,
+}
+
+/// `sha256:<hex>` of each part of an [`Archive`].
+#[derive(Serialize, Deserialize)]
+struct Checksums {
+    manifest: String,
+    records: String,
+}
+
+/// Snapshots written before checksums were added: the manifest fields and the records at the top
+/// level.
+#[derive(Deserialize)]
+struct LegacySnapshot {
+    #[serde(flatten)]
+    manifest: Manifest,
+    records: Vec<VectorRecord>,
+}
+
+/// What reading a snapshot found, for the verify endpoint.
+#[derive(Serialize)]
+pub struct Verification {
+    pub collection: String,
+    pub points: usize,
+    /// False for snapshots written before checksums were added, which can only be checked to
+    /// parse.
+    pub checksummed: bool,
+}
+
+/// Directory snapshots are written to and restored from.
+#[derive(Clone)]
+pub struct Snapshots {
+    dir: PathBuf,
+    /// Encrypts written snapshots when `ENCRYPTION_KEY` is set.
+    cipher: Option<Cipher>,
+}
+
+impl Snapshots {
+    /// Reads `SNAPSHOT_DIR`, defaulting to `snapshots`, and `ENCRYPTION_KEY`.
+    pub fn from_env() -> Result<Self, String> {
+        let dir = std::env::var("SNAPSHOT_DIR").unwrap_or_else(|_| "snapshots".to_string());
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            cipher: Cipher::from_env()?,
+        })
+    }
+
+    /// Writes `snapshot` under a new timestamped file name, which is returned.
+    pub fn write(&self, snapshot: &Snapshot) -> Result<String, String> {
+        fs::create_dir_all(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let file_name = format!("{}-{}.snapshot.json", snapshot.manifest.name, ts);
+        let path = self.dir.join(&file_name);
+        let archive = Archive::new(snapshot)?;
+        let mut contents = serde_json::to_vec(&archive).map_err(|e| e.to_string())?;
+        if let Some(cipher) = &self.cipher {
+            contents = cipher.seal(&contents);
+        }
+        // Written under a temporary name so a partial file is never picked up by a restore.
+        let tmp = self.dir.join(format!(".{}.tmp", file_name));
+        File::create(&tmp)
+            .and_then(|mut file| file.write_all(&contents).and_then(|_| file.sync_all()))
+            .map_err(|e| format!("{}: {}", tmp.display(), e))?;
+        fs::rename(&tmp, &path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        storage::sync_parent(&path)?;
+        Ok(file_name)
+    }
+
+    /// Reads a snapshot by the file name `write` returned, checking it against its checksums;
+    /// `None` when there is no such file.
+    pub fn read(&self, file_name: &str) -> Result<Option<Snapshot>, String> {
+        Ok(self.load(file_name)?.map(|(snapshot, _)| snapshot))
+    }
+
+    /// Checks a snapshot the way [`read`](Self::read) does, without restoring it.
+    pub fn verify(&self, file_name: &str) -> Result<Option<Verification>, String> {
+        Ok(self
+            .load(file_name)?
+            .map(|(snapshot, checksummed)| Verification {
+                collection: snapshot.manifest.name,
+                points: snapshot.records.len(),
+                checksummed,
+            }))
+    }
+
+    /// Rejects names that could leave the snapshot directory or name a temporary file.
+    pub fn check_name(file_name: &str) -> Result<(), String> {
+        if file_name.is_empty() || file_name.starts_with('.') || file_name.contains(['/', '\\']) {
+            return Err(format!("invalid snapshot name {:?}", file_name));
+        }
+        Ok(())
+    }
+
+    /// The snapshot and whether it carried checksums.
+    fn load(&self, file_name: &str) -> Result<Option<(Snapshot, bool)>, String> {
+        Self::check_name(file_name)?;
+        let contents = match fs::read(self.dir.join(file_name)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("{}: {}", file_name, e)),
+        };
+        let contents = cipher::unseal(self.cipher.as_ref(), &contents)
+            .map_err(|e| format!("{}: {}", file_name, e))?;
+        let loaded = match serde_json::from_slice::<Archive>(&contents) {
+            Ok(archive) => archive.open().map(|snapshot| (snapshot, true)),
+            Err(e) => match serde_json::from_slice::<LegacySnapshot>(&contents) {
+                Ok(legacy) => {
+                    log::warn!("{}: snapshot has no checksums", file_name);
+                    let snapshot = Snapshot {
+                        manifest: legacy.manifest,
+                        records: legacy.records,
+                    };
+                    Ok((snapshot, false))
+                }
+                Err(_) => Err(e.to_string()),
+            },
+        };
+        loaded
+            .map(Some)
+            .map_err(|e| format!("{}: {}", file_name, e))
+    }
+}
+
+impl Archive {
+    fn new(snapshot: &Snapshot) -> Result<Self, String> {
+        let manifest =
+            serde_json::value::to_raw_value(&snapshot.manifest).map_err(|e| e.to_string())?;
+        let records =
+            serde_json::value::to_raw_value(&snapshot.records).map_err(|e| e.to_string())?;
+        Ok(Self {
+            checksums: Checksums {
+                manifest: checksum(&manifest),
+                records: checksum(&records),
+            },
+            manifest,
+            records,
+        })
+    }
+
+    fn open(self) -> Result<Snapshot, String> {
+        for (part, raw, expected) in [
+            ("manifest", &self.manifest, &self.checksums.manifest),
+            ("records", &self.records, &self.checksums.records),
+        ] {
+            if checksum(raw) != *expected {
+                return Err(format!("{} checksum mismatch; the file is damaged", part));
+            }
+        }
+        Ok(Snapshot {
+            manifest: serde_json::from_str(self.manifest.get()).map_err(|e| e.to_string())?,
+            records: serde_json::from_str(self.records.get()).map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+fn checksum(raw: &RawValue) -> String {
+    let digest = Sha256::digest(raw.get().as_bytes());
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn snapshots() -> Snapshots {
+        let dir = std::env::temp_dir().join(format!("vector_db-{}", uuid::Uuid::new_v4().simple()));
+        Snapshots { dir, cipher: None }
+    }
+
+    fn snapshot() -> Snapshot {
+        let manifest = json!({"name": "c", "dim": 2, "config": {"distance": "cosine"}});
+        let records = json!([{"id": 1, "vector": [0.5, 0.25], "payload": {"lang": "en"}}]);
+        Snapshot {
+            manifest: serde_json::from_value(manifest).unwrap(),
+            records: serde_json::from_value(records).unwrap(),
+        }
+    }
+
+    #[test]
+    fn written_snapshots_read_back() {
+        let snapshots = snapshots();
+        let name = snapshots.write(&snapshot()).unwrap();
+        let read = snapshots.read(&name).unwrap().unwrap();
+        assert_eq!(read.manifest.name, "c");
+        assert_eq!(read.records[0].vector, [0.5, 0.25]);
+        let verified = snapshots.verify(&name).unwrap().unwrap();
+        assert!(verified.checksummed);
+        assert_eq!(verified.points, 1);
+        fs::remove_dir_all(&snapshots.dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_snapshots_are_rejected() {
+        let snapshots = snapshots();
+        let name = snapshots.write(&snapshot()).unwrap();
+        let path = snapshots.dir.join(&name);
+        let contents = fs::read_to_string(&path).unwrap();
+        // Still valid JSON, with a different point.
+        fs::write(&path, contents.replace("0.25", "0.75")).unwrap();
+        let e = snapshots.read(&name).err().unwrap();
+        assert!(e.contains("records checksum mismatch"), "{}", e);
+        fs::write(&path, contents.replace("cosine", "l2")).unwrap();
+        assert!(snapshots.verify(&name).is_err());
+        fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+        assert!(snapshots.read(&name).is_err());
+        fs::remove_dir_all(&snapshots.dir).unwrap();
+    }
+
+    #[test]
+    fn legacy_snapshots_read_without_checksums() {
+        let snapshots = snapshots();
+        fs::create_dir_all(&snapshots.dir).unwrap();
+        let legacy = json!({
+            "name": "c",
+            "dim": 2,
+            "config": {"distance": "cosine"},
+            "records": [{"id": 1, "vector": [0.5, 0.25], "payload": null}]
+        });
+        fs::write(snapshots.dir.join("c-1.snapshot.json"), legacy.to_string()).unwrap();
+        let verified = snapshots.verify("c-1.snapshot.json").unwrap().unwrap();
+        assert!(!verified.checksummed);
+        assert_eq!(verified.points, 1);
+        fs::remove_dir_all(&snapshots.dir).unwrap();
+    }
+
+    #[test]
+    fn names_stay_in_the_directory() {
+        let snapshots = snapshots();
+        for name in ["", "../x", ".c-1.snapshot.json.tmp", "a\\b"] {
+            assert!(snapshots.read(name).is_err(), "{:?}", name);
+        }
+        assert!(snapshots.read("missing.snapshot.json").unwrap().is_none());
+    }
+
+    #[test]
+    fn encrypted_snapshots_read_back_with_the_key() {
+        let snapshots = Snapshots {
+            cipher: Some(Cipher::new(&[7; 32])),
+            ..self::snapshots()
+        };
+        let name = snapshots.write(&snapshot()).unwrap();
+        let contents = fs::read_to_string(snapshots.dir.join(&name)).unwrap();
+        assert!(!contents.contains("lang"));
+        let read = snapshots.read(&name).unwrap().unwrap();
+        assert_eq!(read.records[0].payload, json!({"lang": "en"}));
+        assert!(snapshots.verify(&name).unwrap().unwrap().checksummed);
+        let keyless = Snapshots {
+            cipher: None,
+            ..snapshots.clone()
+        };
+        let e = keyless.read(&name).err().unwrap();
+        assert!(e.contains("ENCRYPTION_KEY"), "{}", e);
+        let other = Snapshots {
+            cipher: Some(Cipher::new(&[8; 32])),
+            ..snapshots.clone()
+        };
+        assert!(other.verify(&name).is_err());
+        fs::remove_dir_all(&snapshots.dir).unwrap();
+    }
+}
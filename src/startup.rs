@@ -0,0 +1,208 @@
+use crate::{error::ApiError, request_id::RequestId};
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, Error, HttpMessage, HttpResponse, Responder,
+};
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Error code of requests refused while collections are loading.
+const CODE: &str = "starting";
+
+/// Routes served before the collections are loaded.
+pub const PROGRESS_PATH: &str = "/startup";
+pub const READY_PATH: &str = "/readyz";
+
+#[derive(Clone, Copy, PartialEq, Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadState {
+    Pending,
+    /// Reading the manifest and replaying the WAL.
+    Replaying,
+    /// Building the graph from the replayed points.
+    Indexing,
+    Loaded,
+    /// Could not be loaded and was moved aside; see `GET /recovery`.
+    Quarantined,
+}
+
+#[derive(Clone, Serialize)]
+pub struct CollectionProgress {
+    pub name: String,
+    pub state: LoadState,
+    pub wal_bytes: u64,
+    /// Known once the WAL is replayed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub points: Option<usize>,
+    pub indexed: usize,
+}
+
+impl CollectionProgress {
+    /// Share of the collection's loading done, counting only graph building, which dominates.
+    fn done(&self) -> f64 {
+        match (self.state, self.points) {
+            (LoadState::Loaded | LoadState::Quarantined, _) => 1.0,
+            (LoadState::Indexing, Some(points)) if points > 0 => {
+                self.indexed as f64 / points as f64
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct StartupStatus {
+    pub ready: bool,
+    pub elapsed_ms: u64,
+    /// Estimated from the progress so far, weighting collections by WAL size.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_ms: Option<u64>,
+    pub collections: Vec<CollectionProgress>,
+}
+
+/// Progress of loading the stored collections, which runs after the server starts listening.
+pub struct Startup {
+    started: Instant,
+    ready: AtomicBool,
+    collections: Mutex<Vec<CollectionProgress>>,
+    /// Set when loading ended; the time it took.
+    took: Mutex<Option<Duration>>,
+}
+
+impl Startup {
+    /// Startup with `collections`, by name and WAL size, waiting to be loaded.
+    pub fn new(collections: impl IntoIterator<Item = (String, u64)>) -> Self {
+        let collections = collections
+            .into_iter()
+            .map(|(name, wal_bytes)| CollectionProgress {
+                name,
+                state: LoadState::Pending,
+                wal_bytes,
+                points: None,
+                indexed: 0,
+            })
+            .collect();
+        Self {
+            started: Instant::now(),
+            ready: AtomicBool::new(false),
+            collections: Mutex::new(collections),
+            took: Mutex::new(None),
+        }
+    }
+
+    /// Changes the progress of the `i`th collection.
+    pub fn update(&self, i: usize, change: impl FnOnce(&mut CollectionProgress)) {
+        if let Some(progress) = self.collections.lock().unwrap().get_mut(i) {
+            change(progress);
+        }
+    }
+
+    /// Marks loading as complete; requests are served from now on.
+    pub fn finish(&self) {
+        let took = self.started.elapsed();
+        *self.took.lock().unwrap() = Some(took);
+        self.ready.store(true, Ordering::SeqCst);
+        log::info!(
+            "ready after loading collections for {} ms",
+            took.as_millis()
+        );
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn status(&self) -> StartupStatus {
+        let collections = self.collections.lock().unwrap().clone();
+        let elapsed = self.took.lock().unwrap().unwrap_or(self.started.elapsed());
+        let ready = self.is_ready();
+        let weight = |c: &CollectionProgress| c.wal_bytes.max(1) as f64;
+        let total: f64 = collections.iter().map(weight).sum();
+        let done: f64 = collections.iter().map(|c| weight(c) * c.done()).sum();
+        let eta_ms = match done / total.max(1.0) {
+            _ if ready => None,
+            share if share > 0.0 && share < 1.0 => {
+                Some((elapsed.as_millis() as f64 * (1.0 - share) / share) as u64)
+            }
+            _ => None,
+        };
+        StartupStatus {
+            ready,
+            elapsed_ms: elapsed.as_millis() as u64,
+            eta_ms,
+            collections,
+        }
+    }
+
+    /// One log line on the loading as a whole.
+    pub fn log_progress(&self) {
+        let status = self.status();
+        let loaded = status
+            .collections
+            .iter()
+            .filter(|c| c.done() == 1.0)
+            .count();
+        match status.eta_ms {
+            Some(eta) => log::info!(
+                "loaded {} of {} collections, about {} s left",
+                loaded,
+                status.collections.len(),
+                eta.div_ceil(1000)
+            ),
+            None => log::info!(
+                "loaded {} of {} collections",
+                loaded,
+                status.collections.len()
+            ),
+        }
+    }
+}
+
+/// Answers 503 until the collections are loaded, except on the progress and readiness routes.
+pub async fn gate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let loading = req
+        .app_data::<web::Data<Startup>>()
+        .is_some_and(|s| !s.is_ready());
+    if !loading || req.path() == PROGRESS_PATH || req.path() == READY_PATH {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let error = ApiError {
+        status: 503,
+        code: CODE.to_string(),
+        message: format!("Collections are still loading; see {}", PROGRESS_PATH),
+        request_id: req
+            .extensions()
+            .get::<RequestId>()
+            .map(|r| r.0.clone())
+            .unwrap_or_default(),
+    };
+    let resp = HttpResponse::ServiceUnavailable()
+        .insert_header((header::RETRY_AFTER, 1))
+        .json(error);
+    Ok(req.into_response(resp).map_into_right_body())
+}
+
+/// `GET /startup`: the loading progress, also once it is complete.
+pub async fn progress(startup: web::Data<Startup>) -> impl Responder {
+    HttpResponse::Ok().json(startup.status())
+}
+
+/// `GET /readyz`: 200 once the collections are loaded, 503 until then.
+pub async fn ready(startup: web::Data<Startup>) -> impl Responder {
+    match startup.is_ready() {
+        true => HttpResponse::Ok().body("ready"),
+        false => HttpResponse::ServiceUnavailable().body("Collections are still loading"),
+    }
+}
@@ -0,0 +1,1200 @@
+use crate::{
+    cipher::{self, Cipher},
+    payload, CollectionConfig, VectorRecord,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const MANIFEST: &str = "manifest.json";
+const WAL: &str = "wal.jsonl";
+const FEEDBACK: &str = "feedback.jsonl";
+/// Payload values moved out of memory, see [`Blobs`].
+const BLOBS: &str = "blobs";
+/// Held by the running server; collection names cannot contain the dot, so it never clashes.
+const LOCK: &str = ".lock";
+/// Where collections whose manifest cannot be read are moved, out of the way of `load`.
+const QUARANTINE: &str = ".quarantine";
+/// Where dropped collections are kept until `TRASH_RETENTION_SECS` passes.
+const TRASH: &str = ".trash";
+/// A known value sealed with `ENCRYPTION_KEY` once the directory is encrypted, to tell a wrong or
+/// missing key from damage at startup.
+const KEY_CHECK: &str = ".encryption";
+
+/// Collection definition stored next to its WAL.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub dim: usize,
+    pub config: CollectionConfig,
+}
+
+/// Manifest shape written by the first versions, before collections had a `config`:
+/// `{"name", "dim", "metric": "Cosine", "hnsw": {"m", "ef_construction", "ef_search"}}`. It is
+/// read as the equivalent `Manifest`, and replaced by one the first time the manifest is saved.
+#[derive(Deserialize)]
+struct LegacyManifest {
+    name: String,
+    dim: usize,
+    metric: String,
+    #[serde(default)]
+    hnsw: LegacyHnsw,
+}
+
+#[derive(Default, Deserialize)]
+struct LegacyHnsw {
+    m: Option<usize>,
+    ef_construction: Option<usize>,
+    ef_search: Option<usize>,
+}
+
+impl LegacyManifest {
+    fn migrate(self) -> Result<Manifest, String> {
+        let distance = match self.metric.to_ascii_lowercase().as_str() {
+            "cosine" => "cosine",
+            "l2" | "euclidean" => "l2",
+            "dot" | "dotproduct" => "dot",
+            _ => return Err(format!("unknown metric {:?}", self.metric)),
+        };
+        let mut config: CollectionConfig =
+            serde_json::from_value(serde_json::json!({ "distance": distance }))
+                .map_err(|e| e.to_string())?;
+        let hnsw = &mut config.hnsw;
+        hnsw.max_nb_connection = self.hnsw.m.unwrap_or(hnsw.max_nb_connection);
+        hnsw.ef_construction = self.hnsw.ef_construction.unwrap_or(hnsw.ef_construction);
+        hnsw.ef_search = self.hnsw.ef_search.unwrap_or(hnsw.ef_search);
+        Ok(Manifest {
+            name: self.name,
+            dim: self.dim,
+            config,
+        })
+    }
+}
+
+/// One line of a collection's write-ahead log. Upserts are stored as the resulting record, so
+/// replay does not re-run dedup or merging.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WalEntry {
+    Upsert(VectorRecord),
+    Delete { delete: u64 },
+}
+
+/// When a collection's WAL writes are forced to disk. Every write reaches the OS before it is
+/// acknowledged; the policy decides what a power loss or kernel crash can still take with it.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "lowercase")]
+pub enum FsyncPolicy {
+    /// Sync before every write is acknowledged.
+    Always,
+    /// Sync each write within `interval_ms` of making it.
+    Interval { interval_ms: u64 },
+    /// Leave it to the OS's write-back, and sync at shutdown.
+    Os,
+}
+
+impl Default for FsyncPolicy {
+    fn default() -> Self {
+        FsyncPolicy::Interval { interval_ms: 1000 }
+    }
+}
+
+impl FsyncPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            FsyncPolicy::Interval { interval_ms: 0 } => {
+                Err("fsync interval_ms must be greater than 0".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A collection as loaded from disk: its manifest, live records and open WAL.
+pub type Stored = (Manifest, Vec<VectorRecord>, Wal);
+
+/// A stored collection found at startup and not yet read.
+pub struct Pending {
+    /// Its directory's name, which is the collection's name unless the manifest says otherwise.
+    pub name: String,
+    /// Size of its WAL, a measure of how long it takes to load.
+    pub wal_bytes: u64,
+    dir: PathBuf,
+}
+
+/// What startup did about one collection's damaged files.
+#[derive(Serialize)]
+pub struct Recovery {
+    pub collection: String,
+    /// Copy of the damaged file or directory, kept for inspection.
+    pub quarantined: String,
+    /// Unreadable WAL lines, 1-based. They are dropped from the rewritten WAL.
+    pub bad_lines: Vec<usize>,
+    /// Whether the last of them was a write cut short, e.g. by a crash or a full disk.
+    pub truncated_tail: bool,
+    /// Point ids the unreadable lines name, as far as they can be made out; their last write
+    /// before the damage may be lost.
+    pub affected_ids: Vec<u64>,
+    /// Set when the collection could not be loaded at all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A dropped collection waiting in the trash.
+#[derive(Serialize)]
+pub struct Trashed {
+    /// `{collection}-{deleted_ms}`; pass it to restore or purge the entry.
+    pub id: String,
+    pub collection: String,
+    pub deleted_ms: u64,
+    pub expires_ms: u64,
+}
+
+/// Payload values over a collection's `blob_threshold_bytes`, one file each in the `blobs`
+/// directory next to its WAL. Entries refer to them by id; the files no entry refers to any more
+/// are deleted when the WAL is rewritten.
+#[derive(Clone)]
+pub struct Blobs {
+    dir: PathBuf,
+    /// Seals each file as the WAL seals its entries.
+    cipher: Option<Cipher>,
+}
+
+impl Blobs {
+    /// Writes a value's JSON to a new file and returns its id. The file is synced before the id
+    /// is handed out, so no entry can refer to a blob a crash lost.
+    pub fn put(&self, json: &[u8]) -> Result<String, String> {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let path = self.dir.join(&id);
+        let tmp = path.with_extension("tmp");
+        let contents = match &self.cipher {
+            Some(cipher) => cipher.seal(json),
+            None => json.to_vec(),
+        };
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| File::create(&tmp))
+            .and_then(|mut file| file.write_all(&contents).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        sync_parent(&path)?;
+        Ok(id)
+    }
+
+    /// The JSON of the value stored as `id`.
+    pub fn get(&self, id: &str) -> Result<Vec<u8>, String> {
+        if id.len() != 32 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("{:?} is not a blob id", id));
+        }
+        let path = self.dir.join(id);
+        let contents = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        cipher::unseal(self.cipher.as_ref(), &contents)
+            .map(|json| json.into_owned())
+            .map_err(|e| format!("{} {}", path.display(), e))
+    }
+
+    /// Deletes the files whose id is not in `live`.
+    fn retain(&self, live: &HashSet<&str>) -> Result<(), String> {
+        let files = match fs::read_dir(&self.dir) {
+            Ok(files) => files,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("{}: {}", self.dir.display(), e)),
+        };
+        for file in files {
+            let path = file.map_err(|e| e.to_string())?.path();
+            let id = path.file_name().unwrap_or_default().to_string_lossy();
+            if !live.contains(id.as_ref()) {
+                fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Append handle for a collection's WAL.
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    /// Seals each entry as it is appended.
+    cipher: Option<Cipher>,
+    blobs: Blobs,
+    /// Entries in the log, superseded ones included.
+    entries: usize,
+    /// When the oldest entry not yet synced was appended.
+    unsynced_since: Option<Instant>,
+}
+
+impl Wal {
+    fn open(path: PathBuf, cipher: Option<Cipher>) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let blobs = Blobs {
+            dir: path.with_file_name(BLOBS),
+            cipher: cipher.clone(),
+        };
+        Ok(Self {
+            path,
+            file,
+            cipher,
+            blobs,
+            entries: 0,
+            unsynced_since: None,
+        })
+    }
+
+    /// Appends the entries with a single write so a batch is not interleaved with another.
+    pub fn append(&mut self, entries: &[WalEntry]) -> Result<(), String> {
+        let mut buf = Vec::new();
+        for entry in entries {
+            let json = serde_json::to_vec(entry).map_err(|e| e.to_string())?;
+            match &self.cipher {
+                Some(cipher) => buf.extend(cipher.seal(&json)),
+                None => buf.extend(json),
+            }
+            buf.push(b'\n');
+        }
+        self.file
+            .write_all(&buf)
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        self.entries += entries.len();
+        if !entries.is_empty() {
+            self.unsynced_since.get_or_insert_with(Instant::now);
+        }
+        Ok(())
+    }
+
+    /// Where the entries' large payload values are kept.
+    pub fn blobs(&self) -> &Blobs {
+        &self.blobs
+    }
+
+    /// Entries a replay of the log would read.
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    /// When the oldest entry [`sync`](Self::sync) has not yet forced to disk was appended.
+    pub fn unsynced_since(&self) -> Option<Instant> {
+        self.unsynced_since
+    }
+
+    /// Forces appended entries to disk.
+    pub fn sync(&mut self) -> Result<(), String> {
+        self.file
+            .sync_all()
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        self.unsynced_since = None;
+        Ok(())
+    }
+
+    /// Replaces the log with `entries`, e.g. the current records, dropping history replay no longer
+    /// needs. The new log is written aside, synced and renamed over the old one, and the rename is
+    /// synced too, so a crash leaves either log whole. Blobs only the old log referred to are
+    /// deleted after the rename.
+    pub fn rewrite(&mut self, entries: &[WalEntry]) -> Result<(), String> {
+        let tmp = self.path.with_extension("jsonl.tmp");
+        let mut fresh = Wal {
+            file: File::create(&tmp).map_err(|e| format!("{}: {}", tmp.display(), e))?,
+            path: tmp.clone(),
+            cipher: self.cipher.clone(),
+            blobs: self.blobs.clone(),
+            entries: 0,
+            unsynced_since: None,
+        };
+        fresh.append(entries)?;
+        fresh.sync()?;
+        fs::rename(&tmp, &self.path).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        sync_parent(&self.path)?;
+        *self = Wal::open(self.path.clone(), self.cipher.take())?;
+        self.entries = entries.len();
+        let live = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                WalEntry::Upsert(record) => Some(payload::blob_refs(&record.payload)),
+                WalEntry::Delete { .. } => None,
+            })
+            .flatten()
+            .collect();
+        self.blobs.retain(&live)
+    }
+}
+
+/// On-disk layout: `{STORAGE_DIR}/{collection}/manifest.json`, `wal.jsonl` and `blobs/`.
+pub struct Storage {
+    dir: Option<PathBuf>,
+    /// How long dropped collections stay in the trash; zero deletes them right away.
+    trash_retention: Duration,
+    /// How often WALs are checked for checkpointing; zero turns checkpoints off.
+    checkpoint_every: Duration,
+    /// Encrypts manifests and WALs when `ENCRYPTION_KEY` is set.
+    cipher: Option<Cipher>,
+    /// Locked for the life of the process so a second server cannot write the same directory.
+    _lock: Option<File>,
+}
+
+impl Storage {
+    /// Reads `STORAGE_DIR`, `STORAGE_IGNORE_LOCK`, `TRASH_RETENTION_SECS` (default 7 days),
+    /// `WAL_CHECKPOINT_SECS` (default 5 minutes) and `ENCRYPTION_KEY`. Collections are kept in
+    /// memory only when `STORAGE_DIR` is unset.
+    pub fn from_env() -> Result<Self, String> {
+        let trash_retention = match std::env::var("TRASH_RETENTION_SECS") {
+            Ok(v) => v
+                .parse()
+                .map_err(|_| format!("invalid TRASH_RETENTION_SECS {:?}", v))?,
+            Err(_) => 7 * 24 * 60 * 60,
+        };
+        let trash_retention = Duration::from_secs(trash_retention);
+        let checkpoint_every = match std::env::var("WAL_CHECKPOINT_SECS") {
+            Ok(v) => v
+                .parse()
+                .map_err(|_| format!("invalid WAL_CHECKPOINT_SECS {:?}", v))?,
+            Err(_) => 5 * 60,
+        };
+        let checkpoint_every = Duration::from_secs(checkpoint_every);
+        let cipher = Cipher::from_env()?;
+        let Ok(dir) = std::env::var("STORAGE_DIR") else {
+            return Ok(Self {
+                dir: None,
+                trash_retention,
+                checkpoint_every,
+                cipher,
+                _lock: None,
+            });
+        };
+        let ignore_lock = match std::env::var("STORAGE_IGNORE_LOCK") {
+            Ok(v) => v
+                .parse()
+                .map_err(|_| format!("invalid STORAGE_IGNORE_LOCK {:?}", v))?,
+            Err(_) => false,
+        };
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir).map_err(|e| format!("STORAGE_DIR {}: {}", dir.display(), e))?;
+        let lock = lock_dir(&dir, ignore_lock)?;
+        check_key(&dir, cipher.as_ref())?;
+        let storage = Self {
+            dir: Some(dir),
+            trash_retention,
+            checkpoint_every,
+            cipher,
+            _lock: lock,
+        };
+        storage.list_trash()?;
+        Ok(storage)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// How often to look for WALs to checkpoint; `None` when nothing is stored or checkpoints are
+    /// off.
+    pub fn checkpoint_every(&self) -> Option<Duration> {
+        self.dir.as_ref()?;
+        (!self.checkpoint_every.is_zero()).then_some(self.checkpoint_every)
+    }
+
+    /// Writes the manifest for a new collection and opens its empty WAL.
+    pub fn create(&self, manifest: &Manifest) -> Result<Option<Wal>, String> {
+        let Some(root) = &self.dir else {
+            return Ok(None);
+        };
+        let dir = root.join(&manifest.name);
+        fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        write_manifest(&dir, manifest, self.cipher.as_ref())?;
+        let wal = dir.join(WAL);
+        File::create(&wal).map_err(|e| format!("{}: {}", wal.display(), e))?;
+        sync_parent(&wal)?;
+        Wal::open(wal, self.cipher.clone()).map(Some)
+    }
+
+    /// Replaces the manifest of an existing collection, e.g. after its config changed.
+    pub fn update(&self, manifest: &Manifest) -> Result<(), String> {
+        match &self.dir {
+            Some(root) => {
+                write_manifest(&root.join(&manifest.name), manifest, self.cipher.as_ref())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Where a collection's feedback events are appended, when collections are stored.
+    pub fn feedback_path(&self, name: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|root| root.join(name).join(FEEDBACK))
+    }
+
+    /// Moves a dropped collection's directory (manifest, WAL, blobs and feedback) to the trash, or
+    /// deletes it when the trash is disabled. Returns the trash id.
+    pub fn remove(&self, name: &str) -> Result<Option<String>, String> {
+        let Some(root) = &self.dir else {
+            return Ok(None);
+        };
+        let dir = root.join(name);
+        if self.trash_retention.is_zero() {
+            return remove_dir(&dir).map(|_| None);
+        }
+        let id = format!("{}-{}", name, now_ms());
+        let target = root.join(TRASH).join(&id);
+        let moved = fs::create_dir_all(root.join(TRASH)).and_then(|_| fs::rename(&dir, &target));
+        match moved {
+            Ok(()) => Ok(Some(id)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", target.display(), e)),
+        }
+    }
+
+    /// The trash, oldest first, after purging the entries past their retention.
+    pub fn list_trash(&self) -> Result<Vec<Trashed>, String> {
+        let Some(root) = &self.dir else {
+            return Ok(Vec::new());
+        };
+        let trash = root.join(TRASH);
+        let entries = match fs::read_dir(&trash) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("{}: {}", trash.display(), e)),
+        };
+        let retention = self.trash_retention.as_millis() as u64;
+        let mut listed = Vec::new();
+        for entry in entries {
+            let id = entry.map_err(|e| e.to_string())?.file_name();
+            let id = id.to_string_lossy();
+            let Some((collection, deleted_ms)) = parse_trash_id(&id) else {
+                continue;
+            };
+            let expires_ms = deleted_ms + retention;
+            if expires_ms <= now_ms() as u64 {
+                log::info!("purging {} from the trash", id);
+                remove_dir(&trash.join(id.as_ref()))?;
+                continue;
+            }
+            listed.push(Trashed {
+                id: id.to_string(),
+                collection: collection.to_string(),
+                deleted_ms,
+                expires_ms,
+            });
+        }
+        listed.sort_by_key(|t| t.deleted_ms);
+        Ok(listed)
+    }
+
+    /// Reads a trashed collection without taking it out of the trash.
+    pub fn read_trashed(&self, id: &str) -> Result<Option<(Manifest, Vec<VectorRecord>)>, String> {
+        let Some(dir) = self.trash_dir(id) else {
+            return Ok(None);
+        };
+        let manifest = read_manifest(&dir.join(MANIFEST), self.cipher.as_ref())?;
+        let replayed = replay(&dir.join(WAL), self.cipher.as_ref())?;
+        if !replayed.bad_lines.is_empty() {
+            log::warn!(
+                "trashed collection {}: skipped {} unreadable WAL lines",
+                id,
+                replayed.bad_lines.len()
+            );
+        }
+        Ok(Some((manifest, replayed.records)))
+    }
+
+    /// Moves a trashed collection back as `manifest.name`, saving `manifest` in case it was
+    /// restored under a new name, and reopens its WAL.
+    pub fn untrash(&self, id: &str, manifest: &Manifest) -> Result<Option<Wal>, String> {
+        let (Some(root), Some(from)) = (&self.dir, self.trash_dir(id)) else {
+            return Ok(None);
+        };
+        let dir = root.join(&manifest.name);
+        fs::rename(&from, &dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        write_manifest(&dir, manifest, self.cipher.as_ref())?;
+        Wal::open(dir.join(WAL), self.cipher.clone()).map(Some)
+    }
+
+    /// Deletes a trashed collection for good. Returns whether it was there.
+    pub fn purge_trashed(&self, id: &str) -> Result<bool, String> {
+        match self.trash_dir(id) {
+            Some(dir) => remove_dir(&dir).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Moves a stored collection that could not be rebuilt under `.quarantine`, so the next start
+    /// does not try again, and reports it.
+    pub fn quarantine(&self, name: &str, error: String) -> Result<Recovery, String> {
+        let Some(root) = &self.dir else {
+            return Err(error);
+        };
+        quarantine_dir(root, &root.join(name), error)
+    }
+
+    fn trash_dir(&self, id: &str) -> Option<PathBuf> {
+        parse_trash_id(id)?;
+        let dir = self.dir.as_ref()?.join(TRASH).join(id);
+        dir.join(MANIFEST).is_file().then_some(dir)
+    }
+
+    /// The stored collections, to be read one at a time with [`load_one`](Self::load_one).
+    pub fn pending(&self) -> Result<Vec<Pending>, String> {
+        let Some(root) = &self.dir else {
+            return Ok(Vec::new());
+        };
+        let entries = fs::read_dir(root).map_err(|e| format!("{}: {}", root.display(), e))?;
+        let mut pending = Vec::new();
+        for entry in entries {
+            let dir = entry.map_err(|e| e.to_string())?.path();
+            if !dir.join(MANIFEST).is_file() {
+                continue;
+            }
+            let name = dir.file_name().unwrap_or_default().to_string_lossy();
+            pending.push(Pending {
+                name: name.into_owned(),
+                wal_bytes: fs::metadata(dir.join(WAL)).map_or(0, |m| m.len()),
+                dir,
+            });
+        }
+        Ok(pending)
+    }
+
+    /// Reads a stored collection, replaying its WAL into the latest record per id.
+    ///
+    /// Damage is recovered from rather than failing startup. Unreadable WAL lines are skipped,
+    /// the original WAL is kept aside as `wal.jsonl.corrupt-{ts}`, and the WAL is rewritten from
+    /// what could be read. A collection with an unreadable manifest is moved to
+    /// `.quarantine/{name}-{ts}`, as is one whose manifest fails validation, and is not returned.
+    /// Each case is reported. With `ENCRYPTION_KEY` set, files still in the clear are encrypted.
+    pub fn load_one(
+        &self,
+        pending: &Pending,
+    ) -> Result<(Option<Stored>, Option<Recovery>), String> {
+        let Some(root) = &self.dir else {
+            return Ok((None, None));
+        };
+        let dir = &pending.dir;
+        let dir_name = &pending.name;
+        let manifest_path = dir.join(MANIFEST);
+        let manifest = match read_manifest(&manifest_path, self.cipher.as_ref()) {
+            Ok(manifest) => manifest,
+            // Not damaged, only unreadable until the key is set.
+            Err(e) if self.cipher.is_none() && sealed_file(&manifest_path) => return Err(e),
+            Err(e) => return Ok((None, Some(quarantine_dir(root, dir, e)?))),
+        };
+        if self.cipher.is_some() && !sealed_file(&manifest_path) {
+            write_manifest(dir, &manifest, self.cipher.as_ref())?;
+        }
+        let wal_path = dir.join(WAL);
+        let replayed = replay(&wal_path, self.cipher.as_ref())?;
+        let mut wal = Wal::open(wal_path.clone(), self.cipher.clone())?;
+        wal.entries = replayed.entries;
+        if replayed.unterminated && replayed.bad_lines.is_empty() {
+            // The last write is whole but for its newline; the next one must not run into it.
+            wal.file
+                .write_all(b"\n")
+                .map_err(|e| format!("{}: {}", wal_path.display(), e))?;
+        }
+        if self.cipher.is_some() && replayed.plain && replayed.bad_lines.is_empty() {
+            let entries: Vec<WalEntry> = replayed
+                .records
+                .iter()
+                .cloned()
+                .map(WalEntry::Upsert)
+                .collect();
+            wal.rewrite(&entries)?;
+            log::info!("collection {}: encrypted its WAL", dir_name);
+        }
+        let mut recovered = None;
+        if !replayed.bad_lines.is_empty() {
+            let kept = wal_path.with_extension(format!("jsonl.corrupt-{}", now_ms()));
+            fs::copy(&wal_path, &kept).map_err(|e| format!("{}: {}", kept.display(), e))?;
+            let entries: Vec<WalEntry> = replayed
+                .records
+                .iter()
+                .cloned()
+                .map(WalEntry::Upsert)
+                .collect();
+            wal.rewrite(&entries)?;
+            log::warn!(
+                "collection {}: skipped {} unreadable WAL lines, kept the original as {}",
+                dir_name,
+                replayed.bad_lines.len(),
+                kept.display()
+            );
+            recovered = Some(Recovery {
+                collection: dir_name.clone(),
+                quarantined: kept.display().to_string(),
+                bad_lines: replayed.bad_lines,
+                truncated_tail: replayed.truncated_tail,
+                affected_ids: replayed.affected_ids,
+                error: None,
+            });
+        }
+        log::info!(
+            "read collection {} with {} points",
+            manifest.name,
+            replayed.records.len()
+        );
+        Ok((Some((manifest, replayed.records, wal)), recovered))
+    }
+}
+
+/// Takes an exclusive advisory lock on `{dir}/.lock` and writes this instance's id and pid into it.
+/// The OS releases the lock when the process exits, however it exits, so a stale lock never
+/// blocks a restart. When another process holds it, startup fails unless `ignore_held` is set,
+/// for recovery when the holder is known not to write, e.g. a hung process; the lock is then
+/// left to its holder.
+fn lock_dir(dir: &Path, ignore_held: bool) -> Result<Option<File>, String> {
+    let path = dir.join(LOCK);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            let holder = holder.trim();
+            if !ignore_held {
+                return Err(format!(
+                    "STORAGE_DIR {} is in use by another server ({}); stop it first, or set \
+                     STORAGE_IGNORE_LOCK=true if it is certain not to write",
+                    dir.display(),
+                    if holder.is_empty() { "unknown" } else { holder }
+                ));
+            }
+            log::warn!(
+                "STORAGE_IGNORE_LOCK is set; using {} although it is locked by {}",
+                dir.display(),
+                holder
+            );
+            return Ok(None);
+        }
+        Err(TryLockError::Error(e)) => return Err(format!("{}: {}", path.display(), e)),
+    }
+    let instance = uuid::Uuid::new_v4();
+    file.set_len(0)
+        .and_then(|_| write!(file, "instance {} pid {}", instance, std::process::id()))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    log::info!("locked {} as instance {}", dir.display(), instance);
+    Ok(Some(file))
+}
+
+/// Checks `cipher` against the key `{dir}/.encryption` was sealed with, writing the file the first
+/// time a key is set. Starting without the key, or with another, fails rather than reading the
+/// encrypted files as damaged.
+fn check_key(dir: &Path, cipher: Option<&Cipher>) -> Result<(), String> {
+    let path = dir.join(KEY_CHECK);
+    match (fs::read(&path), cipher) {
+        (Ok(sealed), Some(cipher)) => match cipher.open(&sealed) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!(
+                "STORAGE_DIR {} was encrypted with another ENCRYPTION_KEY",
+                dir.display()
+            )),
+        },
+        (Ok(_), None) => Err(format!(
+            "STORAGE_DIR {} is encrypted; set ENCRYPTION_KEY",
+            dir.display()
+        )),
+        (Err(e), Some(cipher)) if e.kind() == std::io::ErrorKind::NotFound => {
+            fs::write(&path, cipher.seal(KEY_CHECK.as_bytes()))
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            sync_parent(&path)
+        }
+        (Err(e), None) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        (Err(e), _) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// Writes the manifest aside, syncs it and renames it into place, so a crash leaves the old one
+/// intact.
+fn write_manifest(dir: &Path, manifest: &Manifest, cipher: Option<&Cipher>) -> Result<(), String> {
+    let mut json = serde_json::to_vec_pretty(manifest).map_err(|e| e.to_string())?;
+    if let Some(cipher) = cipher {
+        json = cipher.seal(&json);
+    }
+    let path = dir.join(MANIFEST);
+    let tmp = path.with_extension("json.tmp");
+    File::create(&tmp)
+        .and_then(|mut file| file.write_all(&json).and_then(|_| file.sync_all()))
+        .map_err(|e| format!("{}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, &path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    sync_parent(&path)
+}
+
+/// Syncs the directory holding `path`, which makes a file created or renamed there durable.
+pub fn sync_parent(path: &Path) -> Result<(), String> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| format!("{}: {}", dir.display(), e))
+}
+
+/// Reads a manifest and checks it as a create request would be, so a hand-edited or outdated
+/// config cannot reach hnsw_rs, which exits the process on some invalid parameters.
+fn read_manifest(path: &Path, cipher: Option<&Cipher>) -> Result<Manifest, String> {
+    let contents = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let contents =
+        cipher::unseal(cipher, &contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let json: serde_json::Value =
+        serde_json::from_slice(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let manifest = if json.get("config").is_none() && json.get("metric").is_some() {
+        log::info!("{}: reading manifest in the legacy format", path.display());
+        serde_json::from_value::<LegacyManifest>(json)
+            .map_err(|e| e.to_string())
+            .and_then(LegacyManifest::migrate)
+    } else {
+        serde_json::from_value::<Manifest>(json).map_err(|e| e.to_string())
+    };
+    let manifest = manifest.map_err(|e| format!("{}: {}", path.display(), e))?;
+    crate::validate_collection_name(&manifest.name)
+        .and_then(|_| manifest.config.validate(manifest.dim))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(manifest)
+}
+
+fn sealed_file(path: &Path) -> bool {
+    fs::read(path).is_ok_and(|contents| cipher::is_sealed(&contents))
+}
+
+/// Moves the directory of a collection that cannot be loaded under `.quarantine`.
+fn quarantine_dir(root: &Path, dir: &Path, error: String) -> Result<Recovery, String> {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    let target = root.join(QUARANTINE).join(format!("{}-{}", name, now_ms()));
+    let moved = fs::create_dir_all(root.join(QUARANTINE)).and_then(|_| fs::rename(dir, &target));
+    moved.map_err(|e| format!("{}: {}", target.display(), e))?;
+    log::error!(
+        "collection {} could not be loaded ({}); moved it to {}",
+        name,
+        error,
+        target.display()
+    );
+    Ok(Recovery {
+        collection: name.into_owned(),
+        quarantined: target.display().to_string(),
+        bad_lines: Vec::new(),
+        truncated_tail: false,
+        affected_ids: Vec::new(),
+        error: Some(error),
+    })
+}
+
+/// Splits a trash id into the collection name and deletion time. Only ids of that shape are
+/// accepted, so an id can never point outside the trash.
+fn parse_trash_id(id: &str) -> Option<(&str, u64)> {
+    let (name, deleted_ms) = id.rsplit_once('-')?;
+    crate::validate_collection_name(name).ok()?;
+    Some((name, deleted_ms.parse().ok()?))
+}
+
+fn remove_dir(dir: &Path) -> Result<(), String> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(format!("{}: {}", dir.display(), e))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis())
+}
+
+#[derive(Default)]
+struct Replayed {
+    records: Vec<VectorRecord>,
+    /// Readable entries, superseded ones included.
+    entries: usize,
+    bad_lines: Vec<usize>,
+    truncated_tail: bool,
+    affected_ids: Vec<u64>,
+    /// The log does not end in a newline.
+    unterminated: bool,
+    /// Some entries were written in the clear.
+    plain: bool,
+}
+
+/// Latest record of every id still present at the end of the log, in order of last write.
+/// Lines that cannot be parsed are skipped and reported, as are encrypted lines `cipher` cannot
+/// open; without a cipher those fail the replay.
+fn replay(path: &Path, cipher: Option<&Cipher>) -> Result<Replayed, String> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Replayed::default()),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let mut replayed = Replayed {
+        unterminated: contents.last().is_some_and(|b| *b != b'\n'),
+        ..Replayed::default()
+    };
+    let mut latest: HashMap<u64, (usize, VectorRecord)> = HashMap::new();
+    let lines: Vec<&[u8]> = contents.split(|b| *b == b'\n').collect();
+    for (n, line) in lines.iter().enumerate() {
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        if cipher.is_none() && cipher::is_sealed(line) {
+            return Err(format!(
+                "{}: is encrypted, but ENCRYPTION_KEY is not set",
+                path.display()
+            ));
+        }
+        replayed.plain |= !cipher::is_sealed(line);
+        let parsed = cipher::unseal(cipher, line)
+            .and_then(|line| serde_json::from_slice(&line).map_err(|e| e.to_string()));
+        let entry = match parsed {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("{} line {}: {}", path.display(), n + 1, e);
+                replayed.bad_lines.push(n + 1);
+                // Every complete write ends in a newline, so only the last line can be cut short.
+                replayed.truncated_tail = n + 1 == lines.len();
+                replayed.affected_ids.extend(salvage_id(line));
+                continue;
+            }
+        };
+        replayed.entries += 1;
+        match entry {
+            WalEntry::Upsert(record) => {
+                latest.insert(record.id, (n, record));
+            }
+            WalEntry::Delete { delete } => {
+                latest.remove(&delete);
+            }
+        }
+    }
+    let mut records: Vec<_> = latest.into_values().collect();
+    records.sort_by_key(|(n, _)| *n);
+    replayed.records = records.into_iter().map(|(_, r)| r).collect();
+    replayed.affected_ids.sort_unstable();
+    replayed.affected_ids.dedup();
+    Ok(replayed)
+}
+
+/// The point id of a damaged WAL line, when its start survived: records begin with `"id"` and
+/// deletions are `{"delete": id}`.
+fn salvage_id(line: &[u8]) -> Option<u64> {
+    let text = String::from_utf8_lossy(line);
+    ["\"id\":", "\"delete\":"].iter().find_map(|key| {
+        let rest = text[text.find(key)? + key.len()..].trim_start();
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        rest[..digits].parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// An empty directory of its own under the system temp dir.
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vector_db-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn storage(dir: &Path) -> Storage {
+        Storage {
+            dir: Some(dir.to_path_buf()),
+            trash_retention: Duration::ZERO,
+            checkpoint_every: Duration::ZERO,
+            cipher: None,
+            _lock: None,
+        }
+    }
+
+    /// A stored collection `c` of dimension 2 whose WAL holds `wal`.
+    fn collection(root: &Path, wal: &str) -> PathBuf {
+        let dir = root.join("c");
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = json!({"name": "c", "dim": 2, "config": {"distance": "cosine"}});
+        fs::write(dir.join(MANIFEST), manifest.to_string()).unwrap();
+        fs::write(dir.join(WAL), wal).unwrap();
+        dir
+    }
+
+    fn encrypted(dir: &Path) -> Storage {
+        Storage {
+            cipher: Some(Cipher::new(&[7; 32])),
+            ..storage(dir)
+        }
+    }
+
+    fn load(storage: &Storage) -> (Vec<Stored>, Vec<Recovery>) {
+        let mut loaded = Vec::new();
+        let mut recovered = Vec::new();
+        for pending in storage.pending().unwrap() {
+            let (stored, recovery) = storage.load_one(&pending).unwrap();
+            loaded.extend(stored);
+            recovered.extend(recovery);
+        }
+        (loaded, recovered)
+    }
+
+    fn ids(records: &[VectorRecord]) -> Vec<u64> {
+        records.iter().map(|r| r.id).collect()
+    }
+
+    #[test]
+    fn replay_keeps_the_last_write_per_id() {
+        let root = temp_dir();
+        let path = root.join(WAL);
+        fs::write(
+            &path,
+            concat!(
+                "{\"id\":1,\"vector\":[1,0],\"payload\":{\"v\":1}}\n",
+                "{\"id\":2,\"vector\":[0,1],\"payload\":null}\n",
+                "{\"id\":3,\"vector\":[1,1],\"payload\":null}\n",
+                "{\"delete\":2}\n",
+                "\n",
+                "{\"id\":1,\"vector\":[1,0],\"payload\":{\"v\":2}}\n",
+            ),
+        )
+        .unwrap();
+        let replayed = replay(&path, None).unwrap();
+        assert_eq!(ids(&replayed.records), [3, 1]);
+        assert_eq!(replayed.entries, 5);
+        assert_eq!(replayed.records[1].payload, json!({"v": 2}));
+        assert!(replayed.bad_lines.is_empty());
+        assert!(!replayed.unterminated);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn replay_of_a_missing_wal_is_empty() {
+        let root = temp_dir();
+        let replayed = replay(&root.join(WAL), None).unwrap();
+        assert!(replayed.records.is_empty() && replayed.bad_lines.is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn replay_reports_a_truncated_tail() {
+        let root = temp_dir();
+        let path = root.join(WAL);
+        let wal = "{\"id\":1,\"vector\":[1,0],\"payload\":null}\n{\"id\":7,\"vector\":[0";
+        fs::write(&path, wal).unwrap();
+        let replayed = replay(&path, None).unwrap();
+        assert_eq!(ids(&replayed.records), [1]);
+        assert_eq!(replayed.bad_lines, [2]);
+        assert!(replayed.truncated_tail);
+        assert_eq!(replayed.affected_ids, [7]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn replay_skips_a_damaged_line_in_the_middle() {
+        let root = temp_dir();
+        let path = root.join(WAL);
+        let wal = "{\"delete\":4\n{\"id\":1,\"vector\":[1,0],\"payload\":null}\n";
+        fs::write(&path, wal).unwrap();
+        let replayed = replay(&path, None).unwrap();
+        assert_eq!(ids(&replayed.records), [1]);
+        assert_eq!(replayed.bad_lines, [1]);
+        assert!(!replayed.truncated_tail);
+        assert_eq!(replayed.affected_ids, [4]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn load_rewrites_a_damaged_wal_and_keeps_the_original() {
+        let root = temp_dir();
+        let wal = "{\"id\":1,\"vector\":[1,0],\"payload\":null}\n{\"id\":2,\"vec";
+        let dir = collection(&root, wal);
+        let (loaded, recovered) = load(&storage(&root));
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(ids(&loaded[0].1), [1]);
+        assert_eq!(recovered.len(), 1);
+        assert!(recovered[0].truncated_tail);
+        assert_eq!(fs::read_to_string(&recovered[0].quarantined).unwrap(), wal);
+        assert_eq!(loaded[0].2.entries(), 1);
+        let rewritten = replay(&dir.join(WAL), None).unwrap();
+        assert!(rewritten.bad_lines.is_empty());
+        assert_eq!(ids(&rewritten.records), [1]);
+        drop(loaded);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn load_terminates_a_whole_last_line_before_appending() {
+        let root = temp_dir();
+        let dir = collection(&root, "{\"id\":1,\"vector\":[1,0],\"payload\":null}");
+        let (mut loaded, recovered) = load(&storage(&root));
+        assert!(recovered.is_empty());
+        let (_, records, mut wal) = loaded.pop().unwrap();
+        assert_eq!(ids(&records), [1]);
+        assert_eq!(wal.entries(), 1);
+        wal.append(&[WalEntry::Delete { delete: 1 }]).unwrap();
+        assert_eq!(wal.entries(), 2);
+        let replayed = replay(&dir.join(WAL), None).unwrap();
+        assert!(replayed.bad_lines.is_empty());
+        assert!(replayed.records.is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn sync_clears_the_unsynced_writes() {
+        let root = temp_dir();
+        let mut wal = Wal::open(root.join(WAL), None).unwrap();
+        wal.append(&[]).unwrap();
+        assert!(wal.unsynced_since().is_none());
+        wal.append(&[WalEntry::Delete { delete: 1 }]).unwrap();
+        let since = wal.unsynced_since().unwrap();
+        wal.append(&[WalEntry::Delete { delete: 2 }]).unwrap();
+        assert_eq!(wal.unsynced_since(), Some(since));
+        wal.sync().unwrap();
+        assert!(wal.unsynced_since().is_none());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn fsync_policies() {
+        let policy = |v| serde_json::from_value::<FsyncPolicy>(v);
+        let always = policy(json!({"policy": "always"})).unwrap();
+        assert_eq!(always, FsyncPolicy::Always);
+        assert_eq!(policy(json!({"policy": "os"})).unwrap(), FsyncPolicy::Os);
+        let interval = policy(json!({"policy": "interval", "interval_ms": 50})).unwrap();
+        assert_eq!(interval, FsyncPolicy::Interval { interval_ms: 50 });
+        assert!(policy(json!({"policy": "interval"})).is_err());
+        let never = policy(json!({"policy": "interval", "interval_ms": 0})).unwrap();
+        assert!(never.validate().is_err());
+    }
+
+    #[test]
+    fn load_reads_legacy_manifests() {
+        let root = temp_dir();
+        let dir = collection(&root, "");
+        let legacy = json!({
+            "name": "c",
+            "dim": 2,
+            "metric": "Euclidean",
+            "hnsw": {"m": 8, "ef_construction": 100, "ef_search": 20}
+        });
+        fs::write(dir.join(MANIFEST), legacy.to_string()).unwrap();
+        let (loaded, recovered) = load(&storage(&root));
+        assert!(recovered.is_empty());
+        let config = &loaded[0].0.config;
+        assert_eq!(config.distance, "l2");
+        assert_eq!(config.hnsw.max_nb_connection, 8);
+        assert_eq!(config.hnsw.ef_construction, 100);
+        drop(loaded);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn load_quarantines_an_invalid_manifest() {
+        let root = temp_dir();
+        let dir = collection(&root, "");
+        let manifest = json!({"name": "c", "dim": 0, "config": {"distance": "cosine"}});
+        fs::write(dir.join(MANIFEST), manifest.to_string()).unwrap();
+        let (loaded, recovered) = load(&storage(&root));
+        assert!(loaded.is_empty());
+        assert_eq!(recovered.len(), 1);
+        assert!(recovered[0].error.is_some());
+        assert!(!dir.exists());
+        let quarantined = Path::new(&recovered[0].quarantined);
+        assert!(quarantined.join(MANIFEST).is_file());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn encrypted_collections_round_trip() {
+        let root = temp_dir();
+        let sealed = encrypted(&root);
+        let manifest = json!({"name": "c", "dim": 2, "config": {"distance": "cosine"}});
+        let manifest: Manifest = serde_json::from_value(manifest).unwrap();
+        let mut wal = sealed.create(&manifest).unwrap().unwrap();
+        let record = json!({"id": 1, "vector": [1, 0], "payload": {"lang": "en"}});
+        let record = serde_json::from_value(record).unwrap();
+        wal.append(&[WalEntry::Upsert(record), WalEntry::Delete { delete: 2 }])
+            .unwrap();
+        drop(wal);
+        for file in [MANIFEST, WAL] {
+            let contents = fs::read_to_string(root.join("c").join(file)).unwrap();
+            assert!(!contents.contains("cosine") && !contents.contains("lang"));
+        }
+        let (loaded, recovered) = load(&sealed);
+        assert!(recovered.is_empty());
+        assert_eq!(loaded[0].0.config.distance, "cosine");
+        assert_eq!(loaded[0].1[0].payload, json!({"lang": "en"}));
+        assert_eq!(loaded[0].2.entries(), 2);
+        drop(loaded);
+        let keyless = storage(&root);
+        assert!(keyless.load_one(&keyless.pending().unwrap()[0]).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn load_encrypts_collections_written_in_the_clear() {
+        let root = temp_dir();
+        let dir = collection(&root, "{\"id\":1,\"vector\":[1,0],\"payload\":null}\n");
+        let (loaded, recovered) = load(&encrypted(&root));
+        assert!(recovered.is_empty());
+        assert_eq!(ids(&loaded[0].1), [1]);
+        drop(loaded);
+        assert!(sealed_file(&dir.join(MANIFEST)));
+        let wal = fs::read(dir.join(WAL)).unwrap();
+        let mut lines = wal.split(|b| *b == b'\n').filter(|line| !line.is_empty());
+        assert!(lines.all(cipher::is_sealed));
+        let (loaded, _) = load(&encrypted(&root));
+        assert_eq!(ids(&loaded[0].1), [1]);
+        drop(loaded);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn startup_needs_the_key_the_directory_was_encrypted_with() {
+        let root = temp_dir();
+        check_key(&root, None).unwrap();
+        check_key(&root, Some(&Cipher::new(&[7; 32]))).unwrap();
+        check_key(&root, Some(&Cipher::new(&[7; 32]))).unwrap();
+        assert!(check_key(&root, Some(&Cipher::new(&[8; 32]))).is_err());
+        assert!(check_key(&root, None).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn blobs_are_kept_while_an_entry_refers_to_them() {
+        let root = temp_dir();
+        let sealed = encrypted(&root);
+        let manifest = json!({"name": "c", "dim": 2, "config": {"distance": "cosine"}});
+        let manifest: Manifest = serde_json::from_value(manifest).unwrap();
+        let mut wal = sealed.create(&manifest).unwrap().unwrap();
+        let full = json!({"lang": "en", "body": "x".repeat(100)});
+        let mut stored = full.clone();
+        payload::offload(&mut stored, 64, |_| false, |json| wal.blobs().put(json)).unwrap();
+        assert_eq!(stored["lang"], "en");
+        let id = payload::blob_ref(&stored["body"]).unwrap().to_string();
+        let file = fs::read(root.join("c").join(BLOBS).join(&id)).unwrap();
+        assert!(cipher::is_sealed(&file));
+        let stale = wal.blobs().put(b"\"old\"").unwrap();
+        let record = json!({"id": 1, "vector": [1, 0], "payload": stored});
+        let record = serde_json::from_value(record).unwrap();
+        wal.append(&[WalEntry::Upsert(record)]).unwrap();
+        drop(wal);
+        let (mut loaded, _) = load(&sealed);
+        let (_, records, mut wal) = loaded.remove(0);
+        let mut read = records[0].payload.clone();
+        payload::inline(&mut read, |_| true, |id| wal.blobs().get(id)).unwrap();
+        assert_eq!(read, full);
+        assert!(wal.blobs().get("../manifest.json").is_err());
+        let entries: Vec<WalEntry> = records.into_iter().map(WalEntry::Upsert).collect();
+        wal.rewrite(&entries).unwrap();
+        assert!(wal.blobs().get(&id).is_ok());
+        assert!(wal.blobs().get(&stale).is_err());
+        wal.rewrite(&[]).unwrap();
+        assert!(wal.blobs().get(&id).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
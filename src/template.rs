@@ -0,0 +1,83 @@
+use serde_json::{Map, Value};
+
+/// A stored search request without its `query`. Any string value `"$name"` is a parameter,
+/// replaced by the caller's value for `name` when the template is run; `"$$..."` stands for a
+/// literal string starting with `$`.
+pub type Template = Map<String, Value>;
+
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid_chars = name
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    if name.is_empty() || name.len() > 64 || !valid_chars {
+        return Err(format!(
+            "invalid template name {:?}; use 1-64 ASCII letters, digits, '_' or '-'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Checks what can be checked before parameters are known.
+pub fn check(template: &Template) -> Result<(), String> {
+    if template.contains_key("query") {
+        return Err("a template cannot fix the query vector".to_string());
+    }
+    Ok(())
+}
+
+/// Names of the parameters used in `template`, sorted.
+pub fn parameters(template: &Template) -> Vec<String> {
+    fn collect(value: &Value, names: &mut Vec<String>) {
+        match value {
+            Value::String(s) => {
+                if let Some(name) = param_name(s) {
+                    names.push(name.to_string());
+                }
+            }
+            Value::Array(items) => items.iter().for_each(|v| collect(v, names)),
+            Value::Object(map) => map.values().for_each(|v| collect(v, names)),
+            _ => {}
+        }
+    }
+    let mut names = Vec::new();
+    template.values().for_each(|v| collect(v, &mut names));
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The template with every parameter replaced by its value in `params`.
+pub fn render(template: &Template, params: &Map<String, Value>) -> Result<Template, String> {
+    template
+        .iter()
+        .map(|(k, v)| Ok((k.clone(), substitute(v, params)?)))
+        .collect()
+}
+
+fn substitute(value: &Value, params: &Map<String, Value>) -> Result<Value, String> {
+    Ok(match value {
+        Value::String(s) => match param_name(s) {
+            Some(name) => params
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("missing template parameter {:?}", name))?,
+            None if s.starts_with("$$") => Value::String(s[1..].to_string()),
+            None => value.clone(),
+        },
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|v| substitute(v, params))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(map) => Value::Object(render(map, params)?),
+        other => other.clone(),
+    })
+}
+
+/// `name` for a `"$name"` placeholder; `None` for plain strings and `"$$"` escapes.
+fn param_name(s: &str) -> Option<&str> {
+    s.strip_prefix('$')
+        .filter(|name| !name.is_empty() && !name.starts_with('$'))
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Writes may run this far ahead of the rate undelayed, so short bursts are not slowed down.
+const BURST: Duration = Duration::from_secs(1);
+
+/// Longest a write is held back; writes that would wait longer are rejected.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Caps the rate points are written to a collection, so bulk loads leave room for searches:
+/// `{"max_points_per_sec": 1000}`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThrottleConfig {
+    pub max_points_per_sec: u64,
+}
+
+impl ThrottleConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_points_per_sec == 0 {
+            return Err("write_throttle.max_points_per_sec must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// Most points one write may carry: as many as the rate absorbs within the longest delay.
+    pub fn max_batch(&self) -> usize {
+        let secs = (BURST + MAX_DELAY).as_secs();
+        self.max_points_per_sec.saturating_mul(secs) as usize
+    }
+
+    fn duration_of(&self, points: usize) -> Duration {
+        Duration::from_secs_f64(points as f64 / self.max_points_per_sec as f64)
+    }
+}
+
+/// Why a write was not admitted.
+pub enum Rejection {
+    /// Earlier writes fill the throttle; retry after this long.
+    Busy(Duration),
+    /// The write holds more points than `max_batch`.
+    TooLarge(usize),
+}
+
+/// A collection's write pacing: the time the points admitted so far take to write at the rate.
+#[derive(Default)]
+pub struct Throttle {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// When the admitted points are paid off.
+    busy_until: Option<Instant>,
+    delayed: u64,
+    rejected: u64,
+}
+
+impl Throttle {
+    /// Admits a write of `points` under `config`, returning how long to hold it back first.
+    pub fn admit(
+        &self,
+        config: Option<&ThrottleConfig>,
+        points: usize,
+    ) -> Result<Duration, Rejection> {
+        let Some(config) = config else {
+            return Ok(Duration::ZERO);
+        };
+        let mut state = self.state.lock().unwrap();
+        if points > config.max_batch() {
+            state.rejected += 1;
+            return Err(Rejection::TooLarge(config.max_batch()));
+        }
+        let now = Instant::now();
+        let start = state.busy_until.filter(|t| *t > now).unwrap_or(now);
+        let busy_until = start + config.duration_of(points);
+        let delay = busy_until.saturating_duration_since(now + BURST);
+        if delay > MAX_DELAY {
+            state.rejected += 1;
+            return Err(Rejection::Busy(delay - MAX_DELAY));
+        }
+        if !delay.is_zero() {
+            state.delayed += 1;
+        }
+        state.busy_until = Some(busy_until);
+        Ok(delay)
+    }
+
+    /// Current state for the collection status; `None` without a throttle.
+    pub fn status(&self, config: Option<&ThrottleConfig>) -> Option<ThrottleStatus> {
+        let config = config?;
+        let state = self.state.lock().unwrap();
+        let backlog = state.busy_until.map_or(Duration::ZERO, |t| {
+            t.saturating_duration_since(Instant::now())
+        });
+        Some(ThrottleStatus {
+            max_points_per_sec: config.max_points_per_sec,
+            backlog_ms: backlog.as_millis() as u64,
+            throttling: backlog > BURST,
+            delayed_writes: state.delayed,
+            rejected_writes: state.rejected,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct ThrottleStatus {
+    pub max_points_per_sec: u64,
+    /// Time the writes admitted so far take at the rate.
+    pub backlog_ms: u64,
+    /// Whether a write arriving now is held back, i.e. the backlog exceeds the one-second burst.
+    pub throttling: bool,
+    /// Writes held back and rejected since the collection was loaded.
+    pub delayed_writes: u64,
+    pub rejected_writes: u64,
+}
@@ -0,0 +1,126 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderValue},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use std::{fs::File, io::BufReader, net::SocketAddr};
+
+/// HSTS policy sent on every response served over TLS.
+const HSTS: &str = "max-age=31536000";
+
+/// Native TLS for the TCP listeners.
+pub struct Tls {
+    pub config: rustls::ServerConfig,
+    /// Plain-HTTP listener that only redirects to HTTPS.
+    pub redirect: Option<SocketAddr>,
+    /// Port clients are redirected to, the public listener's.
+    pub https_port: u16,
+}
+
+impl Tls {
+    /// Reads `TLS_CERT` and `TLS_KEY`, PEM files with the certificate chain and private key, and
+    /// `HTTP_REDIRECT_PORT`, bound on `public`'s address. `None` when TLS is not configured.
+    pub fn from_env(public: SocketAddr) -> Result<Option<Self>, String> {
+        let (cert, key) = match (std::env::var("TLS_CERT"), std::env::var("TLS_KEY")) {
+            (Ok(cert), Ok(key)) => (cert, key),
+            (Err(_), Err(_)) => {
+                if std::env::var("HTTP_REDIRECT_PORT").is_ok() {
+                    return Err("HTTP_REDIRECT_PORT needs TLS_CERT and TLS_KEY".to_string());
+                }
+                return Ok(None);
+            }
+            _ => return Err("TLS_CERT and TLS_KEY must be set together".to_string()),
+        };
+        let certs = rustls_pemfile::certs(&mut open(&cert)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("could not read TLS_CERT {}: {}", cert, e))?;
+        if certs.is_empty() {
+            return Err(format!("TLS_CERT {} holds no certificate", cert));
+        }
+        let private_key = rustls_pemfile::private_key(&mut open(&key)?)
+            .map_err(|e| format!("could not read TLS_KEY {}: {}", key, e))?
+            .ok_or_else(|| format!("TLS_KEY {} holds no private key", key))?;
+        let provider = rustls::crypto::ring::default_provider();
+        let config = rustls::ServerConfig::builder_with_provider(provider.into())
+            .with_safe_default_protocol_versions()
+            .and_then(|b| b.with_no_client_auth().with_single_cert(certs, private_key))
+            .map_err(|e| format!("invalid TLS certificate or key: {}", e))?;
+        let redirect = match std::env::var("HTTP_REDIRECT_PORT") {
+            Ok(v) => Some(SocketAddr::new(
+                public.ip(),
+                v.parse()
+                    .map_err(|_| format!("invalid HTTP_REDIRECT_PORT {:?}", v))?,
+            )),
+            Err(_) => None,
+        };
+        Ok(Some(Self {
+            config,
+            redirect,
+            https_port: public.port(),
+        }))
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("could not open {}: {}", path, e))
+}
+
+/// What [`enforce_https`] needs from the TLS setup.
+#[derive(Clone, Copy)]
+pub struct HttpsPolicy {
+    pub redirect: Option<SocketAddr>,
+    pub https_port: u16,
+}
+
+impl From<&Tls> for HttpsPolicy {
+    fn from(tls: &Tls) -> Self {
+        Self {
+            redirect: tls.redirect,
+            https_port: tls.https_port,
+        }
+    }
+}
+
+/// Redirects requests reaching the plain-HTTP listener to HTTPS with 308, which keeps the method
+/// and body, and marks responses served over TLS with `Strict-Transport-Security`.
+pub async fn enforce_https(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let policy = req.app_data::<web::Data<Option<HttpsPolicy>>>();
+    let Some(policy) = policy.and_then(|p| *p.get_ref()) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    if policy
+        .redirect
+        .is_some_and(|redirect| req.app_config().local_addr() == redirect)
+    {
+        let host = req.connection_info().host().to_string();
+        let host = match host.rsplit_once(':') {
+            // Keep IPv6 literals like `[::1]` whole.
+            Some((name, port)) if !port.contains(']') => name.to_string(),
+            _ => host,
+        };
+        let location = match policy.https_port {
+            443 => format!("https://{}{}", host, req.uri()),
+            port => format!("https://{}:{}{}", host, port, req.uri()),
+        };
+        let resp = HttpResponse::PermanentRedirect()
+            .insert_header((header::LOCATION, location))
+            .finish();
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+    let secure = req.app_config().secure();
+    let mut res = next.call(req).await?;
+    if secure {
+        res.headers_mut().insert(
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static(HSTS),
+        );
+    }
+    Ok(res.map_into_left_body())
+}
@@ -0,0 +1,180 @@
+use crate::auth::{self, Action};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Sets tokens apart from API keys.
+const PREFIX: &str = "vdbt_";
+
+/// Lifetime of tokens minted without `ttl_secs`.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// What a token lets its holder do, signed into the token itself.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Claims {
+    pub collection: String,
+    pub actions: Vec<Action>,
+    /// Point tags the holder may see, as granted by `API_KEY_TAGS`; unset means every point.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// Seconds since the Unix epoch.
+    pub expires: u64,
+}
+
+impl Claims {
+    /// Claims on `collection` until `ttl` from now.
+    pub fn new(
+        collection: String,
+        actions: Vec<Action>,
+        tags: Option<Vec<String>>,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            collection,
+            actions,
+            tags,
+            expires: now_secs() + ttl.as_secs(),
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        now_secs() >= self.expires
+    }
+}
+
+/// Signs and checks short-lived tokens scoped to one collection, for clients that should not
+/// hold an API key.
+pub struct Tokens {
+    /// The HMAC key, padded to one SHA-256 block.
+    key: [u8; 64],
+    pub max_ttl: Duration,
+}
+
+impl Tokens {
+    /// Reads `TOKEN_SECRET`, the key tokens are signed with, and `TOKEN_MAX_TTL_SECS` (default a
+    /// day). Without a secret one is drawn at startup, so tokens do not survive a restart and are
+    /// not accepted by other nodes.
+    pub fn from_env() -> Result<Self, String> {
+        let secret = match std::env::var("TOKEN_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+            _ => [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+                .iter()
+                .flat_map(|u| *u.as_bytes())
+                .collect(),
+        };
+        let max_ttl = match std::env::var("TOKEN_MAX_TTL_SECS") {
+            Ok(v) => v.parse().map(Duration::from_secs).map_err(|_| {
+                format!(
+                    "TOKEN_MAX_TTL_SECS must be a non-negative integer, got {:?}",
+                    v
+                )
+            })?,
+            Err(_) => Duration::from_secs(24 * 60 * 60),
+        };
+        Ok(Self::new(&secret, max_ttl))
+    }
+
+    /// Tokens signed with `secret`.
+    pub fn new(secret: &[u8], max_ttl: Duration) -> Self {
+        let mut key = [0; 64];
+        // Keys longer than a block are hashed first (RFC 2104).
+        if secret.len() > key.len() {
+            key[..32].copy_from_slice(&Sha256::digest(secret));
+        } else {
+            key[..secret.len()].copy_from_slice(secret);
+        }
+        Self { key, max_ttl }
+    }
+
+    pub fn mint(&self, claims: &Claims) -> String {
+        let body = hex(&serde_json::to_vec(claims).expect("claims serialize"));
+        let mac = hex(&self.sign(body.as_bytes()));
+        format!("{}{}.{}", PREFIX, body, mac)
+    }
+
+    /// The claims of a token this server signed, expired or not; `None` for anything else.
+    pub fn verify(&self, token: &str) -> Option<Claims> {
+        let (body, mac) = token.strip_prefix(PREFIX)?.split_once('.')?;
+        let expected = hex(&self.sign(body.as_bytes()));
+        if !auth::constant_time_eq(expected.as_bytes(), mac.as_bytes()) {
+            return None;
+        }
+        serde_json::from_slice(&unhex(body)?).ok()
+    }
+
+    /// HMAC-SHA256 of `message`.
+    fn sign(&self, message: &[u8]) -> [u8; 32] {
+        let inner = Sha256::new()
+            .chain_update(self.key.map(|b| b ^ 0x36))
+            .chain_update(message)
+            .finalize();
+        Sha256::new()
+            .chain_update(self.key.map(|b| b ^ 0x5c))
+            .chain_update(inner)
+            .finalize()
+            .into()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(ttl: Duration) -> Claims {
+        Claims::new("c".to_string(), vec![Action::Search], None, ttl)
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231, test case 2.
+        let tokens = Tokens::new(b"Jefe", DEFAULT_TTL);
+        assert_eq!(
+            hex(&tokens.sign(b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn minted_tokens_verify() {
+        let tokens = Tokens::new(b"secret", DEFAULT_TTL);
+        let claims = claims(DEFAULT_TTL);
+        let token = tokens.mint(&claims);
+        assert_eq!(tokens.verify(&token).as_ref(), Some(&claims));
+        assert!(!tokens.verify(&token).unwrap().expired());
+        let expired = tokens.mint(&Claims::new("c".to_string(), vec![], None, Duration::ZERO));
+        assert!(tokens.verify(&expired).unwrap().expired());
+    }
+
+    #[test]
+    fn altered_tokens_are_rejected() {
+        let tokens = Tokens::new(b"secret", DEFAULT_TTL);
+        let token = tokens.mint(&claims(DEFAULT_TTL));
+        let other = Tokens::new(b"other", DEFAULT_TTL);
+        assert!(other.verify(&token).is_none());
+        let (body, mac) = token.split_once('.').unwrap();
+        let widened = hex(br#"{"collection":"d","actions":["write"],"expires":99999999999}"#);
+        assert!(tokens
+            .verify(&format!("{}{}.{}", PREFIX, widened, mac))
+            .is_none());
+        assert!(tokens.verify(&format!("{}.{}", body, &mac[1..])).is_none());
+        assert!(tokens.verify(&token[PREFIX.len()..]).is_none());
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{
+    auth::{KeyId, Role},
+    error::ApiError,
+    request_id::RequestId,
+};
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    web, Error, HttpMessage, HttpResponse,
+};
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Error code of requests refused over a key's quota.
+const CODE: &str = "quota_exceeded";
+
+/// Error code of writes refused for want of a `Content-Length` to check the byte quota against.
+const LENGTH_CODE: &str = "length_required";
+
+#[derive(Default)]
+struct Counters {
+    requests: u64,
+    compute: Duration,
+    bytes_written: u64,
+}
+
+/// What one key used in the accounting period.
+#[derive(Serialize)]
+pub struct KeyUsage {
+    /// The key's [`KeyId`].
+    pub key: String,
+    pub requests: u64,
+    /// Time spent handling the key's requests.
+    pub compute_ms: u64,
+    /// Request body bytes read for the key's successful writes.
+    pub bytes_written: u64,
+}
+
+#[derive(Serialize)]
+pub struct UsageReport {
+    /// Start of the accounting period, in milliseconds since the Unix epoch.
+    pub since_ms: u64,
+    pub keys: Vec<KeyUsage>,
+}
+
+struct Period {
+    since: SystemTime,
+    keys: HashMap<KeyId, Counters>,
+}
+
+/// Usage of each API key since the server started or the accounting period was last reset,
+/// with optional per-key quotas over the period.
+pub struct Usage {
+    max_requests: Option<u64>,
+    max_write_bytes: Option<u64>,
+    period: Mutex<Period>,
+}
+
+impl Usage {
+    /// Reads `USAGE_MAX_REQUESTS` and `USAGE_MAX_WRITE_BYTES`, each key's quota per accounting
+    /// period. Unset means no quota.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            max_requests: env_u64("USAGE_MAX_REQUESTS")?,
+            max_write_bytes: env_u64("USAGE_MAX_WRITE_BYTES")?,
+            period: Mutex::new(Period {
+                since: SystemTime::now(),
+                keys: HashMap::new(),
+            }),
+        })
+    }
+
+    pub fn report(&self) -> UsageReport {
+        report(&self.period.lock().unwrap())
+    }
+
+    /// Ends the accounting period, returning its report; counting starts again from zero.
+    pub fn reset(&self) -> UsageReport {
+        let mut period = self.period.lock().unwrap();
+        let ended = report(&period);
+        *period = Period {
+            since: SystemTime::now(),
+            keys: HashMap::new(),
+        };
+        ended
+    }
+
+    /// Why a request writing `bytes` would take `key` over its quota, if it would.
+    fn over_quota(&self, key: &KeyId, bytes: u64) -> Option<String> {
+        let period = self.period.lock().unwrap();
+        let used = period.keys.get(key);
+        let requests = used.map_or(0, |c| c.requests);
+        if let Some(max) = self.max_requests.filter(|max| requests >= *max) {
+            return Some(format!("API key used its {} requests for this period", max));
+        }
+        let written = used.map_or(0, |c| c.bytes_written);
+        if let Some(max) = self
+            .max_write_bytes
+            .filter(|max| bytes > 0 && written + bytes > *max)
+        {
+            return Some(format!(
+                "API key wrote {} of its {} bytes for this period; this request has {}",
+                written, max, bytes
+            ));
+        }
+        None
+    }
+
+    fn record(&self, key: KeyId, compute: Duration, bytes_written: u64) {
+        let mut period = self.period.lock().unwrap();
+        let counters = period.keys.entry(key).or_default();
+        counters.requests += 1;
+        counters.compute += compute;
+        counters.bytes_written += bytes_written;
+    }
+}
+
+fn report(period: &Period) -> UsageReport {
+    let mut keys: Vec<KeyUsage> = period
+        .keys
+        .iter()
+        .map(|(key, c)| KeyUsage {
+            key: key.0.clone(),
+            requests: c.requests,
+            compute_ms: c.compute.as_millis() as u64,
+            bytes_written: c.bytes_written,
+        })
+        .collect();
+    keys.sort_by(|a, b| a.key.cmp(&b.key));
+    let since = period.since.duration_since(UNIX_EPOCH).unwrap_or_default();
+    UsageReport {
+        since_ms: since.as_millis() as u64,
+        keys,
+    }
+}
+
+fn env_u64(key: &str) -> Result<Option<u64>, String> {
+    match std::env::var(key) {
+        Ok(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} must be a non-negative integer, got {:?}", key, v)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Counts each authenticated request against its key, refusing it with 429 when it would go over
+/// the key's quota. Admin keys have no quota, so they can always read and reset the usage. A
+/// write's bytes are counted as the handler reads its body, so chunked uploads count too; with a
+/// byte quota, writes must declare their `Content-Length` up front for it to be checked.
+pub async fn track(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let usage = req.app_data::<web::Data<Usage>>().cloned();
+    let key = req.extensions().get::<KeyId>().cloned();
+    let (Some(usage), Some(key)) = (usage, key) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let write = !matches!(*req.method(), Method::GET | Method::HEAD);
+    let declared: Option<u64> = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse().ok());
+    let admin = req.extensions().get::<Role>() == Some(&Role::Admin);
+    if write && declared.is_none() && usage.max_write_bytes.is_some() && !admin {
+        let message = "Writes need a Content-Length while a byte quota is set".to_string();
+        return Ok(refuse(
+            req,
+            StatusCode::LENGTH_REQUIRED,
+            LENGTH_CODE,
+            message,
+        ));
+    }
+    let bytes = if write { declared.unwrap_or(0) } else { 0 };
+    if let Some(message) = usage.over_quota(&key, bytes).filter(|_| !admin) {
+        return Ok(refuse(req, StatusCode::TOO_MANY_REQUESTS, CODE, message));
+    }
+    let received = Arc::new(AtomicU64::new(0));
+    if write {
+        let counter = received.clone();
+        let body = req.take_payload().inspect_ok(move |chunk| {
+            counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        });
+        req.set_payload(Payload::Stream {
+            payload: Box::pin(body),
+        });
+    }
+    let started = Instant::now();
+    let res = next.call(req).await?;
+    let written = match res.status().is_success() {
+        true => received.load(Ordering::Relaxed),
+        false => 0,
+    };
+    usage.record(key, started.elapsed(), written);
+    Ok(res.map_into_left_body())
+}
+
+fn refuse<B>(
+    req: ServiceRequest,
+    status: StatusCode,
+    code: &str,
+    message: String,
+) -> ServiceResponse<EitherBody<B>> {
+    let error = ApiError {
+        status: status.as_u16(),
+        code: code.to_string(),
+        message,
+        request_id: req
+            .extensions()
+            .get::<RequestId>()
+            .map(|r| r.0.clone())
+            .unwrap_or_default(),
+    };
+    let resp = HttpResponse::build(status).json(error);
+    req.into_response(resp).map_into_right_body()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        dev::Service,
+        middleware::from_fn,
+        test::{call_service, init_service, TestRequest},
+        App,
+    };
+
+    fn usage(max_requests: Option<u64>, max_write_bytes: Option<u64>) -> Usage {
+        Usage {
+            max_requests,
+            max_write_bytes,
+            period: Mutex::new(Period {
+                since: SystemTime::now(),
+                keys: HashMap::new(),
+            }),
+        }
+    }
+
+    #[test]
+    fn keys_are_counted_apart() {
+        let usage = usage(None, None);
+        let (a, b) = (KeyId::of("a"), KeyId::of("b"));
+        usage.record(a.clone(), Duration::from_millis(5), 100);
+        usage.record(a.clone(), Duration::from_millis(7), 0);
+        usage.record(b, Duration::from_millis(1), 10);
+        let report = usage.report();
+        let a_usage = report.keys.iter().find(|k| k.key == a.0).unwrap();
+        assert_eq!(a_usage.requests, 2);
+        assert_eq!(a_usage.compute_ms, 12);
+        assert_eq!(a_usage.bytes_written, 100);
+        assert_eq!(report.keys.len(), 2);
+    }
+
+    #[test]
+    fn quotas_hold_until_reset() {
+        let usage = usage(Some(2), Some(100));
+        let key = KeyId::of("a");
+        assert!(usage.over_quota(&key, 100).is_none());
+        assert!(usage.over_quota(&key, 101).is_some());
+        usage.record(key.clone(), Duration::ZERO, 60);
+        assert!(usage.over_quota(&key, 0).is_none());
+        assert!(usage.over_quota(&key, 50).is_some());
+        usage.record(key.clone(), Duration::ZERO, 0);
+        assert!(usage.over_quota(&key, 0).is_some());
+        assert_eq!(usage.reset().keys[0].requests, 2);
+        assert!(usage.over_quota(&key, 0).is_none());
+        assert!(usage.report().keys.is_empty());
+    }
+
+    #[test]
+    fn key_ids_do_not_reveal_keys() {
+        let id = KeyId::of("secret");
+        assert_eq!(id.0.len(), 16);
+        assert!(!id.0.contains("secret"));
+        assert_eq!(id, KeyId::of("secret"));
+        assert_ne!(id, KeyId::of("secret2"));
+    }
+
+    /// Posts `body` without a `Content-Length`, as a chunked upload is sent, for key `a` through
+    /// `track`, answering with the status.
+    async fn post_unlengthed(usage: web::Data<Usage>, body: &'static str) -> StatusCode {
+        let app = init_service(
+            App::new()
+                .app_data(usage)
+                .wrap(from_fn(track))
+                .wrap_fn(|req, srv| {
+                    req.extensions_mut().insert(KeyId::of("a"));
+                    req.extensions_mut().insert(Role::Write);
+                    srv.call(req)
+                })
+                .route("/", web::post().to(|body: web::Bytes| async move { body })),
+        )
+        .await;
+        let mut req = TestRequest::post().set_payload(body).to_request();
+        req.headers_mut().remove(header::CONTENT_LENGTH);
+        call_service(&app, req).await.status()
+    }
+
+    #[actix_web::test]
+    async fn bodies_count_as_read_without_a_length() {
+        let usage = web::Data::new(usage(None, None));
+        assert_eq!(
+            post_unlengthed(usage.clone(), "0123456789").await,
+            StatusCode::OK
+        );
+        assert_eq!(usage.report().keys[0].bytes_written, 10);
+    }
+
+    #[actix_web::test]
+    async fn byte_quotas_need_a_length() {
+        let usage = web::Data::new(usage(None, Some(100)));
+        let status = post_unlengthed(usage.clone(), "0123456789").await;
+        assert_eq!(status, StatusCode::LENGTH_REQUIRED);
+        assert!(usage.report().keys.is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{distance::DotProduct, CollectionConfig};
+use hnsw_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// How a collection finds nearest neighbours.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexType {
+    /// An HNSW graph: approximate, fast on large collections.
+    #[default]
+    Hnsw,
+    /// No graph: every search scores all points, for exact results on small collections.
+    Flat,
+}
+
+/// A collection's ANN graph, typed by its distance. Clones share the same graph.
+#[derive(Clone)]
+pub enum VectorIndex<'a> {
+    L2(Arc<Hnsw<'a, f32, DistL2>>),
+    Cosine(Arc<Hnsw<'a, f32, DistCosine>>),
+    Dot(Arc<Hnsw<'a, f32, DotProduct>>),
+    /// A `flat` collection's: just its distance, as the collection scans its own records.
+    Flat(Arc<dyn Distance<f32> + Send + Sync>),
+}
+
+/// Evaluates `$body` with `$hnsw` bound to the graph, whichever distance it uses. Without a
+/// graph, evaluates to the default of `$body`'s type.
+macro_rules! with_graph {
+    ($index:expr, $hnsw:ident => $body:expr) => {
+        match $index {
+            VectorIndex::L2($hnsw) => $body,
+            VectorIndex::Cosine($hnsw) => $body,
+            VectorIndex::Dot($hnsw) => $body,
+            VectorIndex::Flat(_) => Default::default(),
+        }
+    };
+}
+
+impl<'a> VectorIndex<'a> {
+    /// An empty graph for `config`, whose distance has already been validated.
+    pub fn new(config: &CollectionConfig) -> Self {
+        if config.index_type == IndexType::Flat {
+            return match config.distance.as_str() {
+                "cosine" => VectorIndex::Flat(Arc::new(DistCosine)),
+                "dot" => VectorIndex::Flat(Arc::new(DotProduct)),
+                _ => VectorIndex::Flat(Arc::new(DistL2)),
+            };
+        }
+        fn graph<'a, D: Distance<f32> + Send + Sync>(
+            config: &CollectionConfig,
+            distance: D,
+        ) -> Arc<Hnsw<'a, f32, D>> {
+            let params = &config.hnsw;
+            let mut hnsw = Hnsw::new(
+                params.max_nb_connection,
+                params.max_elements,
+                params.max_layer,
+                params.ef_construction,
+                distance,
+            );
+            hnsw.set_extend_candidates(params.extend_candidates);
+            hnsw.set_keeping_pruned(params.keep_pruned);
+            Arc::new(hnsw)
+        }
+        match config.distance.as_str() {
+            "cosine" => VectorIndex::Cosine(graph(config, DistCosine)),
+            "dot" => VectorIndex::Dot(graph(config, DotProduct)),
+            _ => VectorIndex::L2(graph(config, DistL2)),
+        }
+    }
+
+    /// Whether there is no graph, so searches must scan the records instead.
+    pub fn is_flat(&self) -> bool {
+        matches!(self, VectorIndex::Flat(_))
+    }
+
+    /// Links `vector` into the graph; a no-op without one.
+    pub fn insert(&self, vector: &[f32], slot: usize) {
+        with_graph!(self, hnsw => hnsw.insert((vector, slot)))
+    }
+
+    /// Nearest slots to `query` among those `filter` accepts; none without a graph.
+    pub fn search(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        ef_search: usize,
+        filter: &dyn FilterT,
+    ) -> Vec<Neighbour> {
+        with_graph!(self, hnsw => hnsw.search_filter(query, top_k, ef_search, Some(filter)))
+    }
+
+    /// Distance between two vectors as the graph measures it.
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            VectorIndex::L2(_) => DistL2.eval(a, b),
+            VectorIndex::Cosine(_) => DistCosine.eval(a, b),
+            VectorIndex::Dot(_) => DotProduct.eval(a, b),
+            VectorIndex::Flat(distance) => distance.eval(a, b),
+        }
+    }
+
+    /// Whether `other` is a handle to this same graph.
+    pub fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (VectorIndex::L2(a), VectorIndex::L2(b)) => Arc::ptr_eq(a, b),
+            (VectorIndex::Cosine(a), VectorIndex::Cosine(b)) => Arc::ptr_eq(a, b),
+            (VectorIndex::Dot(a), VectorIndex::Dot(b)) => Arc::ptr_eq(a, b),
+            (VectorIndex::Flat(a), VectorIndex::Flat(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, time::Duration};
+
+/// Parameter sets compared by one what-if request at most.
+pub const MAX_PARAMETER_SETS: usize = 16;
+
+/// Search parameters to evaluate; anything left out keeps the collection's configured value.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParameterSet {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ef_search: Option<usize>,
+}
+
+impl ParameterSet {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ef_search == Some(0) {
+            return Err("ef_search must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// How one parameter set did over all replayed queries.
+#[derive(Serialize)]
+pub struct Outcome {
+    pub parameters: ParameterSet,
+    /// Mean share of the exact top-k that the search found.
+    pub recall: f64,
+    pub mean_ms: f64,
+    pub p95_ms: f64,
+}
+
+/// Per-query measurements for one parameter set.
+#[derive(Default)]
+pub struct Samples {
+    recall: Vec<f64>,
+    latency_ms: Vec<f64>,
+}
+
+impl Samples {
+    pub fn record(&mut self, found: &[(u64, f32)], exact: &[(u64, f32)], took: Duration) {
+        self.recall.push(recall(found, exact));
+        self.latency_ms.push(took.as_secs_f64() * 1000.0);
+    }
+
+    pub fn finish(mut self, parameters: ParameterSet) -> Outcome {
+        let n = self.recall.len().max(1) as f64;
+        self.latency_ms.sort_by(f64::total_cmp);
+        let p95 = match self.latency_ms.len() {
+            0 => 0.0,
+            len => self.latency_ms[(len * 95).div_ceil(100) - 1],
+        };
+        Outcome {
+            parameters,
+            recall: self.recall.iter().sum::<f64>() / n,
+            mean_ms: self.latency_ms.iter().sum::<f64>() / n,
+            p95_ms: p95,
+        }
+    }
+}
+
+/// Share of `exact` present in `found`, counting a query with no exact hits as fully recalled.
+fn recall(found: &[(u64, f32)], exact: &[(u64, f32)]) -> f64 {
+    if exact.is_empty() {
+        return 1.0;
+    }
+    let found: HashSet<u64> = found.iter().map(|(id, _)| *id).collect();
+    let hits = exact.iter().filter(|(id, _)| found.contains(id)).count();
+    hits as f64 / exact.len() as f64
+}